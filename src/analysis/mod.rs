@@ -1,2 +1,4 @@
+pub mod reachability;
 pub mod semantic;
+pub mod synthesis;
 pub mod typestate;
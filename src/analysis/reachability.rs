@@ -0,0 +1,184 @@
+use crate::frontend::ast::{self, TypeStateSet};
+use crate::analysis::typestate::{self, AliasMap, fmt_typestate_set, fmt_typestate_set_vec};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+
+#[derive(Debug)]
+pub enum ReachabilityWarning {
+    UnreachableState {
+        peripheral: String,
+        state: String,
+    },
+
+    DeadDriver {
+        peripheral: String,
+        driver: String,
+        input_states: Vec<TypeStateSet>,
+    },
+
+    NoExit {
+        peripheral: String,
+        state: TypeStateSet,
+    },
+}
+
+impl fmt::Display for ReachabilityWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReachabilityWarning::UnreachableState { peripheral, state } => {
+                write!(f, "State '{}' of '{}' is never reachable from its initial state", state, peripheral)
+            }
+
+            ReachabilityWarning::DeadDriver { peripheral, driver, input_states } => {
+                write!(
+                    f,
+                    "Driver '{}' can never be called: no reachable state of '{}' satisfies '{}'",
+                    driver,
+                    peripheral,
+                    fmt_typestate_set_vec(input_states),
+                )
+            }
+
+            ReachabilityWarning::NoExit { peripheral, state } => {
+                write!(
+                    f,
+                    "State '{}' of '{}' has no outgoing transitions but is not declared final",
+                    fmt_typestate_set(state),
+                    peripheral,
+                )
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Transition {
+    pub from: usize,
+    pub to: usize,
+    pub driver: String,
+}
+
+/* Reachable state space of one peripheral
+ *
+ * states[0] is the initial state, the rest in breadth-first discovery order.
+ * Declared states that never appear in a reachable state set are unreachable. */
+#[derive(Debug, Clone)]
+pub struct StateGraph {
+    pub peripheral: String,
+    pub states: Vec<TypeStateSet>,
+    pub transitions: Vec<Transition>,
+    pub unreachable: Vec<String>,
+}
+
+pub fn explore(program: &ast::Program) -> Vec<StateGraph> {
+    let alias_map = typestate::build_alias_map(program);
+    program.peripherals
+        .iter()
+        .map(|p| explore_peripheral(p, &program.functions, &alias_map))
+        .collect()
+}
+
+/* Breadth-first search from {initial}, applying every driver signature of the peripheral
+ * (including parametric ones) in declaration order
+ *
+ *   Σ(P) = S    sig(f) applies to S giving S'
+ *   ─────────────────────────────────────────
+ *            S ─f→ S'  is a transition
+ */
+fn explore_peripheral(
+    peripheral: &ast::Peripheral,
+    functions: &[ast::Function],
+    alias_map: &AliasMap,
+) -> StateGraph {
+    let drivers: Vec<(&str, &ast::TypeState)> = functions.iter()
+        .filter_map(|f| f.signature.as_ref().map(|sig| (f.name.as_str(), sig)))
+        .filter(|(_, sig)| sig.peripheral == peripheral.name)
+        .collect();
+
+    let initial: TypeStateSet = std::iter::once(peripheral.initial.clone()).collect();
+    let mut states = vec![initial.clone()];
+    let mut index: HashMap<TypeStateSet, usize> = HashMap::from([(initial, 0)]);
+    let mut transitions = Vec::new();
+    let mut queue = VecDeque::from([0]);
+
+    while let Some(from) = queue.pop_front() {
+        for (name, sig) in &drivers {
+            let Ok(next) = typestate::transition(&states[from], &sig.type_params, &sig.input_states, &sig.output_state, alias_map) else {
+                continue;
+            };
+            let to = *index.entry(next.clone()).or_insert_with(|| {
+                states.push(next);
+                queue.push_back(states.len() - 1);
+                states.len() - 1
+            });
+            transitions.push(Transition { from, to, driver: name.to_string() });
+        }
+    }
+
+    let unreachable = peripheral.states.iter()
+        .filter(|s| !states.iter().any(|set| set.contains(*s)))
+        .cloned()
+        .collect();
+
+    StateGraph {
+        peripheral: peripheral.name.clone(),
+        states,
+        transitions,
+        unreachable,
+    }
+}
+
+/* Warn about parts of each peripheral's protocol that the drivers can never exercise
+ *
+ *   - declared states that are not part of any reachable state set
+ *   - drivers whose input states are never reachable
+ *   - reachable state sets with no outgoing transition that are not declared final
+ */
+pub fn check(program: &ast::Program) -> Vec<ReachabilityWarning> {
+    let alias_map = typestate::build_alias_map(program);
+    let mut warnings = Vec::new();
+
+    for (peripheral, graph) in program.peripherals.iter().zip(explore(program)) {
+        for state in &graph.unreachable {
+            warnings.push(ReachabilityWarning::UnreachableState {
+                peripheral: graph.peripheral.clone(),
+                state: state.clone(),
+            });
+        }
+
+        let live: HashSet<&str> = graph.transitions.iter().map(|t| t.driver.as_str()).collect();
+        for func in &program.functions {
+            let Some(sig) = &func.signature else { continue };
+            if sig.peripheral == graph.peripheral && !live.contains(func.name.as_str()) {
+                warnings.push(ReachabilityWarning::DeadDriver {
+                    peripheral: graph.peripheral.clone(),
+                    driver: func.name.clone(),
+                    input_states: sig.input_states.clone(),
+                });
+            }
+        }
+
+        for (i, set) in graph.states.iter().enumerate() {
+            let has_exit = graph.transitions.iter().any(|t| t.from == i);
+            if !has_exit && !is_final(set, &peripheral.finals, &alias_map) {
+                warnings.push(ReachabilityWarning::NoExit {
+                    peripheral: graph.peripheral.clone(),
+                    state: set.clone(),
+                });
+            }
+        }
+    }
+
+    warnings
+}
+
+// A state set is final if it contains a final label, or exactly matches a final alias
+fn is_final(set: &TypeStateSet, finals: &[String], alias_map: &AliasMap) -> bool {
+    finals.iter().any(|label| {
+        if alias_map.contains_key(label) {
+            typestate::output_matches(set, &std::iter::once(label.clone()).collect(), alias_map)
+        } else {
+            set.contains(label)
+        }
+    })
+}
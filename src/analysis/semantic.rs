@@ -34,6 +34,39 @@ pub enum SemanticError {
         func_name: String,
         var_name: String,
     },
+
+    MisplacedDefault {
+        func_name: String,
+        arg_name: String,
+    },
+
+    ReadWriteOnly {
+        func_name: String,
+        peripheral: String,
+        register: String,
+    },
+
+    WriteReadOnly {
+        func_name: String,
+        peripheral: String,
+        register: String,
+    },
+
+    FieldOutOfRange {
+        peripheral: String,
+        register: String,
+        field: String,
+        msb: u32,
+        width: u32,
+    },
+
+    ReversedField {
+        peripheral: String,
+        register: String,
+        field: String,
+        msb: u32,
+        lsb: u32,
+    },
 }
 
 impl fmt::Display for SemanticError {
@@ -66,13 +99,34 @@ impl fmt::Display for SemanticError {
             SemanticError::AssignToConst { func_name, var_name } => {
                 write!(f, "Cannot assign to const '{}' in function '{}'", var_name, func_name)
             }
+
+            SemanticError::MisplacedDefault { func_name, arg_name } => {
+                write!(f, "Argument '{}' of function '{}' must have a default value, as it follows a defaulted argument", arg_name, func_name)
+            }
+
+            SemanticError::ReadWriteOnly { func_name, peripheral, register } => {
+                write!(f, "Cannot read write-only register '{}::{}' in function '{}'", peripheral, register, func_name)
+            }
+
+            SemanticError::WriteReadOnly { func_name, peripheral, register } => {
+                write!(f, "Cannot write read-only register '{}::{}' in function '{}'", peripheral, register, func_name)
+            }
+
+            SemanticError::FieldOutOfRange { peripheral, register, field, msb, width } => {
+                write!(f, "Field '{}' of register '{}::{}' uses bit {}, but the register is {} bits wide", field, peripheral, register, msb, width)
+            }
+
+            SemanticError::ReversedField { peripheral, register, field, msb, lsb } => {
+                write!(f, "Field '{}' of register '{}::{}' is [{}:{}], but its msb must not be below its lsb", field, peripheral, register, msb, lsb)
+            }
         }
     }
 }
 
 pub fn check(program: &ast::Program) -> Result<(), Vec<SemanticError>> {
     let mut errors = Vec::new();
-    let mut func_signatures: HashMap<String, usize> = HashMap::new();
+    // Function name -> (required arity, total arity)
+    let mut func_signatures: HashMap<String, (usize, usize)> = HashMap::new();
     let mut seen_functions: HashSet<String> = HashSet::new();
 
     // TODO: Check if seen_functions needed or func_signatures can be used
@@ -82,7 +136,8 @@ pub fn check(program: &ast::Program) -> Result<(), Vec<SemanticError>> {
                 func_name: func.name.clone(),
             });
         }
-        func_signatures.insert(func.name.clone(), func.args.len());
+        let required = func.args.iter().take_while(|a| a.default.is_none()).count();
+        func_signatures.insert(func.name.clone(), (required, func.args.len()));
     }
 
     let mut global_consts: HashSet<String> = HashSet::new();
@@ -90,13 +145,36 @@ pub fn check(program: &ast::Program) -> Result<(), Vec<SemanticError>> {
         global_consts.insert(name.clone());
     }
 
+    // Default values are evaluated at the call site, so may only refer to global constants
+    for func in &program.functions {
+        let mut seen_default = false;
+        for arg in &func.args {
+            match &arg.default {
+                Some(default) => {
+                    seen_default = true;
+                    check_expr(default, &func.name, &func_signatures, &global_consts, &mut errors);
+                }
+                None if seen_default => {
+                    errors.push(SemanticError::MisplacedDefault {
+                        func_name: func.name.clone(),
+                        arg_name: arg.name.clone(),
+                    });
+                }
+                None => {}
+            }
+        }
+    }
+
     for func in &program.functions {
         check_function(func, &func_signatures, &global_consts, &mut errors);
     }
 
+    check_register_access(program, &mut errors);
+    check_fields(program, &mut errors);
+
     if !func_signatures.contains_key("main") {
         errors.push(SemanticError::MissingMain);
-    } else if let Some(&arity) = func_signatures.get("main") {
+    } else if let Some(&(_, arity)) = func_signatures.get("main") {
         if arity != 0 {
             errors.push(SemanticError::InvalidMainArity { arity });
         }
@@ -111,14 +189,14 @@ pub fn check(program: &ast::Program) -> Result<(), Vec<SemanticError>> {
 
 fn check_function(
     func: &ast::Function,
-    func_signatures: &HashMap<String, usize>,
+    func_signatures: &HashMap<String, (usize, usize)>,
     global_consts: &HashSet<String>,
     errors: &mut Vec<SemanticError>,
 ) {
     let mut scope: HashSet<String> = global_consts.clone();
     let mut consts: HashSet<String> = global_consts.clone();
-    for (arg_name, _) in &func.args {
-        scope.insert(arg_name.clone());
+    for arg in &func.args {
+        scope.insert(arg.name.clone());
     }
 
     for stmt in &func.body {
@@ -129,7 +207,7 @@ fn check_function(
 fn check_statement(
     stmt: &ast::Statement,
     func_name: &str,
-    func_signatures: &HashMap<String, usize>,
+    func_signatures: &HashMap<String, (usize, usize)>,
     scope: &mut HashSet<String>,
     consts: &mut HashSet<String>,
     errors: &mut Vec<SemanticError>,
@@ -198,13 +276,15 @@ fn check_statement(
         ast::Statement::PeripheralWrite { value, .. } => {
             check_expr(value, func_name, func_signatures, scope, errors);
         }
+
+        ast::Statement::Reach { .. } => {}
     }
 }
 
 fn check_expr(
     expr: &ast::Expr,
     func_name: &str,
-    func_signatures: &HashMap<String, usize>,
+    func_signatures: &HashMap<String, (usize, usize)>,
     scope: &HashSet<String>,
     errors: &mut Vec<SemanticError>,
 ) {
@@ -237,11 +317,11 @@ fn check_expr(
                         called_from: func_name.to_string(),
                     });
                 }
-                Some(&expected_arity) => {
-                    if args.len() != expected_arity {
+                Some(&(required, total)) => {
+                    if args.len() < required || args.len() > total {
                         errors.push(SemanticError::ArityMismatch {
                             func_name: name.clone(),
-                            expected: expected_arity,
+                            expected: if args.len() < required { required } else { total },
                             actual: args.len(),
                             called_from: func_name.to_string(),
                         });
@@ -257,3 +337,124 @@ fn check_expr(
         ast::Expr::PeripheralRead { .. } => {}
     }
 }
+
+// Bitfields are [msb:lsb] within their register's width
+fn check_fields(program: &ast::Program, errors: &mut Vec<SemanticError>) {
+    for peripheral in &program.peripherals {
+        for block in &peripheral.register_blocks {
+            let width = match block.reg_type {
+                ast::RegisterType::U8 => 8,
+                ast::RegisterType::U16 => 16,
+                ast::RegisterType::U32 => 32,
+                ast::RegisterType::U64 => 64,
+            };
+            for register in &block.registers {
+                for field in &register.fields {
+                    if field.msb < field.lsb {
+                        errors.push(SemanticError::ReversedField {
+                            peripheral: peripheral.name.clone(),
+                            register: register.name.clone(),
+                            field: field.name.clone(),
+                            msb: field.msb,
+                            lsb: field.lsb,
+                        });
+                    } else if field.msb >= width {
+                        errors.push(SemanticError::FieldOutOfRange {
+                            peripheral: peripheral.name.clone(),
+                            register: register.name.clone(),
+                            field: field.name.clone(),
+                            msb: field.msb,
+                            width,
+                        });
+                    }
+                }
+            }
+        }
+    }
+}
+
+/* Registers declared `ro` may only be read and `wo` may only be written. Registers
+ * sharing an offset (e.g. 16550 RBR/THR) are told apart by name, so this is per name. */
+fn check_register_access(program: &ast::Program, errors: &mut Vec<SemanticError>) {
+    let mut access: HashMap<(&str, &str), ast::Access> = HashMap::new();
+    for peripheral in &program.peripherals {
+        for block in &peripheral.register_blocks {
+            for reg in &block.registers {
+                access.insert((&peripheral.name, &reg.name), reg.access);
+            }
+        }
+    }
+
+    fn visit_stmt(
+        stmt: &ast::Statement,
+        func_name: &str,
+        access: &HashMap<(&str, &str), ast::Access>,
+        errors: &mut Vec<SemanticError>,
+    ) {
+        match stmt {
+            ast::Statement::Let { value, .. }
+            | ast::Statement::Const { value, .. }
+            | ast::Statement::Assign { value, .. } => visit_expr(value, func_name, access, errors),
+            ast::Statement::Expr { expr } | ast::Statement::Return { expr } => visit_expr(expr, func_name, access, errors),
+            ast::Statement::If { cond, then_block, else_block } => {
+                visit_expr(cond, func_name, access, errors);
+                for s in then_block.iter().chain(else_block) {
+                    visit_stmt(s, func_name, access, errors);
+                }
+            }
+            ast::Statement::While { cond, body } => {
+                visit_expr(cond, func_name, access, errors);
+                for s in body {
+                    visit_stmt(s, func_name, access, errors);
+                }
+            }
+            ast::Statement::PeripheralWrite { peripheral, register, value } => {
+                if access.get(&(peripheral.as_str(), register.as_str())) == Some(&ast::Access::ReadOnly) {
+                    errors.push(SemanticError::WriteReadOnly {
+                        func_name: func_name.to_string(),
+                        peripheral: peripheral.clone(),
+                        register: register.clone(),
+                    });
+                }
+                visit_expr(value, func_name, access, errors);
+            }
+            ast::Statement::Reach { .. } => {}
+        }
+    }
+
+    fn visit_expr(
+        expr: &ast::Expr,
+        func_name: &str,
+        access: &HashMap<(&str, &str), ast::Access>,
+        errors: &mut Vec<SemanticError>,
+    ) {
+        match expr {
+            ast::Expr::Binary { left, right, .. } => {
+                visit_expr(left, func_name, access, errors);
+                visit_expr(right, func_name, access, errors);
+            }
+            ast::Expr::Unary { operand, .. } => visit_expr(operand, func_name, access, errors),
+            ast::Expr::FnCall { args, .. } => {
+                for arg in args {
+                    visit_expr(arg, func_name, access, errors);
+                }
+            }
+            ast::Expr::PeripheralRead { peripheral, register } => {
+                if access.get(&(peripheral.as_str(), register.as_str())) == Some(&ast::Access::WriteOnly) {
+                    errors.push(SemanticError::ReadWriteOnly {
+                        func_name: func_name.to_string(),
+                        peripheral: peripheral.clone(),
+                        register: register.clone(),
+                    });
+                }
+            }
+            ast::Expr::IntLit { .. } | ast::Expr::Variable { .. } => {}
+        }
+    }
+
+    for func in &program.functions {
+        for stmt in &func.body {
+            visit_stmt(stmt, &func.name, &access, errors);
+        }
+    }
+}
@@ -0,0 +1,466 @@
+use crate::frontend::ast::{self, TypeStateSet};
+use crate::analysis::typestate::{self, AliasMap, StateEnv, fmt_typestate_set};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+
+#[derive(Debug)]
+pub enum SynthesisError {
+    UnknownPeripheral {
+        func_name: String,
+        name: String,
+    },
+
+    Unreachable {
+        func_name: String,
+        peripheral: String,
+        from: TypeStateSet,
+        target: TypeStateSet,
+    },
+
+    MissingDefault {
+        func_name: String,
+        peripheral: String,
+        target: TypeStateSet,
+        driver: String,
+        arg_name: String,
+    },
+
+    AmbiguousExpansion {
+        func_name: String,
+        peripheral: String,
+        target: TypeStateSet,
+    },
+}
+
+impl fmt::Display for SynthesisError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SynthesisError::UnknownPeripheral { func_name, name } => {
+                write!(f, "Unknown peripheral '{}' in function '{}'", name, func_name)
+            }
+
+            SynthesisError::Unreachable { func_name, peripheral, from, target } => {
+                write!(
+                    f,
+                    "Cannot reach '{}<{}>' from '{}' in function '{}': no sequence of driver calls leads there",
+                    peripheral,
+                    fmt_typestate_set(target),
+                    fmt_typestate_set(from),
+                    func_name,
+                )
+            }
+
+            SynthesisError::MissingDefault { func_name, peripheral, target, driver, arg_name } => {
+                write!(
+                    f,
+                    "Reaching '{}<{}>' in function '{}' requires calling '{}', but its argument '{}' has no default value",
+                    peripheral,
+                    fmt_typestate_set(target),
+                    func_name,
+                    driver,
+                    arg_name,
+                )
+            }
+
+            SynthesisError::AmbiguousExpansion { func_name, peripheral, target } => {
+                write!(
+                    f,
+                    "'reach {}<{}>' in function '{}' expands differently for each input state of its signature",
+                    peripheral,
+                    fmt_typestate_set(target),
+                    func_name,
+                )
+            }
+        }
+    }
+}
+
+// A driver the search may call: a function with a typestate signature
+#[derive(Clone, Copy)]
+struct Driver<'a> {
+    name: &'a str,
+    sig: &'a ast::TypeState,
+    missing_default: Option<&'a str>,   /* First argument without a default value */
+}
+
+struct Context<'a> {
+    functions: &'a [ast::Function],
+    peripherals: &'a [ast::Peripheral],
+    alias_map: AliasMap,
+}
+
+/* Expand every `reach P<S>;` statement into a concrete sequence of driver calls
+ *
+ * The search is a breadth-first walk over P's reachable state sets, applying every
+ * driver signature (including parametric ones) in declaration order. The first path
+ * found is therefore the shortest, with ties broken by declaration order of the drivers.
+ *
+ * Drivers whose arguments lack default values are only considered when no path exists
+ * without them, in which case the missing default is reported.
+ */
+pub fn expand(program: &ast::Program) -> Result<ast::Program, Vec<SynthesisError>> {
+    let ctx = Context {
+        functions: &program.functions,
+        peripherals: &program.peripherals,
+        alias_map: typestate::build_alias_map(program),
+    };
+
+    let mut errors = Vec::new();
+    let mut expanded = program.clone();
+
+    for func in &mut expanded.functions {
+        if contains_reach(&func.body) {
+            func.body = expand_function(&ctx, func, &mut errors);
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(expanded)
+    } else {
+        Err(errors)
+    }
+}
+
+fn contains_reach(stmts: &[ast::Statement]) -> bool {
+    stmts.iter().any(|s| match s {
+        ast::Statement::Reach { .. } => true,
+        ast::Statement::If { then_block, else_block, .. } => contains_reach(then_block) || contains_reach(else_block),
+        ast::Statement::While { body, .. } => contains_reach(body),
+        _ => false,
+    })
+}
+
+fn init_state_env(peripherals: &[ast::Peripheral]) -> StateEnv {
+    peripherals
+        .iter()
+        .map(|p| (p.name.clone(), std::iter::once(p.initial.clone()).collect()))
+        .collect()
+}
+
+/* A function with a signature is expanded once per input alternative, and every
+ * alternative must agree on the synthesised calls, since only one body is emitted */
+fn expand_function(
+    ctx: &Context,
+    func: &ast::Function,
+    errors: &mut Vec<SynthesisError>,
+) -> Vec<ast::Statement> {
+    let mut envs = Vec::new();
+    match &func.signature {
+        Some(sig) => {
+            for input_set in &sig.input_states {
+                let mut env = init_state_env(ctx.peripherals);
+                env.insert(sig.peripheral.clone(), input_set.clone());
+                envs.push(env);
+            }
+        }
+        None => envs.push(init_state_env(ctx.peripherals)),
+    }
+
+    let mut expansions: Vec<Vec<ast::Statement>> = Vec::new();
+    let mut plans: Vec<Vec<Vec<String>>> = Vec::new();
+    for mut env in envs {
+        let mut plan = Vec::new();
+        let before = errors.len();
+        expansions.push(expand_block(ctx, &func.body, &mut env, &func.name, &mut plan, errors));
+        if errors.len() > before {
+            return func.body.clone();
+        }
+        plans.push(plan);
+    }
+
+    if let Some(mismatch) = plans.iter().position(|p| *p != plans[0]) {
+        if let Some((peripheral, target)) = nth_reach(&func.body, first_difference(&plans[0], &plans[mismatch])) {
+            errors.push(SynthesisError::AmbiguousExpansion {
+                func_name: func.name.clone(),
+                peripheral,
+                target,
+            });
+        }
+        return func.body.clone();
+    }
+
+    expansions.swap_remove(0)
+}
+
+fn first_difference(a: &[Vec<String>], b: &[Vec<String>]) -> usize {
+    a.iter().zip(b).position(|(x, y)| x != y).unwrap_or(0)
+}
+
+// The n-th reach statement of a body, in the same pre-order expand_block visits them
+fn nth_reach(stmts: &[ast::Statement], n: usize) -> Option<(String, TypeStateSet)> {
+    fn collect(stmts: &[ast::Statement], out: &mut Vec<(String, TypeStateSet)>) {
+        for s in stmts {
+            match s {
+                ast::Statement::Reach { peripheral, target } => out.push((peripheral.clone(), target.clone())),
+                ast::Statement::If { then_block, else_block, .. } => {
+                    collect(then_block, out);
+                    collect(else_block, out);
+                }
+                ast::Statement::While { body, .. } => collect(body, out),
+                _ => {}
+            }
+        }
+    }
+    let mut all = Vec::new();
+    collect(stmts, &mut all);
+    all.into_iter().nth(n)
+}
+
+/* Walk a block tracking Σ the same way analysis::typestate does over the CFG:
+ * driver calls anywhere in a statement update Σ, branches continue with the then-state,
+ * loops leave Σ unchanged. Calls that violate the typestate are left for
+ * analysis::typestate to report. */
+fn expand_block(
+    ctx: &Context,
+    stmts: &[ast::Statement],
+    env: &mut StateEnv,
+    func_name: &str,
+    plan: &mut Vec<Vec<String>>,
+    errors: &mut Vec<SynthesisError>,
+) -> Vec<ast::Statement> {
+    let mut out = Vec::new();
+
+    for stmt in stmts {
+        match stmt {
+            ast::Statement::Let { value: expr, .. }
+            | ast::Statement::Const { value: expr, .. }
+            | ast::Statement::Assign { value: expr, .. }
+            | ast::Statement::PeripheralWrite { value: expr, .. }
+            | ast::Statement::Return { expr }
+            | ast::Statement::Expr { expr } => {
+                apply_calls(ctx, env, expr);
+                out.push(stmt.clone());
+            }
+
+            ast::Statement::If { cond, then_block, else_block } => {
+                apply_calls(ctx, env, cond);
+                let mut then_env = env.clone();
+                let mut else_env = env.clone();
+                let then_block = expand_block(ctx, then_block, &mut then_env, func_name, plan, errors);
+                let else_block = expand_block(ctx, else_block, &mut else_env, func_name, plan, errors);
+                *env = then_env;
+                out.push(ast::Statement::If { cond: cond.clone(), then_block, else_block });
+            }
+
+            ast::Statement::While { cond, body } => {
+                let mut body_env = env.clone();
+                apply_calls(ctx, &mut body_env, cond);
+                let body = expand_block(ctx, body, &mut body_env, func_name, plan, errors);
+                out.push(ast::Statement::While { cond: cond.clone(), body });
+            }
+
+            ast::Statement::Reach { peripheral, target } => {
+                let Some(current) = env.get(peripheral).cloned() else {
+                    errors.push(SynthesisError::UnknownPeripheral {
+                        func_name: func_name.to_string(),
+                        name: peripheral.clone(),
+                    });
+                    continue;
+                };
+
+                match synthesise(ctx, func_name, peripheral, &current, target) {
+                    Ok(calls) => {
+                        for driver in &calls {
+                            apply(ctx, env, driver.sig);
+                            out.push(ast::Statement::Expr {
+                                expr: ast::Expr::FnCall { name: driver.name.to_string(), args: vec![] },
+                            });
+                        }
+                        plan.push(calls.iter().map(|d| d.name.to_string()).collect());
+                    }
+                    Err(err) => errors.push(err),
+                }
+            }
+        }
+    }
+
+    out
+}
+
+// Driver calls in an expression, in evaluation order: operands left to right, arguments before the call
+fn apply_calls(ctx: &Context, env: &mut StateEnv, expr: &ast::Expr) {
+    match expr {
+        ast::Expr::FnCall { name, args } => {
+            for arg in args {
+                apply_calls(ctx, env, arg);
+            }
+            if let Some(sig) = ctx.functions.iter().find(|f| f.name == *name).and_then(|f| f.signature.as_ref()) {
+                apply(ctx, env, sig);
+            }
+        }
+        ast::Expr::Binary { left, right, .. } => {
+            apply_calls(ctx, env, left);
+            apply_calls(ctx, env, right);
+        }
+        ast::Expr::Unary { operand, .. } => apply_calls(ctx, env, operand),
+        ast::Expr::IntLit { .. } | ast::Expr::Variable { .. } | ast::Expr::PeripheralRead { .. } => {}
+    }
+}
+
+fn apply(ctx: &Context, env: &mut StateEnv, sig: &ast::TypeState) {
+    if let Some(current) = env.get(&sig.peripheral) {
+        if let Ok(next) = typestate::transition(current, &sig.type_params, &sig.input_states, &sig.output_state, &ctx.alias_map) {
+            env.insert(sig.peripheral.clone(), next);
+        }
+    }
+}
+
+fn synthesise<'c>(
+    ctx: &Context<'c>,
+    func_name: &str,
+    peripheral: &str,
+    current: &TypeStateSet,
+    target: &TypeStateSet,
+) -> Result<Vec<Driver<'c>>, SynthesisError> {
+    // The enclosing function is excluded so a driver cannot be synthesised from itself
+    let drivers: Vec<Driver> = ctx.functions.iter()
+        .filter(|f| f.name != func_name)
+        .filter_map(|f| {
+            let sig = f.signature.as_ref()?;
+            (sig.peripheral == peripheral).then(|| Driver {
+                name: &f.name,
+                sig,
+                missing_default: f.args.iter().find(|a| a.default.is_none()).map(|a| a.name.as_str()),
+            })
+        })
+        .collect();
+
+    let callable: Vec<&Driver> = drivers.iter().filter(|d| d.missing_default.is_none()).collect();
+    if let Some(path) = search(ctx, current, target, &callable) {
+        return Ok(path.into_iter().copied().collect());
+    }
+
+    let all: Vec<&Driver> = drivers.iter().collect();
+    match search(ctx, current, target, &all) {
+        Some(path) => {
+            let blocked = path.iter().find(|d| d.missing_default.is_some()).unwrap();
+            Err(SynthesisError::MissingDefault {
+                func_name: func_name.to_string(),
+                peripheral: peripheral.to_string(),
+                target: target.clone(),
+                driver: blocked.name.to_string(),
+                arg_name: blocked.missing_default.unwrap().to_string(),
+            })
+        }
+        None => Err(SynthesisError::Unreachable {
+            func_name: func_name.to_string(),
+            peripheral: peripheral.to_string(),
+            from: current.clone(),
+            target: target.clone(),
+        }),
+    }
+}
+
+/* Breadth-first search over state sets
+ *
+ *   Σ(P) ─f₁→ S₁ ─f₂→ S₂ ... ─fₙ→ Sₙ    where Sₙ matches the target
+ *
+ * Successors are expanded in driver order and each state is visited once, so the
+ * returned path is the shortest, lexicographically least by driver order. */
+fn search<'d, 'c>(
+    ctx: &Context,
+    start: &TypeStateSet,
+    target: &TypeStateSet,
+    drivers: &[&'d Driver<'c>],
+) -> Option<Vec<&'d Driver<'c>>> {
+    let mut visited: HashSet<TypeStateSet> = HashSet::new();
+    let mut parent: HashMap<TypeStateSet, (TypeStateSet, usize)> = HashMap::new();
+    let mut queue = VecDeque::new();
+
+    visited.insert(start.clone());
+    queue.push_back(start.clone());
+
+    while let Some(state) = queue.pop_front() {
+        if typestate::output_matches(&state, target, &ctx.alias_map) {
+            let mut path = Vec::new();
+            let mut node = state;
+            while let Some((prev, driver)) = parent.get(&node) {
+                path.push(drivers[*driver]);
+                node = prev.clone();
+            }
+            path.reverse();
+            return Some(path);
+        }
+
+        for (i, driver) in drivers.iter().enumerate() {
+            let sig = driver.sig;
+            let Ok(next) = typestate::transition(&state, &sig.type_params, &sig.input_states, &sig.output_state, &ctx.alias_map) else {
+                continue;
+            };
+            if visited.insert(next.clone()) {
+                parent.insert(next.clone(), (state.clone(), i));
+                queue.push_back(next);
+            }
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frontend::parser;
+
+    const UART: &str = "
+        peripheral UART at 0x1000_0000 {
+            states: Unconfigured, LineConfigured, Ready;
+            initial: Unconfigured;
+            registers u8 {
+                LCR at 0x03;
+                IER at 0x01;
+            }
+        }
+
+        fn uart_set_lcr() -> u32 :: UART<Unconfigured> -> UART<LineConfigured> {
+            UART::LCR = 3;
+            return 0;
+        }
+
+        fn uart_set_ier(interrupts: u8 = 1) :: UART<LineConfigured> -> UART<Ready> {
+            UART::IER = interrupts;
+        }
+    ";
+
+    // Names of the functions main calls, in order, after expansion
+    fn expanded_calls(main: &str) -> Vec<String> {
+        let source = format!("{}\nfn main() {{ {} }}", UART, main);
+        let program = expand(&parser::parse(&source).unwrap()).unwrap();
+        let main = program.functions.iter().find(|f| f.name == "main").unwrap();
+
+        fn calls(expr: &ast::Expr, out: &mut Vec<String>) {
+            match expr {
+                ast::Expr::FnCall { name, args } => {
+                    args.iter().for_each(|a| calls(a, out));
+                    out.push(name.clone());
+                }
+                ast::Expr::Binary { left, right, .. } => {
+                    calls(left, out);
+                    calls(right, out);
+                }
+                ast::Expr::Unary { operand, .. } => calls(operand, out),
+                _ => {}
+            }
+        }
+        let mut out = Vec::new();
+        for stmt in &main.body {
+            match stmt {
+                ast::Statement::Let { value: expr, .. } | ast::Statement::Expr { expr } => calls(expr, &mut out),
+                _ => {}
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn reach_from_initial_state() {
+        assert_eq!(expanded_calls("reach UART<Ready>;"), ["uart_set_lcr", "uart_set_ier"]);
+    }
+
+    // A driver called inside an expression moves Σ just like a call statement
+    #[test]
+    fn driver_calls_in_expressions_update_state() {
+        assert_eq!(expanded_calls("let s = uart_set_lcr(); reach UART<Ready>;"), ["uart_set_lcr", "uart_set_ier"]);
+        assert_eq!(expanded_calls("let s = 1 + uart_set_lcr(); reach UART<Ready>;"), ["uart_set_lcr", "uart_set_ier"]);
+    }
+}
@@ -52,11 +52,11 @@ pub enum TypestateError {
     },
 }
 
-fn fmt_typestate_set(s: &TypeStateSet) -> String {
+pub fn fmt_typestate_set(s: &TypeStateSet) -> String {
     s.iter().cloned().collect::<Vec<_>>().join(" & ")
 }
 
-fn fmt_typestate_set_vec(e: &[TypeStateSet]) -> String {
+pub fn fmt_typestate_set_vec(e: &[TypeStateSet]) -> String {
     e.iter()
         .map(|s| if s.len() > 1 { format!("({})", fmt_typestate_set(s)) } else { fmt_typestate_set(s) })
         .collect::<Vec<_>>()
@@ -143,7 +143,7 @@ enum FunctionType {
     Orchestration,
 }
 
-fn build_alias_map(program: &ast::Program) -> AliasMap {
+pub fn build_alias_map(program: &ast::Program) -> AliasMap {
     let mut map = AliasMap::new();
     for p in &program.peripherals {
         for alias in &p.aliases {
@@ -310,7 +310,7 @@ pub fn check(program: &ast::Program, ir: &[(String, CFG)]) -> Result<(), String>
     Ok(())
 }
 
-fn build_signature_map(program: &ast::Program) -> HashMap<String, ast::TypeState> {
+pub fn build_signature_map(program: &ast::Program) -> HashMap<String, ast::TypeState> {
     program.functions.iter()
         .filter_map(|f| f.signature.as_ref().map(|sig| (f.name.clone(), sig.clone())))
         .collect()
@@ -387,18 +387,7 @@ fn verify_function(
                     })?;
 
                 let expected = &sig.output_state;
-                let output_ok = if expected.len() == 1 {
-                    let label = expected.iter().next().unwrap();
-                    if let Some(alias_def) = alias_map.get(label) {
-                        check_as_bound(actual, alias_def)
-                    } else {
-                        actual == expected
-                    }
-                } else {
-                    actual == expected
-                };
-
-                if !output_ok {
+                if !output_matches(actual, expected, alias_map) {
                     return Err(TypestateError::WrongExitState {
                         func_name: fn_name.clone(),
                         peripheral: sig.peripheral.clone(),
@@ -531,6 +520,54 @@ fn expand_output(output: &TypeStateSet, alias_map: &AliasMap) -> TypeStateSet {
     result
 }
 
+// Why a driver signature cannot be applied to the current state
+#[derive(Debug)]
+pub enum Rejection {
+    Input,
+    Bound { param_name: String, bound_name: String },
+}
+
+/* Apply a driver signature to the current state of its peripheral
+ *
+ *   Plain:       Σ(P) must satisfy some input alternative, result is the (alias-expanded) output
+ *   Parametric:  Σ(P) must satisfy every input template and all bounds, result substitutes Σ(P)
+ */
+pub fn transition(
+    current: &TypeStateSet,
+    type_params: &[TypeParam],
+    from_states: &[TypeStateSet],
+    to_state: &TypeStateSet,
+    alias_map: &AliasMap,
+) -> Result<TypeStateSet, Rejection> {
+    if type_params.is_empty() {
+        if !state_satisfies(current, from_states, alias_map) {
+            return Err(Rejection::Input);
+        }
+        return Ok(expand_output(to_state, alias_map));
+    }
+
+    for alt in from_states {
+        if !check_parametric_input(current, alt, type_params, alias_map) {
+            return Err(Rejection::Input);
+        }
+    }
+    if let Some((param_name, bound_name)) = check_bounds(current, type_params, alias_map) {
+        return Err(Rejection::Bound { param_name, bound_name });
+    }
+    Ok(compute_parametric_output(current, to_state, type_params))
+}
+
+// Does a state produced by a body match a declared (possibly aliased) output state?
+pub fn output_matches(actual: &TypeStateSet, expected: &TypeStateSet, alias_map: &AliasMap) -> bool {
+    if expected.len() == 1 {
+        let label = expected.iter().next().unwrap();
+        if let Some(alias_def) = alias_map.get(label) {
+            return check_as_bound(actual, alias_def);
+        }
+    }
+    actual == expected
+}
+
 /* Verify a single statement's effect on the state environment
  *
  * Typing rule for driver calls:
@@ -554,43 +591,24 @@ fn verify_stmt(
                     name: peripheral.clone(),
                 })?;
 
-            let is_parametric = !type_params.is_empty();
-
-            if is_parametric {
-                for alt in from_states {
-                    if !check_parametric_input(&current, alt, type_params, alias_map) {
-                        return Err(TypestateError::InvalidTransition {
-                            func_name: function.clone(),
-                            called_from: func_name.to_string(),
-                            peripheral: peripheral.clone(),
-                            candidate_states: from_states.clone(),
-                            actual_state: current.clone(),
-                        });
-                    }
-                }
-                if let Some((param_name, bound_name)) = check_bounds(&current, type_params, alias_map) {
-                    return Err(TypestateError::BoundViolation {
+            let new_state = transition(current, type_params, from_states, to_state, alias_map)
+                .map_err(|rejection| match rejection {
+                    Rejection::Input => TypestateError::InvalidTransition {
                         func_name: function.clone(),
                         called_from: func_name.to_string(),
-                        param_name,
-                        bound_name,
+                        peripheral: peripheral.clone(),
+                        candidate_states: from_states.clone(),
                         actual_state: current.clone(),
-                    });
-                }
-                let new_state = compute_parametric_output(&current, to_state, type_params);
-                state_env.insert(peripheral.clone(), new_state);
-            } else {
-                if !state_satisfies(&current, from_states, alias_map) {
-                    return Err(TypestateError::InvalidTransition {
+                    },
+                    Rejection::Bound { param_name, bound_name } => TypestateError::BoundViolation {
                         func_name: function.clone(),
                         called_from: func_name.to_string(),
-                        peripheral: peripheral.clone(),
-                        candidate_states: from_states.clone(),
+                        param_name,
+                        bound_name,
                         actual_state: current.clone(),
-                    });
-                }
-                state_env.insert(peripheral.clone(), expand_output(to_state, alias_map));
-            }
+                    },
+                })?;
+            state_env.insert(peripheral.clone(), new_state);
         }
 
         Statement::Expr { expr } => {
@@ -0,0 +1,966 @@
+use std::collections::{HashMap, HashSet};
+
+/* Assembler for the RV32IMC subset generator.rs emits and the runtime startup files use
+
+      .section .text                  .text
+      .global main                    00000000  addi sp, sp, -16
+  main:                       ->      ...
+      addi sp, sp, -16                00000008  auipc ra, 0         R_RISCV_CALL_PLT uart_init
+      call uart_init                  0000000c  jalr ra, 0(ra)
+
+Two passes over the source: the first expands every statement to machine instructions
+to place the labels, the second encodes them. Branches and jumps to a label in the same
+section are resolved here. Branches to other sections or undefined symbols, calls, `la`
+and `.word` with a symbol are left to the linker as relocations.
+
+Pseudo instructions expand as in the GNU assembler without relaxation: `li` becomes
+addi, lui or lui + addi, `call`/`tail` an auipc + jalr pair and `la` an auipc + addi pair.
+Instructions are only compressed when written as `c.` forms, as compress.rs writes them.
+Comments are `#`, `//` and C-style, as in .S files. */
+
+#[derive(Debug, Clone)]
+pub struct Object {
+    pub sections: Vec<Section>,
+    pub symbols: Vec<Symbol>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Section {
+    pub name: String,
+    pub data: Vec<u8>,
+    pub relocations: Vec<Relocation>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub section: Option<usize>,     // None for symbols defined elsewhere
+    pub value: u32,                 // Offset into the section
+    pub global: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Relocation {
+    pub offset: u32,
+    pub kind: RelocationKind,
+    pub symbol: usize,              // Index into Object::symbols
+    pub addend: i32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RelocationKind {
+    Abs32,          // .word symbol
+    Branch,         // beq/bne/blt/bge/bltu/bgeu
+    Jal,            // jal, j
+    CallPlt,        // auipc + jalr of call/tail
+    PcrelHi20,      // auipc of la
+    PcrelLo12I,     // addi of la, against the label of its auipc
+    RvcBranch,      // c.beqz/c.bnez
+    RvcJump,        // c.j
+}
+
+impl RelocationKind {
+    pub fn elf_type(self) -> u32 {
+        match self {
+            RelocationKind::Abs32 => 1,
+            RelocationKind::Branch => 16,
+            RelocationKind::Jal => 17,
+            RelocationKind::CallPlt => 19,
+            RelocationKind::PcrelHi20 => 23,
+            RelocationKind::PcrelLo12I => 24,
+            RelocationKind::RvcBranch => 44,
+            RelocationKind::RvcJump => 45,
+        }
+    }
+}
+
+const ZERO: u32 = 0;
+const RA: u32 = 1;
+const T1: u32 = 6;
+
+const OP_LUI: u32 = 0x37;
+const OP_AUIPC: u32 = 0x17;
+const OP_JALR: u32 = 0x67;
+const OP_LOAD: u32 = 0x03;
+const OP_IMM: u32 = 0x13;
+const OP_SYSTEM: u32 = 0x73;
+
+// One machine instruction or data word, possibly still naming a symbol
+#[derive(Debug, Clone)]
+enum Item {
+    R { funct7: u32, funct3: u32, rd: u32, rs1: u32, rs2: u32 },
+    I { opcode: u32, funct3: u32, rd: u32, rs1: u32, imm: i32 },
+    S { funct3: u32, rs1: u32, rs2: u32, imm: i32 },
+    B { funct3: u32, rs1: u32, rs2: u32, target: String },
+    U { opcode: u32, rd: u32, imm: u32 },
+    J { rd: u32, target: String },
+    Call { link: u32, scratch: u32, target: String },  // auipc scratch; jalr link, 0(scratch)
+    La { rd: u32, target: String },                     // auipc rd; addi rd, rd
+    C(u16),                                             // Compressed, fully encoded
+    CBranch { funct3: u32, rs1: u32, target: String },  // c.beqz/c.bnez, rs1 in x8-x15
+    CJump { target: String },
+    Word(u32),
+    WordSymbol(String),
+    Zero(u32),
+}
+
+impl Item {
+    fn size(&self) -> u32 {
+        match self {
+            Item::Call { .. } | Item::La { .. } => 8,
+            Item::C(_) | Item::CBranch { .. } | Item::CJump { .. } => 2,
+            Item::Zero(bytes) => *bytes,
+            _ => 4,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Statement {
+    Label(String),
+    Directive(String, Vec<String>),
+    Instruction(String, Vec<String>),
+}
+
+pub fn assemble(source: &str) -> Result<Object, String> {
+    let statements = parse(source)?;
+    let mut assembler = Assembler::default();
+
+    // First pass: place labels
+    for (line, statement) in &statements {
+        assembler.place(statement).map_err(|e| format!("line {}: {}", line, e))?;
+    }
+
+    // Second pass: encode
+    assembler.start_encoding();
+    for (line, statement) in &statements {
+        assembler.encode(statement).map_err(|e| format!("line {}: {}", line, e))?;
+    }
+
+    Ok(assembler.finish())
+}
+
+#[derive(Default)]
+struct Assembler {
+    sections: Vec<Section>,
+    section_index: HashMap<String, usize>,
+    current: usize,
+    offsets: Vec<u32>,                          // Size of each section so far
+    labels: HashMap<String, (usize, u32)>,      // Label -> (section, offset)
+    label_order: Vec<String>,
+    globals: HashSet<String>,
+    symbols: Vec<Symbol>,
+    symbol_index: HashMap<String, usize>,
+    pcrel_labels: usize,
+}
+
+impl Assembler {
+    fn switch_section(&mut self, name: &str) {
+        self.current = match self.section_index.get(name) {
+            Some(&index) => index,
+            None => {
+                self.sections.push(Section { name: name.to_string(), data: Vec::new(), relocations: Vec::new() });
+                self.offsets.push(0);
+                self.section_index.insert(name.to_string(), self.sections.len() - 1);
+                self.sections.len() - 1
+            }
+        };
+    }
+
+    fn ensure_section(&mut self) {
+        if self.sections.is_empty() {
+            self.switch_section(".text");
+        }
+    }
+
+    fn place(&mut self, statement: &Statement) -> Result<(), String> {
+        match statement {
+            Statement::Label(name) => {
+                self.ensure_section();
+                if self.labels.contains_key(name) {
+                    return Err(format!("label '{}' is defined more than once", name));
+                }
+                self.labels.insert(name.clone(), (self.current, self.offsets[self.current]));
+                self.label_order.push(name.clone());
+            }
+            Statement::Directive(name, args) => {
+                for item in self.directive(name, args)? {
+                    self.offsets[self.current] += item.size();
+                }
+            }
+            Statement::Instruction(mnemonic, operands) => {
+                self.ensure_section();
+                for item in expand(mnemonic, operands)? {
+                    self.offsets[self.current] += item.size();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Label symbols first, in order of definition, then declared but undefined globals
+    fn start_encoding(&mut self) {
+        self.current = 0;
+        for name in self.label_order.clone() {
+            if !name.starts_with(".L") {
+                self.symbol(&name);
+            }
+        }
+        let mut undefined: Vec<String> = self.globals.iter()
+            .filter(|g| !self.labels.contains_key(*g))
+            .cloned()
+            .collect();
+        undefined.sort();
+        for name in undefined {
+            self.symbol(&name);
+        }
+    }
+
+    fn symbol(&mut self, name: &str) -> usize {
+        if let Some(&index) = self.symbol_index.get(name) {
+            return index;
+        }
+        let defined = self.labels.get(name).copied();
+        self.symbols.push(Symbol {
+            name: name.to_string(),
+            section: defined.map(|(section, _)| section),
+            value: defined.map_or(0, |(_, offset)| offset),
+            global: self.globals.contains(name) || defined.is_none(),
+        });
+        self.symbol_index.insert(name.to_string(), self.symbols.len() - 1);
+        self.symbols.len() - 1
+    }
+
+    fn directive(&mut self, name: &str, args: &[String]) -> Result<Vec<Item>, String> {
+        match name {
+            ".section" => {
+                let section = args.first().ok_or("expected a section name after '.section'")?;
+                self.switch_section(section);
+            }
+            ".text" | ".data" | ".bss" | ".rodata" => self.switch_section(name),
+            ".global" | ".globl" => {
+                for arg in args {
+                    self.globals.insert(arg.clone());
+                }
+            }
+            ".word" => {
+                self.ensure_section();
+                return args.iter()
+                    .map(|arg| match immediate(arg) {
+                        Ok(value) => Ok(Item::Word(value as u32)),
+                        Err(_) if is_symbol(arg) => Ok(Item::WordSymbol(arg.clone())),
+                        Err(e) => Err(e),
+                    })
+                    .collect();
+            }
+            ".zero" | ".space" => {
+                self.ensure_section();
+                let bytes = immediate(args.first().ok_or("expected a size")?)?;
+                if bytes < 0 {
+                    return Err(format!("negative size {}", bytes));
+                }
+                return Ok(vec![Item::Zero(bytes as u32)]);
+            }
+            ".align" | ".p2align" => {
+                self.ensure_section();
+                let power = immediate(args.first().ok_or("expected an alignment")?)?;
+                if !(0..=12).contains(&power) {
+                    return Err(format!("alignment 2^{} is out of range", power));
+                }
+                let alignment = 1u32 << power;
+                let offset = self.offsets[self.current];
+                let padding = (alignment - offset % alignment) % alignment;
+                return Ok(if padding > 0 { vec![Item::Zero(padding)] } else { vec![] });
+            }
+            _ => return Err(format!("unsupported directive '{}'", name)),
+        }
+        Ok(vec![])
+    }
+
+    fn encode(&mut self, statement: &Statement) -> Result<(), String> {
+        let items = match statement {
+            Statement::Label(_) => return Ok(()),
+            Statement::Directive(name, args) => {
+                // Alignment is worked out from what has been encoded so far
+                if let Some(section) = self.sections.get(self.current) {
+                    self.offsets[self.current] = section.data.len() as u32;
+                }
+                self.directive(name, args)?
+            }
+            Statement::Instruction(mnemonic, operands) => expand(mnemonic, operands)?,
+        };
+
+        for item in items {
+            self.emit(item)?;
+        }
+        Ok(())
+    }
+
+    fn emit(&mut self, item: Item) -> Result<(), String> {
+        let section = self.current;
+        let here = self.sections[section].data.len() as u32;
+
+        let words: Vec<u32> = match item {
+            Item::R { funct7, funct3, rd, rs1, rs2 } => {
+                vec![funct7 << 25 | rs2 << 20 | rs1 << 15 | funct3 << 12 | rd << 7 | 0x33]
+            }
+            Item::I { opcode, funct3, rd, rs1, imm } => vec![encode_i(opcode, funct3, rd, rs1, imm)],
+            Item::S { funct3, rs1, rs2, imm } => {
+                let imm = imm as u32;
+                vec![(imm >> 5 & 0x7f) << 25 | rs2 << 20 | rs1 << 15 | funct3 << 12 | (imm & 0x1f) << 7 | 0x23]
+            }
+            Item::B { funct3, rs1, rs2, target } => {
+                let offset = self.local_offset(&target, here, RelocationKind::Branch, 1 << 12)?;
+                vec![branch_offset(offset) | rs2 << 20 | rs1 << 15 | funct3 << 12 | 0x63]
+            }
+            Item::U { opcode, rd, imm } => vec![imm << 12 | rd << 7 | opcode],
+            Item::J { rd, target } => {
+                let offset = self.local_offset(&target, here, RelocationKind::Jal, 1 << 20)?;
+                vec![jump_offset(offset) | rd << 7 | 0x6f]
+            }
+            Item::Call { link, scratch, target } => {
+                self.relocate(here, RelocationKind::CallPlt, &target);
+                vec![scratch << 7 | OP_AUIPC, encode_i(OP_JALR, 0, link, scratch, 0)]
+            }
+            Item::La { rd, target } => {
+                let anchor = format!(".Lpcrel_hi{}", self.pcrel_labels);
+                self.pcrel_labels += 1;
+                self.labels.insert(anchor.clone(), (section, here));
+                self.relocate(here, RelocationKind::PcrelHi20, &target);
+                self.relocate(here + 4, RelocationKind::PcrelLo12I, &anchor);
+                vec![rd << 7 | OP_AUIPC, encode_i(OP_IMM, 0, rd, rd, 0)]
+            }
+            Item::C(half) => {
+                self.sections[section].data.extend_from_slice(&half.to_le_bytes());
+                vec![]
+            }
+            Item::CBranch { funct3, rs1, target } => {
+                let offset = self.local_offset(&target, here, RelocationKind::RvcBranch, 1 << 8)?;
+                let half = funct3 << 13 | c_branch_offset(offset) | (rs1 - 8) << 7 | 0x1;
+                self.sections[section].data.extend_from_slice(&(half as u16).to_le_bytes());
+                vec![]
+            }
+            Item::CJump { target } => {
+                let offset = self.local_offset(&target, here, RelocationKind::RvcJump, 1 << 11)?;
+                let half = 0x5 << 13 | c_jump_offset(offset) | 0x1;
+                self.sections[section].data.extend_from_slice(&(half as u16).to_le_bytes());
+                vec![]
+            }
+            Item::Word(value) => vec![value],
+            Item::WordSymbol(target) => {
+                self.relocate(here, RelocationKind::Abs32, &target);
+                vec![0]
+            }
+            Item::Zero(bytes) => {
+                let data = &mut self.sections[section].data;
+                data.resize(data.len() + bytes as usize, 0);
+                vec![]
+            }
+        };
+
+        for word in words {
+            self.sections[section].data.extend_from_slice(&word.to_le_bytes());
+        }
+        Ok(())
+    }
+
+    // PC-relative offset to a label in this section, or a relocation and 0 otherwise
+    fn local_offset(&mut self, target: &str, here: u32, kind: RelocationKind, range: i64) -> Result<i32, String> {
+        match self.labels.get(target) {
+            Some(&(section, offset)) if section == self.current => {
+                let distance = offset as i64 - here as i64;
+                if distance < -range || distance >= range {
+                    return Err(format!("'{}' is out of range ({} bytes away)", target, distance));
+                }
+                Ok(distance as i32)
+            }
+            _ => {
+                self.relocate(here, kind, target);
+                Ok(0)
+            }
+        }
+    }
+
+    fn relocate(&mut self, offset: u32, kind: RelocationKind, target: &str) {
+        let symbol = self.symbol(target);
+        self.sections[self.current].relocations.push(Relocation { offset, kind, symbol, addend: 0 });
+    }
+
+    fn finish(self) -> Object {
+        Object { sections: self.sections, symbols: self.symbols }
+    }
+}
+
+// Offset bits of a B-type instruction, also used by the linker to patch relocations
+pub fn branch_offset(offset: i32) -> u32 {
+    let imm = offset as u32;
+    (imm >> 12 & 1) << 31 | (imm >> 5 & 0x3f) << 25 | (imm >> 1 & 0xf) << 8 | (imm >> 11 & 1) << 7
+}
+
+// Offset bits of a J-type instruction
+pub fn jump_offset(offset: i32) -> u32 {
+    let imm = offset as u32;
+    (imm >> 20 & 1) << 31 | (imm >> 1 & 0x3ff) << 21 | (imm >> 11 & 1) << 20 | (imm >> 12 & 0xff) << 12
+}
+
+// Offset bits of c.beqz and c.bnez
+pub fn c_branch_offset(offset: i32) -> u32 {
+    let imm = offset as u32;
+    (imm >> 8 & 1) << 12 | (imm >> 3 & 3) << 10 | (imm >> 6 & 3) << 5 | (imm >> 1 & 3) << 3 | (imm >> 5 & 1) << 2
+}
+
+// Offset bits of c.j
+pub fn c_jump_offset(offset: i32) -> u32 {
+    let imm = offset as u32;
+    (imm >> 11 & 1) << 12 | (imm >> 4 & 1) << 11 | (imm >> 8 & 3) << 9 | (imm >> 10 & 1) << 8
+        | (imm >> 6 & 1) << 7 | (imm >> 7 & 1) << 6 | (imm >> 1 & 7) << 3 | (imm >> 5 & 1) << 2
+}
+
+fn encode_i(opcode: u32, funct3: u32, rd: u32, rs1: u32, imm: i32) -> u32 {
+    (imm as u32 & 0xfff) << 20 | rs1 << 15 | funct3 << 12 | rd << 7 | opcode
+}
+
+// Machine instructions for one source instruction, pseudo instructions expanded
+fn expand(mnemonic: &str, operands: &[String]) -> Result<Vec<Item>, String> {
+    let count = |n: usize| -> Result<(), String> {
+        if operands.len() == n {
+            Ok(())
+        } else {
+            Err(format!("'{}' expects {} operand(s), found {}", mnemonic, n, operands.len()))
+        }
+    };
+    let reg = |i: usize| register(&operands[i]);
+    let target = |i: usize| -> Result<String, String> {
+        let name = &operands[i];
+        if is_symbol(name) { Ok(name.clone()) } else { Err(format!("expected a label, found '{}'", name)) }
+    };
+    let imm12 = |i: usize| -> Result<i32, String> {
+        let value = immediate(&operands[i])?;
+        if (-2048..2048).contains(&value) { Ok(value) } else { Err(format!("immediate {} does not fit in 12 bits", value)) }
+    };
+    let shamt = |i: usize| -> Result<i32, String> {
+        let value = immediate(&operands[i])?;
+        if (0..32).contains(&value) { Ok(value) } else { Err(format!("shift amount {} is out of range", value)) }
+    };
+
+    let r = |funct7: u32, funct3: u32| -> Result<Vec<Item>, String> {
+        count(3)?;
+        Ok(vec![Item::R { funct7, funct3, rd: reg(0)?, rs1: reg(1)?, rs2: reg(2)? }])
+    };
+    let i = |funct3: u32| -> Result<Vec<Item>, String> {
+        count(3)?;
+        Ok(vec![Item::I { opcode: OP_IMM, funct3, rd: reg(0)?, rs1: reg(1)?, imm: imm12(2)? }])
+    };
+    let shift = |funct3: u32, high: i32| -> Result<Vec<Item>, String> {
+        count(3)?;
+        Ok(vec![Item::I { opcode: OP_IMM, funct3, rd: reg(0)?, rs1: reg(1)?, imm: high | shamt(2)? }])
+    };
+    let load = |funct3: u32| -> Result<Vec<Item>, String> {
+        count(2)?;
+        let (imm, rs1) = memory(&operands[1])?;
+        Ok(vec![Item::I { opcode: OP_LOAD, funct3, rd: reg(0)?, rs1, imm }])
+    };
+    let store = |funct3: u32| -> Result<Vec<Item>, String> {
+        count(2)?;
+        let (imm, rs1) = memory(&operands[1])?;
+        Ok(vec![Item::S { funct3, rs1, rs2: reg(0)?, imm }])
+    };
+    let branch = |funct3: u32, swap: bool| -> Result<Vec<Item>, String> {
+        count(3)?;
+        let (rs1, rs2) = if swap { (reg(1)?, reg(0)?) } else { (reg(0)?, reg(1)?) };
+        Ok(vec![Item::B { funct3, rs1, rs2, target: target(2)? }])
+    };
+    // Comparisons against zero, with zero on the given side
+    let branch_zero = |funct3: u32, zero_first: bool| -> Result<Vec<Item>, String> {
+        count(2)?;
+        let (rs1, rs2) = if zero_first { (ZERO, reg(0)?) } else { (reg(0)?, ZERO) };
+        Ok(vec![Item::B { funct3, rs1, rs2, target: target(1)? }])
+    };
+    let system = |word: u32| -> Result<Vec<Item>, String> {
+        count(0)?;
+        Ok(vec![Item::Word(word)])
+    };
+
+    // Register fields of compressed instructions: any but zero, or three bits for x8-x15
+    let c_reg = |i: usize| -> Result<u32, String> {
+        match reg(i)? {
+            ZERO => Err(format!("'{}' cannot use zero", mnemonic)),
+            rd => Ok(rd),
+        }
+    };
+    let c_compact = |i: usize| -> Result<u32, String> {
+        match reg(i)? {
+            rd @ 8..=15 => Ok(rd),
+            _ => Err(format!("'{}' needs a register in x8-x15, found '{}'", mnemonic, operands[i])),
+        }
+    };
+    let c_imm6 = |i: usize| -> Result<u32, String> {
+        let value = immediate(&operands[i])?;
+        if (-32..32).contains(&value) { Ok(value as u32) } else { Err(format!("immediate {} does not fit in 6 bits", value)) }
+    };
+    // Word offsets, scaled and unsigned: below 128 from x8-x15, below 256 from sp
+    let c_offset = |i: usize, sp: bool| -> Result<(u32, u32), String> {
+        let (offset, base) = memory(&operands[i])?;
+        let limit = if sp { 256 } else { 128 };
+        if offset % 4 != 0 || !(0..limit).contains(&offset) {
+            return Err(format!("offset {} is out of range for '{}'", offset, mnemonic));
+        }
+        match (sp, base) {
+            (true, 2) | (false, 8..=15) => Ok((offset as u32, base)),
+            _ => Err(format!("'{}' cannot address from '{}'", mnemonic, operands[i])),
+        }
+    };
+    let c = |half: u32| -> Result<Vec<Item>, String> { Ok(vec![Item::C(half as u16)]) };
+
+    match mnemonic {
+        "add" => r(0x00, 0), "sub" => r(0x20, 0), "sll" => r(0x00, 1), "slt" => r(0x00, 2),
+        "sltu" => r(0x00, 3), "xor" => r(0x00, 4), "srl" => r(0x00, 5), "sra" => r(0x20, 5),
+        "or" => r(0x00, 6), "and" => r(0x00, 7),
+        "mul" => r(0x01, 0), "mulh" => r(0x01, 1), "mulhsu" => r(0x01, 2), "mulhu" => r(0x01, 3),
+        "div" => r(0x01, 4), "divu" => r(0x01, 5), "rem" => r(0x01, 6), "remu" => r(0x01, 7),
+
+        "addi" => i(0), "slti" => i(2), "sltiu" => i(3), "xori" => i(4), "ori" => i(6), "andi" => i(7),
+        "slli" => shift(1, 0), "srli" => shift(5, 0), "srai" => shift(5, 0x400),
+
+        "lb" => load(0), "lh" => load(1), "lw" => load(2), "lbu" => load(4), "lhu" => load(5),
+        "sb" => store(0), "sh" => store(1), "sw" => store(2),
+
+        "beq" => branch(0, false), "bne" => branch(1, false), "blt" => branch(4, false),
+        "bge" => branch(5, false), "bltu" => branch(6, false), "bgeu" => branch(7, false),
+        "bgt" => branch(4, true), "ble" => branch(5, true), "bgtu" => branch(6, true), "bleu" => branch(7, true),
+        "beqz" => branch_zero(0, false), "bnez" => branch_zero(1, false),
+        "bltz" => branch_zero(4, false), "bgez" => branch_zero(5, false),
+        "bgtz" => branch_zero(4, true), "blez" => branch_zero(5, true),
+
+        "lui" | "auipc" => {
+            count(2)?;
+            let imm = immediate(&operands[1])?;
+            if !(0..1 << 20).contains(&imm) {
+                return Err(format!("immediate {} does not fit in 20 bits", imm));
+            }
+            let opcode = if mnemonic == "lui" { OP_LUI } else { OP_AUIPC };
+            Ok(vec![Item::U { opcode, rd: reg(0)?, imm: imm as u32 }])
+        }
+
+        "jal" => match operands.len() {
+            1 => Ok(vec![Item::J { rd: RA, target: target(0)? }]),
+            _ => {
+                count(2)?;
+                Ok(vec![Item::J { rd: reg(0)?, target: target(1)? }])
+            }
+        },
+        "j" => {
+            count(1)?;
+            Ok(vec![Item::J { rd: ZERO, target: target(0)? }])
+        }
+        "jalr" => {
+            let (rd, imm, rs1) = match operands.len() {
+                1 => (RA, 0, reg(0)?),
+                2 => {
+                    let (imm, rs1) = memory(&operands[1])?;
+                    (reg(0)?, imm, rs1)
+                }
+                _ => {
+                    count(3)?;
+                    (reg(0)?, imm12(2)?, reg(1)?)
+                }
+            };
+            Ok(vec![Item::I { opcode: OP_JALR, funct3: 0, rd, rs1, imm }])
+        }
+        "jr" => {
+            count(1)?;
+            Ok(vec![Item::I { opcode: OP_JALR, funct3: 0, rd: ZERO, rs1: reg(0)?, imm: 0 }])
+        }
+        "ret" => {
+            count(0)?;
+            Ok(vec![Item::I { opcode: OP_JALR, funct3: 0, rd: ZERO, rs1: RA, imm: 0 }])
+        }
+        "call" => {
+            count(1)?;
+            Ok(vec![Item::Call { link: RA, scratch: RA, target: target(0)? }])
+        }
+        "tail" => {
+            count(1)?;
+            Ok(vec![Item::Call { link: ZERO, scratch: T1, target: target(0)? }])
+        }
+
+        "li" => {
+            count(2)?;
+            Ok(load_immediate(reg(0)?, immediate(&operands[1])?))
+        }
+        "la" => {
+            count(2)?;
+            Ok(vec![Item::La { rd: reg(0)?, target: target(1)? }])
+        }
+        "mv" => {
+            count(2)?;
+            Ok(vec![Item::I { opcode: OP_IMM, funct3: 0, rd: reg(0)?, rs1: reg(1)?, imm: 0 }])
+        }
+        "not" => {
+            count(2)?;
+            Ok(vec![Item::I { opcode: OP_IMM, funct3: 4, rd: reg(0)?, rs1: reg(1)?, imm: -1 }])
+        }
+        "neg" => {
+            count(2)?;
+            Ok(vec![Item::R { funct7: 0x20, funct3: 0, rd: reg(0)?, rs1: ZERO, rs2: reg(1)? }])
+        }
+        "seqz" => {
+            count(2)?;
+            Ok(vec![Item::I { opcode: OP_IMM, funct3: 3, rd: reg(0)?, rs1: reg(1)?, imm: 1 }])
+        }
+        "snez" => {
+            count(2)?;
+            Ok(vec![Item::R { funct7: 0, funct3: 3, rd: reg(0)?, rs1: ZERO, rs2: reg(1)? }])
+        }
+        "nop" => {
+            count(0)?;
+            Ok(vec![Item::I { opcode: OP_IMM, funct3: 0, rd: ZERO, rs1: ZERO, imm: 0 }])
+        }
+
+        "c.li" => {
+            count(2)?;
+            let imm = c_imm6(1)?;
+            c(0x2 << 13 | (imm >> 5 & 1) << 12 | c_reg(0)? << 7 | (imm & 0x1f) << 2 | 0x1)
+        }
+        "c.addi" => {
+            count(2)?;
+            let imm = c_imm6(1)?;
+            if imm == 0 {
+                return Err("'c.addi' needs a nonzero immediate".to_string());
+            }
+            c((imm >> 5 & 1) << 12 | c_reg(0)? << 7 | (imm & 0x1f) << 2 | 0x1)
+        }
+        "c.mv" => {
+            count(2)?;
+            c(0x4 << 13 | c_reg(0)? << 7 | c_reg(1)? << 2 | 0x2)
+        }
+        "c.jr" => {
+            count(1)?;
+            c(0x4 << 13 | c_reg(0)? << 7 | 0x2)
+        }
+        "c.lw" | "c.sw" => {
+            count(2)?;
+            let (offset, base) = c_offset(1, false)?;
+            let funct3 = if mnemonic == "c.lw" { 0x2 } else { 0x6 };
+            c(funct3 << 13 | (offset >> 3 & 7) << 10 | (base - 8) << 7 | (offset >> 2 & 1) << 6
+                | (offset >> 6 & 1) << 5 | (c_compact(0)? - 8) << 2)
+        }
+        "c.lwsp" => {
+            count(2)?;
+            let (offset, _) = c_offset(1, true)?;
+            c(0x2 << 13 | (offset >> 5 & 1) << 12 | c_reg(0)? << 7 | (offset >> 2 & 7) << 4 | (offset >> 6 & 3) << 2 | 0x2)
+        }
+        "c.swsp" => {
+            count(2)?;
+            let (offset, _) = c_offset(1, true)?;
+            c(0x6 << 13 | (offset >> 2 & 0xf) << 9 | (offset >> 6 & 3) << 7 | reg(0)? << 2 | 0x2)
+        }
+        "c.j" => {
+            count(1)?;
+            Ok(vec![Item::CJump { target: target(0)? }])
+        }
+        "c.beqz" | "c.bnez" => {
+            count(2)?;
+            let funct3 = if mnemonic == "c.beqz" { 0x6 } else { 0x7 };
+            Ok(vec![Item::CBranch { funct3, rs1: c_compact(0)?, target: target(1)? }])
+        }
+
+        "ecall" => system(OP_SYSTEM),
+        "ebreak" => system(0x0010_0000 | OP_SYSTEM),
+        "wfi" => system(0x1050_0000 | OP_SYSTEM),
+
+        _ => Err(format!("unknown instruction '{}'", mnemonic)),
+    }
+}
+
+// li as addi, lui, or lui + addi with the low part sign-extended
+fn load_immediate(rd: u32, value: i32) -> Vec<Item> {
+    if (-2048..2048).contains(&value) {
+        return vec![Item::I { opcode: OP_IMM, funct3: 0, rd, rs1: ZERO, imm: value }];
+    }
+    let upper = (value as u32).wrapping_add(0x800) >> 12;
+    let lower = value.wrapping_sub((upper << 12) as i32);
+    let mut items = vec![Item::U { opcode: OP_LUI, rd, imm: upper & 0xfffff }];
+    if lower != 0 {
+        items.push(Item::I { opcode: OP_IMM, funct3: 0, rd, rs1: rd, imm: lower });
+    }
+    items
+}
+
+fn register(name: &str) -> Result<u32, String> {
+    const ABI: [&str; 32] = [
+        "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4", "a5",
+        "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4", "t5", "t6",
+    ];
+    if let Some(index) = ABI.iter().position(|&r| r == name) {
+        return Ok(index as u32);
+    }
+    if name == "fp" {
+        return Ok(8);
+    }
+    name.strip_prefix('x')
+        .and_then(|n| n.parse::<u32>().ok())
+        .filter(|&n| n < 32)
+        .ok_or_else(|| format!("unknown register '{}'", name))
+}
+
+// 16(sp), (a0) or 0x10(t0)
+fn memory(operand: &str) -> Result<(i32, u32), String> {
+    let (offset, rest) = operand.split_once('(')
+        .ok_or_else(|| format!("expected 'offset(register)', found '{}'", operand))?;
+    let base = rest.strip_suffix(')')
+        .ok_or_else(|| format!("expected ')' in '{}'", operand))?;
+    let offset = if offset.trim().is_empty() { 0 } else { immediate(offset.trim())? };
+    if !(-2048..2048).contains(&offset) {
+        return Err(format!("offset {} does not fit in 12 bits", offset));
+    }
+    Ok((offset, register(base.trim())?))
+}
+
+// Decimal, 0x hex or 0b binary, optionally negative, `_` separators allowed
+fn immediate(text: &str) -> Result<i32, String> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text),
+    };
+    let digits = digits.replace('_', "");
+    let parsed = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16)
+    } else if let Some(bin) = digits.strip_prefix("0b").or_else(|| digits.strip_prefix("0B")) {
+        i64::from_str_radix(bin, 2)
+    } else {
+        digits.parse::<i64>()
+    };
+    let value = parsed.map_err(|_| format!("invalid immediate '{}'", text))?;
+    let value = if negative { -value } else { value };
+
+    // Addresses above 0x7fffffff are written unsigned
+    if (i32::MIN as i64..=u32::MAX as i64).contains(&value) {
+        Ok(value as u32 as i32)
+    } else {
+        Err(format!("immediate '{}' does not fit in 32 bits", text))
+    }
+}
+
+fn is_symbol(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == '.')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '$')
+}
+
+fn parse(source: &str) -> Result<Vec<(usize, Statement)>, String> {
+    let source = strip_block_comments(source)?;
+    let mut statements = Vec::new();
+
+    for (index, line) in source.lines().enumerate() {
+        let number = index + 1;
+        let mut rest = line;
+        if let Some(pos) = rest.find('#') {
+            rest = &rest[..pos];
+        }
+        if let Some(pos) = rest.find("//") {
+            rest = &rest[..pos];
+        }
+        let mut rest = rest.trim();
+
+        // Any number of labels may precede a statement
+        while let Some((label, after)) = rest.split_once(':') {
+            let label = label.trim();
+            if !is_symbol(label) {
+                return Err(format!("line {}: invalid label '{}'", number, label));
+            }
+            statements.push((number, Statement::Label(label.to_string())));
+            rest = after.trim();
+        }
+        if rest.is_empty() {
+            continue;
+        }
+
+        let (mnemonic, operands) = match rest.split_once(char::is_whitespace) {
+            Some((mnemonic, operands)) => (mnemonic, operands.trim()),
+            None => (rest, ""),
+        };
+        let operands: Vec<String> = if operands.is_empty() {
+            Vec::new()
+        } else {
+            operands.split(',').map(|o| o.trim().to_string()).collect()
+        };
+
+        let statement = if mnemonic.starts_with('.') {
+            Statement::Directive(mnemonic.to_string(), operands)
+        } else {
+            Statement::Instruction(mnemonic.to_ascii_lowercase(), operands)
+        };
+        statements.push((number, statement));
+    }
+
+    Ok(statements)
+}
+
+// Blank out /* */ comments, keeping line breaks so line numbers stay right
+fn strip_block_comments(source: &str) -> Result<String, String> {
+    let mut output = String::with_capacity(source.len());
+    let mut rest = source;
+    while let Some(start) = rest.find("/*") {
+        output.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find("*/") else {
+            let line = output.matches('\n').count() + 1;
+            return Err(format!("line {}: unterminated comment", line));
+        };
+        let comment = &rest[start..start + end + 2];
+        output.extend(comment.chars().filter(|&c| c == '\n'));
+        output.push(' ');
+        rest = &rest[start + end + 2..];
+    }
+    output.push_str(rest);
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Expected encodings are from llvm-mc -triple=riscv32 -mattr=+m,+c -show-encoding
+    fn text(source: &str) -> Vec<u8> {
+        let object = assemble(source).unwrap();
+        object.sections[0].data.clone()
+    }
+
+    fn words(source: &str) -> Vec<u32> {
+        text(source).chunks(4).map(|w| u32::from_le_bytes([w[0], w[1], w[2], w[3]])).collect()
+    }
+
+    fn halves(source: &str) -> Vec<u16> {
+        text(source).chunks(2).map(|h| u16::from_le_bytes([h[0], h[1]])).collect()
+    }
+
+    #[test]
+    fn r_type() {
+        assert_eq!(words("add a0, a1, a2"), [0x00c58533]);
+        assert_eq!(words("sub t0, t1, t2"), [0x407302b3]);
+        assert_eq!(words("mul a0, a0, a1"), [0x02b50533]);
+        assert_eq!(words("remu s1, s2, s3"), [0x033974b3]);
+    }
+
+    #[test]
+    fn i_type() {
+        assert_eq!(words("addi a0, a1, -2048"), [0x80058513]);
+        assert_eq!(words("addi a0, a1, 2047"), [0x7ff58513]);
+        assert_eq!(words("srai a0, a0, 31"), [0x41f55513]);
+        assert_eq!(words("lw a0, -4(sp)"), [0xffc12503]);
+        assert_eq!(words("jalr ra, 0(t0)"), [0x000280e7]);
+        assert_eq!(words("ecall"), [0x00000073]);
+        assert!(assemble("addi a0, a1, 2048").is_err());
+        assert!(assemble("slli a0, a0, 32").is_err());
+    }
+
+    #[test]
+    fn s_type() {
+        assert_eq!(words("sw a1, 2047(s0)"), [0x7eb42fa3]);
+        assert_eq!(words("sb zero, -2048(a0)"), [0x80050023]);
+    }
+
+    #[test]
+    fn u_type() {
+        assert_eq!(words("lui a0, 0xfffff"), [0xfffff537]);
+        assert_eq!(words("auipc t1, 0"), [0x00000317]);
+        assert!(assemble("lui a0, 0x100000").is_err());
+    }
+
+    #[test]
+    fn b_type() {
+        // Forward to 4092 and back to -4096
+        let source = "top: beq a0, a1, top\n bge t0, zero, far\n .space 4088\n far: blt a0, a1, top";
+        let words = words(source);
+        assert_eq!(words[0], 0x00b50063);
+        assert_eq!(words[1], 0x7e02dee3);
+        assert_eq!(words[words.len() - 1], 0x80b54063);
+        assert!(assemble("top: .space 4100\n beq a0, a1, top").is_err());
+    }
+
+    #[test]
+    fn j_type() {
+        let source = "top: jal ra, top\n j far\n .space 1048568\n far: nop";
+        let words = words(source);
+        assert_eq!(words[0], 0x000000ef);
+        assert_eq!(words[1], 0x7fdff06f);
+        assert!(assemble("top: j far\n .space 1048572\n far: nop").is_err());
+    }
+
+    #[test]
+    fn compressed() {
+        assert_eq!(halves("c.li a0, -32"), [0x5501]);       // CI
+        assert_eq!(halves("c.addi sp, 31"), [0x017d]);
+        assert_eq!(halves("c.lwsp ra, 252(sp)"), [0x50fe]);
+        assert_eq!(halves("c.swsp ra, 0(sp)"), [0xc006]);   // CSS
+        assert_eq!(halves("c.lw a5, 124(s1)"), [0x5cfc]);   // CL
+        assert_eq!(halves("c.sw s0, 4(a5)"), [0xc3c0]);     // CS
+        assert_eq!(halves("c.mv a0, a1"), [0x852e]);        // CR
+        assert_eq!(halves("c.jr ra"), [0x8082]);
+        assert!(assemble("c.li a0, 32").is_err());
+        assert!(assemble("c.lw a0, 128(s1)").is_err());
+        assert!(assemble("c.lw a0, 0(sp)").is_err());
+    }
+
+    #[test]
+    fn compressed_branches_and_jumps() {
+        // CB to 252 and back to -254, CJ back to -256 and forward to 2044
+        let source = "top: c.beqz a0, top\n c.bnez s1, far\n .space 250\n far: c.bnez a5, top\n \
+                      c.j top\n c.j farther\n .space 2042\n farther:";
+        let halves = halves(source);
+        assert_eq!(halves[0], 0xc101);
+        assert_eq!(halves[1], 0xecf5);
+        assert_eq!(&halves[127..130], [0xf389, 0xb701, 0xaff5]);
+        assert!(assemble("top: .space 258\n c.beqz a0, top").is_err());
+    }
+
+    #[test]
+    fn symbols_and_relocations() {
+        let source = "
+            .text
+            .global main
+        main:
+            call uart_init
+        .Lloop:
+            beq a0, zero, .Lloop
+            bne a0, a1, handler
+            la a0, table
+            ret
+        helper:
+            c.j main
+            .data
+        table:
+            .word helper
+        ";
+        let object = assemble(source).unwrap();
+        let names: Vec<(&str, Option<usize>, u32, bool)> = object.symbols.iter()
+            .map(|s| (s.name.as_str(), s.section, s.value, s.global))
+            .collect();
+        assert_eq!(names, [
+            ("main", Some(0), 0, true),
+            ("helper", Some(0), 28, false),
+            ("table", Some(1), 0, false),
+            ("uart_init", None, 0, true),
+            ("handler", None, 0, true),
+            (".Lpcrel_hi0", Some(0), 16, false),
+        ]);
+
+        let relocations = |section: usize| -> Vec<(u32, RelocationKind, &str)> {
+            object.sections[section].relocations.iter()
+                .map(|r| (r.offset, r.kind, object.symbols[r.symbol].name.as_str()))
+                .collect()
+        };
+        assert_eq!(relocations(0), [
+            (0, RelocationKind::CallPlt, "uart_init"),
+            (12, RelocationKind::Branch, "handler"),
+            (16, RelocationKind::PcrelHi20, "table"),
+            (20, RelocationKind::PcrelLo12I, ".Lpcrel_hi0"),
+        ]);
+        assert_eq!(relocations(1), [(0, RelocationKind::Abs32, "helper")]);
+
+        // The local branch and jump are resolved, the external branch keeps a zero offset
+        let text = &object.sections[0].data;
+        assert_eq!(&text[8..16], [0x63, 0x00, 0x05, 0x00, 0x63, 0x10, 0xb5, 0x00]);
+        assert_eq!(&text[28..30], [0xd5, 0xb7]);
+    }
+}
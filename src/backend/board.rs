@@ -0,0 +1,191 @@
+use crate::backend::{thumb, Target};
+use crate::frontend::ast;
+use crate::frontend::board::{self, Board, Exit, RegionKind};
+use std::fmt::Write;
+use std::fs;
+
+/* Boards `peric build` links for, from the declarative descriptions in boards/
+
+  board fpga {                                _start:
+      ram SYSTEM at 0x0000_0000 size 16K;  ->     li sp, 0x00010000
+      stack at 0x0001_0000;                       (clear .bss)
+      exit loop;                                  call main
+  }                                           .Lhang: j .Lhang
+
+The startup routine and a GNU ld linker script are generated from the description, so
+the built-in linker and an external toolchain lay programs out the same way: code and
+read-only data in the first ROM region (RAM on boards without ROM), data and .bss in
+the first RAM region. `peric board` writes both files for use outside peric, with a
+Cortex-M vector table and reset handler as the startup file for `--target cortex-m`. */
+
+const BUILTIN: [(&str, &str); 3] = [
+    ("qemu-virt", include_str!("../../boards/qemu-virt.board")),
+    ("fpga", include_str!("../../boards/fpga.board")),
+    ("lm3s6965", include_str!("../../boards/lm3s6965.board")),
+];
+
+// A built-in board by name, or a .board file
+pub fn find(name: &str) -> Result<Board, String> {
+    let source = match BUILTIN.iter().find(|(builtin, _)| *builtin == name) {
+        Some((_, source)) => source.to_string(),
+        None if name.ends_with(".board") => {
+            fs::read_to_string(name).map_err(|err| format!("cannot read '{}': {}", name, err))?
+        }
+        None => {
+            let names: Vec<&str> = BUILTIN.iter().map(|(builtin, _)| *builtin).collect();
+            return Err(format!("unknown board '{}' (expected {} or a .board file)", name, names.join(", ")));
+        }
+    };
+    board::parse(&source).map_err(|err| format!("in board '{}': {}", name, err))
+}
+
+/* Give peripherals declared without `at` the board's default base, then check every
+ * peripheral's registers lie inside one of the board's I/O regions */
+pub fn place_peripherals(program: &mut ast::Program, board: &Board) -> Result<(), Vec<String>> {
+    let mut errors = Vec::new();
+
+    for peripheral in &mut program.peripherals {
+        if peripheral.base_address.is_none() {
+            peripheral.base_address = board.peripherals.iter()
+                .find(|(name, _)| *name == peripheral.name)
+                .map(|&(_, base)| base);
+        }
+        let Some(base) = peripheral.base_address else { continue };
+
+        let end = peripheral.register_blocks.iter()
+            .flat_map(|block| block.registers.iter().map(move |r| r.offset as u64 + width(&block.reg_type)))
+            .max()
+            .unwrap_or(1);
+        let (start, end) = (base as u64, base as u64 + end);
+
+        let inside = board.regions.iter()
+            .filter(|r| r.kind == RegionKind::Io)
+            .any(|r| r.origin as u64 <= start && end <= r.origin as u64 + r.length as u64);
+        if !inside {
+            errors.push(format!(
+                "Peripheral '{}' at 0x{:08x}..0x{:08x} is outside the I/O regions of board '{}'",
+                peripheral.name, start, end, board.name,
+            ));
+        }
+    }
+
+    if errors.is_empty() { Ok(()) } else { Err(errors) }
+}
+
+fn width(reg_type: &ast::RegisterType) -> u64 {
+    match reg_type {
+        ast::RegisterType::U8 => 1,
+        ast::RegisterType::U16 => 2,
+        ast::RegisterType::U32 => 4,
+        ast::RegisterType::U64 => 8,
+    }
+}
+
+pub fn startup(board: &Board, target: Target) -> Result<String, std::fmt::Error> {
+    if target == Target::CortexM {
+        return thumb::startup(board);
+    }
+    let mut output = String::new();
+
+    writeln!(output, "/* Startup for board '{}', generated by peric from its board description", board.name)?;
+    writeln!(output, " *")?;
+    writeln!(output, " *   1. Sets up the stack pointer")?;
+    writeln!(output, " *   2. Clears .bss")?;
+    writeln!(output, " *   3. Calls main()")?;
+    match board.exit {
+        Exit::Loop => writeln!(output, " *   4. Spins once main() returns")?,
+        Exit::Finisher(_) => writeln!(output, " *   4. Exits through the test finisher with main()'s return value")?,
+    }
+    writeln!(output, " */")?;
+    writeln!(output)?;
+    writeln!(output, ".section .text.start")?;
+    writeln!(output, ".global _start")?;
+    writeln!(output)?;
+    writeln!(output, "_start:")?;
+    writeln!(output, "    li sp, 0x{:08x}", board.stack_top)?;
+    writeln!(output)?;
+    writeln!(output, "    la t0, __bss_start")?;
+    writeln!(output, "    la t1, __bss_end")?;
+    writeln!(output, ".Lclear_bss:")?;
+    writeln!(output, "    bgeu t0, t1, .Lmain")?;
+    writeln!(output, "    sw zero, 0(t0)")?;
+    writeln!(output, "    addi t0, t0, 4")?;
+    writeln!(output, "    j .Lclear_bss")?;
+    writeln!(output)?;
+    writeln!(output, ".Lmain:")?;
+    writeln!(output, "    call main")?;
+    writeln!(output)?;
+
+    if let Exit::Finisher(address) = board.exit {
+        writeln!(output, "    /* 0x5555 passes, exit_code << 16 | 0x3333 fails with exit_code */")?;
+        writeln!(output, "    li t0, 0x{:08x}", address)?;
+        writeln!(output, "    li t1, 0x5555")?;
+        writeln!(output, "    beqz a0, .Lexit")?;
+        writeln!(output, "    slli a0, a0, 16")?;
+        writeln!(output, "    li t1, 0x3333")?;
+        writeln!(output, "    or t1, a0, t1")?;
+        writeln!(output, ".Lexit:")?;
+        writeln!(output, "    sw t1, 0(t0)")?;
+        writeln!(output)?;
+    }
+
+    writeln!(output, ".Lhang:")?;
+    writeln!(output, "    j .Lhang")?;
+
+    Ok(output)
+}
+
+pub fn linker_script(board: &Board) -> Result<String, std::fmt::Error> {
+    let mut output = String::new();
+    let code = &board.regions[board.code_region()].name;
+    let data = &board.regions[board.data_region()].name;
+
+    writeln!(output, "/* Linker script for board '{}', generated by peric from its board description */", board.name)?;
+    writeln!(output)?;
+    writeln!(output, "ENTRY(_start)")?;
+    writeln!(output)?;
+    writeln!(output, "MEMORY {{")?;
+    for region in board.regions.iter().filter(|r| r.kind != RegionKind::Io) {
+        let access = if region.kind == RegionKind::Rom { "rx" } else { "rwx" };
+        writeln!(output, "    {} ({}) : ORIGIN = 0x{:08x}, LENGTH = {}", region.name, access, region.origin, size(region.length))?;
+    }
+    writeln!(output, "}}")?;
+    writeln!(output)?;
+    writeln!(output, "SECTIONS {{")?;
+    writeln!(output, "    /* Startup code must come first */")?;
+    writeln!(output, "    .text : {{")?;
+    writeln!(output, "        *(.text.start)")?;
+    writeln!(output, "        *(.text .text.*)")?;
+    writeln!(output, "    }} > {}", code)?;
+    writeln!(output)?;
+    writeln!(output, "    .rodata : {{")?;
+    writeln!(output, "        *(.rodata .rodata.*)")?;
+    writeln!(output, "    }} > {}", code)?;
+    writeln!(output)?;
+    writeln!(output, "    .data : {{")?;
+    writeln!(output, "        *(.data .data.* .sdata .sdata.*)")?;
+    writeln!(output, "    }} > {}", data)?;
+    writeln!(output)?;
+    writeln!(output, "    .bss (NOLOAD) : ALIGN(4) {{")?;
+    writeln!(output, "        __bss_start = .;")?;
+    writeln!(output, "        *(.bss .bss.* .sbss .sbss.* COMMON)")?;
+    writeln!(output, "        . = ALIGN(4);")?;
+    writeln!(output, "        __bss_end = .;")?;
+    writeln!(output, "    }} > {}", data)?;
+    writeln!(output)?;
+    writeln!(output, "    __stack_top = 0x{:08x};", board.stack_top)?;
+    writeln!(output, "}}")?;
+
+    Ok(output)
+}
+
+// 32M, 16K or 0x1800
+fn size(length: u32) -> String {
+    if length.is_multiple_of(1024 * 1024) {
+        format!("{}M", length / (1024 * 1024))
+    } else if length.is_multiple_of(1024) {
+        format!("{}K", length / 1024)
+    } else {
+        format!("0x{:x}", length)
+    }
+}
@@ -0,0 +1,119 @@
+use std::collections::HashMap;
+
+/* Compressed (RVC) forms of the generated assembly, for cores with the C extension
+
+  li a0, 5                 ->  c.li a0, 5            imm in -32..31
+  mv s0, t1                ->  c.mv s0, t1
+  addi sp, sp, -16         ->  c.addi sp, -16        rd = rs1, nonzero imm in -32..31
+  lw a1, 8(s0)             ->  c.lw a1, 8(s0)        x8-x15, offset 0..124
+  sw ra, 12(sp)            ->  c.swsp ra, 12(sp)     offset 0..252
+  ret                      ->  c.jr ra
+  j .LBB_main_3            ->  c.j .LBB_main_3       within 2 KiB
+  beqz a0, .LBB_main_5     ->  c.beqz a0, ...        x8-x15, within 256 bytes
+
+Everything but jumps and branches is rewritten first. Jumps and branches are then only
+compressed when their label is in range even if nothing after them were compressed:
+compressing an instruction never moves two others apart, so the bound stays safe. */
+
+pub fn compress(assembly: &str) -> String {
+    let mut lines: Vec<String> = assembly.lines().map(|line| compress_line(line).unwrap_or_else(|| line.to_string())).collect();
+
+    // Byte offset of every line and label, with branches and jumps still full size
+    let mut offsets = Vec::with_capacity(lines.len());
+    let mut labels: HashMap<&str, i64> = HashMap::new();
+    let mut offset = 0;
+    for line in &lines {
+        offsets.push(offset);
+        match line.trim().strip_suffix(':') {
+            Some(label) => {
+                labels.insert(label, offset);
+            }
+            None => offset += size(line),
+        }
+    }
+
+    let mut branches = Vec::new();
+    for (index, line) in lines.iter().enumerate() {
+        let (mnemonic, operands) = split(line);
+        let (compressed, target, range) = match (mnemonic, operands.as_slice()) {
+            ("j", [target]) => (format!("    c.j {}", target), *target, 1 << 11),
+            ("beqz" | "bnez", [rs, target]) if compact(rs) => {
+                (format!("    c.{} {}, {}", mnemonic, rs, target), *target, 1 << 8)
+            }
+            _ => continue,
+        };
+        let Some(&destination) = labels.get(target) else { continue };
+        if (-range..range).contains(&(destination - offsets[index])) {
+            branches.push((index, compressed));
+        }
+    }
+    for (index, compressed) in branches {
+        lines[index] = compressed;
+    }
+
+    let mut output = lines.join("\n");
+    output.push('\n');
+    output
+}
+
+fn compress_line(line: &str) -> Option<String> {
+    let (mnemonic, operands) = split(line);
+    let small = |imm: &str| imm.parse::<i32>().is_ok_and(|value| (-32..32).contains(&value));
+
+    let compressed = match (mnemonic, operands.as_slice()) {
+        ("li", [rd, imm]) if *rd != "zero" && small(imm) => format!("c.li {}, {}", rd, imm),
+        ("mv", [rd, rs]) if *rd != "zero" && *rs != "zero" => format!("c.mv {}, {}", rd, rs),
+        ("addi", [rd, rs, imm]) if rd == rs && *rd != "zero" && *imm != "0" && small(imm) => {
+            format!("c.addi {}, {}", rd, imm)
+        }
+        ("lw" | "sw", [reg, address]) => {
+            let (offset, base) = address.strip_suffix(')')?.split_once('(')?;
+            let offset: i32 = offset.parse().ok()?;
+            if offset % 4 != 0 {
+                return None;
+            }
+            if base == "sp" && (0..256).contains(&offset) && (mnemonic == "sw" || *reg != "zero") {
+                format!("c.{}sp {}, {}", mnemonic, reg, address)
+            } else if compact(base) && compact(reg) && (0..128).contains(&offset) {
+                format!("c.{} {}, {}", mnemonic, reg, address)
+            } else {
+                return None;
+            }
+        }
+        ("ret", []) => "c.jr ra".to_string(),
+        _ => return None,
+    };
+    Some(format!("    {}", compressed))
+}
+
+// Mnemonic and operands of an instruction line, or "" for labels and directives
+fn split(line: &str) -> (&str, Vec<&str>) {
+    let line = line.trim();
+    if line.is_empty() || line.ends_with(':') || line.starts_with('.') {
+        return ("", Vec::new());
+    }
+    match line.split_once(' ') {
+        Some((mnemonic, operands)) => (mnemonic, operands.split(',').map(str::trim).collect()),
+        None => (line, Vec::new()),
+    }
+}
+
+// Upper bound on the bytes of an instruction line, as the assembler expands it
+fn size(line: &str) -> i64 {
+    let (mnemonic, operands) = split(line);
+    match (mnemonic, operands.as_slice()) {
+        ("", _) => 0,
+        (m, _) if m.starts_with("c.") => 2,
+        ("call" | "tail" | "la", _) => 8,
+        ("li", [_, imm]) => match imm.parse::<i64>() {
+            Ok(value) if (-2048..2048).contains(&value) => 4,
+            _ => 8,
+        },
+        _ => 4,
+    }
+}
+
+// x8-x15, the registers the three-bit fields of compressed instructions can name
+fn compact(reg: &str) -> bool {
+    matches!(reg, "s0" | "fp" | "s1" | "a0" | "a1" | "a2" | "a3" | "a4" | "a5")
+}
@@ -0,0 +1,425 @@
+use crate::backend::assembler::Object;
+use crate::backend::linker::Executable;
+
+/* ELF32 relocatable objects and executables for RISC-V
+
+  ELF header                    ET_REL, EM_RISCV, soft-float ABI, RVC and RVE flags
+  section contents              .text, .text.start, ... in order of first use
+  .rela<section>                one per section with relocations (Elf32_Rela)
+  .symtab                       null symbol, local symbols, then global symbols
+  .strtab, .shstrtab
+  section header table
+
+Section flags follow the name: .text* is executable, .data* and .bss* writable,
+.bss* takes no space in the file, anything else is read-only data.
+
+An executable from the linker has the same layout without relocations, plus a PT_LOAD
+program header after the ELF header for each memory region it uses. The image from the
+code origin follows the program headers; sections sit in the file at their offset into
+that image, and its symbol table holds the global symbols with their final addresses. */
+
+const EM_RISCV: u16 = 243;
+pub const EF_RISCV_RVC: u32 = 0x1;
+pub const EF_RISCV_RVE: u32 = 0x8;
+const ET_REL: u16 = 1;
+const ET_EXEC: u16 = 2;
+
+const PT_LOAD: u32 = 1;
+const PF_RWX: u32 = 0x7;
+
+const SHT_PROGBITS: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const SHT_STRTAB: u32 = 3;
+const SHT_RELA: u32 = 4;
+const SHT_NOBITS: u32 = 8;
+
+const SHF_WRITE: u32 = 0x1;
+const SHF_ALLOC: u32 = 0x2;
+const SHF_EXECINSTR: u32 = 0x4;
+const SHF_INFO_LINK: u32 = 0x40;
+
+const STB_LOCAL: u8 = 0;
+const STB_GLOBAL: u8 = 1;
+const SHN_ABS: u16 = 0xfff1;
+
+const HEADER_SIZE: usize = 52;
+const PROGRAM_HEADER_SIZE: usize = 32;
+const SECTION_HEADER_SIZE: usize = 40;
+const SYMBOL_SIZE: u32 = 16;
+const RELA_SIZE: u32 = 12;
+
+struct SectionHeader {
+    name: u32,
+    kind: u32,
+    flags: u32,
+    address: u32,
+    offset: u32,
+    size: u32,
+    link: u32,
+    info: u32,
+    align: u32,
+    entry_size: u32,
+}
+
+// Names are appended once and referred to by offset
+struct StringTable {
+    data: Vec<u8>,
+}
+
+impl StringTable {
+    fn new() -> StringTable {
+        StringTable { data: vec![0] }
+    }
+
+    fn add(&mut self, name: &str) -> u32 {
+        let offset = self.data.len() as u32;
+        self.data.extend_from_slice(name.as_bytes());
+        self.data.push(0);
+        offset
+    }
+}
+
+const NULL_SECTION: SectionHeader = SectionHeader {
+    name: 0, kind: 0, flags: 0, address: 0, offset: 0, size: 0, link: 0, info: 0, align: 0, entry_size: 0,
+};
+
+// Section type and flags from the section name
+fn section_kind(name: &str) -> (u32, u32) {
+    let nobits = name.starts_with(".bss");
+    let mut flags = SHF_ALLOC;
+    if name.starts_with(".text") {
+        flags |= SHF_EXECINSTR;
+    }
+    if name.starts_with(".data") || nobits {
+        flags |= SHF_WRITE;
+    }
+    (if nobits { SHT_NOBITS } else { SHT_PROGBITS }, flags)
+}
+
+pub fn write_object(object: &Object, flags: u32) -> Vec<u8> {
+    let mut output = vec![0u8; HEADER_SIZE];
+    let mut headers = vec![NULL_SECTION];
+    let mut section_names = StringTable::new();
+
+    for section in &object.sections {
+        let (kind, flags) = section_kind(&section.name);
+        align(&mut output, 4);
+        let offset = output.len() as u32;
+        if kind != SHT_NOBITS {
+            output.extend_from_slice(&section.data);
+        }
+        headers.push(SectionHeader {
+            name: section_names.add(&section.name),
+            kind,
+            flags,
+            address: 0,
+            offset,
+            size: section.data.len() as u32,
+            link: 0,
+            info: 0,
+            align: 4,
+            entry_size: 0,
+        });
+    }
+
+    // Local symbols must come before global ones, so symbols are renumbered
+    let order: Vec<usize> = (0..object.symbols.len()).filter(|&i| !object.symbols[i].global)
+        .chain((0..object.symbols.len()).filter(|&i| object.symbols[i].global))
+        .collect();
+    let mut index = vec![0u32; object.symbols.len()];
+    for (position, &symbol) in order.iter().enumerate() {
+        index[symbol] = position as u32 + 1;
+    }
+
+    let symtab_index = (headers.len() + object.sections.iter().filter(|s| !s.relocations.is_empty()).count()) as u32;
+
+    for (number, section) in object.sections.iter().enumerate() {
+        if section.relocations.is_empty() {
+            continue;
+        }
+        align(&mut output, 4);
+        let offset = output.len() as u32;
+        for relocation in &section.relocations {
+            let info = index[relocation.symbol] << 8 | relocation.kind.elf_type();
+            output.extend_from_slice(&relocation.offset.to_le_bytes());
+            output.extend_from_slice(&info.to_le_bytes());
+            output.extend_from_slice(&relocation.addend.to_le_bytes());
+        }
+        headers.push(SectionHeader {
+            name: section_names.add(&format!(".rela{}", section.name)),
+            kind: SHT_RELA,
+            flags: SHF_INFO_LINK,
+            address: 0,
+            offset,
+            size: section.relocations.len() as u32 * RELA_SIZE,
+            link: symtab_index,
+            info: number as u32 + 1,
+            align: 4,
+            entry_size: RELA_SIZE,
+        });
+    }
+
+    let mut names = StringTable::new();
+    let symbols: Vec<SymbolEntry> = order.iter()
+        .map(|&i| {
+            let symbol = &object.symbols[i];
+            SymbolEntry {
+                name: names.add(&symbol.name),
+                value: symbol.value,
+                global: symbol.global,
+                section: symbol.section.map_or(0, |s| s as u16 + 1),
+            }
+        })
+        .collect();
+    write_symbols(&mut output, &mut headers, &mut section_names, &symbols, names);
+    finish(output, headers, section_names, ET_REL, 0, 0, flags)
+}
+
+pub fn write_executable(executable: &Executable, flags: u32) -> Vec<u8> {
+    let program_headers = executable.segments.len();
+    let mut output = vec![0u8; HEADER_SIZE + program_headers * PROGRAM_HEADER_SIZE];
+    let mut headers = vec![NULL_SECTION];
+    let mut section_names = StringTable::new();
+
+    let image = executable.binary();
+    let image_offset = output.len() as u32;
+    output.extend_from_slice(&image);
+
+    // Sections outside the image, .bss in RAM on boards with ROM, take no space in the file
+    let file_offset = |address: u32| match address.checked_sub(executable.origin) {
+        Some(offset) if offset <= image.len() as u32 => image_offset + offset,
+        _ => image_offset + image.len() as u32,
+    };
+
+    for section in &executable.sections {
+        let (kind, flags) = section_kind(section.name);
+        headers.push(SectionHeader {
+            name: section_names.add(section.name),
+            kind,
+            flags,
+            address: section.address,
+            offset: file_offset(section.address),
+            size: section.data.len() as u32,
+            link: 0,
+            info: 0,
+            align: 4,
+            entry_size: 0,
+        });
+    }
+
+    // Symbols outside every section, such as the stack top, are absolute
+    let mut names = StringTable::new();
+    let symbols: Vec<SymbolEntry> = executable.symbols.iter()
+        .map(|(name, value)| SymbolEntry {
+            name: names.add(name),
+            value: *value,
+            global: true,
+            section: executable.sections.iter()
+                .position(|s| (s.address..s.address + s.data.len() as u32).contains(value))
+                .map_or(SHN_ABS, |i| i as u16 + 1),
+        })
+        .collect();
+    write_symbols(&mut output, &mut headers, &mut section_names, &symbols, names);
+
+    for (number, segment) in executable.segments.iter().enumerate() {
+        let program_header = [
+            PT_LOAD, file_offset(segment.address), segment.address, segment.address,
+            segment.file_size, segment.memory_size, PF_RWX, 4,
+        ];
+        let at = HEADER_SIZE + number * PROGRAM_HEADER_SIZE;
+        for (i, word) in program_header.iter().enumerate() {
+            output[at + 4 * i..at + 4 * i + 4].copy_from_slice(&word.to_le_bytes());
+        }
+    }
+
+    finish(output, headers, section_names, ET_EXEC, executable.entry, program_headers as u16, flags)
+}
+
+struct SymbolEntry {
+    name: u32,
+    value: u32,
+    global: bool,
+    section: u16,
+}
+
+// .symtab and .strtab, with the local symbols already ahead of the global ones
+fn write_symbols(
+    output: &mut Vec<u8>,
+    headers: &mut Vec<SectionHeader>,
+    section_names: &mut StringTable,
+    symbols: &[SymbolEntry],
+    names: StringTable,
+) {
+    let symtab_index = headers.len() as u32;
+    align(output, 4);
+    let symtab_offset = output.len() as u32;
+    output.extend_from_slice(&[0u8; SYMBOL_SIZE as usize]);
+    for symbol in symbols {
+        let bind = if symbol.global { STB_GLOBAL } else { STB_LOCAL };
+        output.extend_from_slice(&symbol.name.to_le_bytes());
+        output.extend_from_slice(&symbol.value.to_le_bytes());
+        output.extend_from_slice(&0u32.to_le_bytes());
+        output.push(bind << 4);
+        output.push(0);
+        output.extend_from_slice(&symbol.section.to_le_bytes());
+    }
+    headers.push(SectionHeader {
+        name: section_names.add(".symtab"),
+        kind: SHT_SYMTAB,
+        flags: 0,
+        address: 0,
+        offset: symtab_offset,
+        size: (symbols.len() as u32 + 1) * SYMBOL_SIZE,
+        link: symtab_index + 1,
+        info: 1 + symbols.iter().filter(|s| !s.global).count() as u32,
+        align: 4,
+        entry_size: SYMBOL_SIZE,
+    });
+
+    let strtab_offset = output.len() as u32;
+    output.extend_from_slice(&names.data);
+    headers.push(SectionHeader {
+        name: section_names.add(".strtab"),
+        kind: SHT_STRTAB,
+        flags: 0,
+        address: 0,
+        offset: strtab_offset,
+        size: names.data.len() as u32,
+        link: 0,
+        info: 0,
+        align: 1,
+        entry_size: 0,
+    });
+}
+
+// .shstrtab, the section header table and the ELF header
+fn finish(
+    mut output: Vec<u8>,
+    mut headers: Vec<SectionHeader>,
+    mut section_names: StringTable,
+    kind: u16,
+    entry: u32,
+    program_headers: u16,
+    flags: u32,
+) -> Vec<u8> {
+    let shstrtab_name = section_names.add(".shstrtab");
+    let shstrtab_offset = output.len() as u32;
+    output.extend_from_slice(&section_names.data);
+    headers.push(SectionHeader {
+        name: shstrtab_name,
+        kind: SHT_STRTAB,
+        flags: 0,
+        address: 0,
+        offset: shstrtab_offset,
+        size: section_names.data.len() as u32,
+        link: 0,
+        info: 0,
+        align: 1,
+        entry_size: 0,
+    });
+
+    align(&mut output, 4);
+    let section_headers = output.len() as u32;
+    for header in &headers {
+        for word in [
+            header.name, header.kind, header.flags, header.address, header.offset, header.size,
+            header.link, header.info, header.align, header.entry_size,
+        ] {
+            output.extend_from_slice(&word.to_le_bytes());
+        }
+    }
+
+    let (program_header_offset, program_header_size) = match program_headers {
+        0 => (0u32, 0u16),
+        _ => (HEADER_SIZE as u32, PROGRAM_HEADER_SIZE as u16),
+    };
+    let mut header = Vec::with_capacity(HEADER_SIZE);
+    header.extend_from_slice(&[0x7f, b'E', b'L', b'F', 1, 1, 1, 0]);   // ELFCLASS32, little endian
+    header.extend_from_slice(&[0; 8]);
+    header.extend_from_slice(&kind.to_le_bytes());
+    header.extend_from_slice(&EM_RISCV.to_le_bytes());
+    header.extend_from_slice(&1u32.to_le_bytes());                      // EV_CURRENT
+    header.extend_from_slice(&entry.to_le_bytes());
+    header.extend_from_slice(&program_header_offset.to_le_bytes());
+    header.extend_from_slice(&section_headers.to_le_bytes());
+    header.extend_from_slice(&flags.to_le_bytes());                     // Soft-float ABI
+    header.extend_from_slice(&(HEADER_SIZE as u16).to_le_bytes());
+    header.extend_from_slice(&program_header_size.to_le_bytes());
+    header.extend_from_slice(&program_headers.to_le_bytes());
+    header.extend_from_slice(&(SECTION_HEADER_SIZE as u16).to_le_bytes());
+    header.extend_from_slice(&(headers.len() as u16).to_le_bytes());
+    header.extend_from_slice(&(headers.len() as u16 - 1).to_le_bytes());  // .shstrtab is last
+    output[..HEADER_SIZE].copy_from_slice(&header);
+
+    output
+}
+
+fn align(output: &mut Vec<u8>, alignment: usize) {
+    output.resize(output.len().next_multiple_of(alignment), 0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::assembler::assemble;
+
+    fn u32_at(data: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn name_at(table: &[u8], offset: usize) -> &str {
+        let end = table[offset..].iter().position(|&b| b == 0).unwrap();
+        std::str::from_utf8(&table[offset..offset + end]).unwrap()
+    }
+
+    #[test]
+    fn object_symbols_and_relocations() {
+        let object = assemble(".global main\nmain:\n call uart_init\nhelper:\n bne a0, a1, handler\n j helper").unwrap();
+        let elf = write_object(&object, EF_RISCV_RVC);
+        assert_eq!(&elf[..4], b"\x7fELF");
+        assert_eq!(u16::from_le_bytes([elf[16], elf[17]]), ET_REL);
+        assert_eq!(u32_at(&elf, 36), EF_RISCV_RVC);
+
+        // Section headers as (type, offset, size, link, info)
+        let table = u32_at(&elf, 32) as usize;
+        let count = u16::from_le_bytes([elf[48], elf[49]]) as usize;
+        let headers: Vec<[u32; 5]> = (0..count)
+            .map(|i| {
+                let at = table + i * SECTION_HEADER_SIZE;
+                [u32_at(&elf, at + 4), u32_at(&elf, at + 16), u32_at(&elf, at + 20), u32_at(&elf, at + 24), u32_at(&elf, at + 28)]
+            })
+            .collect();
+        let find = |kind: u32| headers.iter().find(|h| h[0] == kind).unwrap();
+        let [_, rela_offset, rela_size, symtab, target] = *find(SHT_RELA);
+        assert_eq!(target, 1);
+        let [_, symtab_offset, symtab_size, strtab, locals] = headers[symtab as usize];
+        let [_, strtab_offset, strtab_size, _, _] = headers[strtab as usize];
+        let names = &elf[strtab_offset as usize..(strtab_offset + strtab_size) as usize];
+
+        // (name, value, binding, section index), locals first
+        let symbols: Vec<(&str, u32, u8, u16)> = (1..(symtab_size / SYMBOL_SIZE) as usize)
+            .map(|i| {
+                let at = symtab_offset as usize + i * SYMBOL_SIZE as usize;
+                let name = name_at(names, u32_at(&elf, at) as usize);
+                (name, u32_at(&elf, at + 4), elf[at + 12] >> 4, u16::from_le_bytes([elf[at + 14], elf[at + 15]]))
+            })
+            .collect();
+        assert_eq!(symbols, [
+            ("helper", 8, STB_LOCAL, 1),
+            ("main", 0, STB_GLOBAL, 1),
+            ("uart_init", 0, STB_GLOBAL, 0),
+            ("handler", 0, STB_GLOBAL, 0),
+        ]);
+        assert_eq!(locals, 2);
+
+        // (offset, type, symbol name, addend); the jump back to helper needs none
+        let relocations: Vec<(u32, u32, &str, u32)> = (0..(rela_size / RELA_SIZE) as usize)
+            .map(|i| {
+                let at = rela_offset as usize + i * RELA_SIZE as usize;
+                let info = u32_at(&elf, at + 4);
+                (u32_at(&elf, at), info & 0xff, symbols[(info >> 8) as usize - 1].0, u32_at(&elf, at + 8))
+            })
+            .collect();
+        assert_eq!(relocations, [(0, 19, "uart_init", 0), (8, 16, "handler", 0)]);
+    }
+}
@@ -1,43 +1,90 @@
 use crate::ir::{Instruction, Op};
 use crate::ir::cfg::CmpOp;
+use crate::backend::{March, Target};
 use crate::backend::regalloc::AllocationResult;
 use std::fmt::Write;
 
-/* RV32I Stack frame layout (grows downward, 16-byte aligned)
+/* RV32I Stack frame layout (grows downward, 16-byte aligned, 4-byte on RV32E)
 
-  sp + frame_size - 4  <- ra
-  sp + frame_size - 8  <- s_regs[0]  (first used callee-saved reg)
-  sp + frame_size - 12 <- s_regs[1]
+  sp + frame_size + w  <- incoming argument 9   (caller's outgoing area)
+  sp + frame_size      <- incoming argument 8
+  sp + frame_size - w  <- ra         (only in functions that make calls)
+  sp + frame_size - 2w <- s_regs[0]  (first used callee-saved reg)
+  sp + frame_size - 3w <- s_regs[1]
   ...
-  sp + 0
+  sp + w * out + w     <- spill slot 1
+  sp + w * out         <- spill slot 0
+  ...
+  sp + w               <- outgoing argument 9
+  sp + 0               <- outgoing argument 8
+
+w = 4, the register size of ILP32, or 8 on RV64 (LP64), where slots are saved with sd
+out = most arguments beyond a7 passed by any call in the function (one slot each)
+frame_size = round_up_16(w * (saves_ra + num_s_regs + num_spill_slots + out))
+
+On RV32E, ILP32E passes arguments beyond a5 on the stack instead, and the frame is only
+rounded up to 4 bytes, so it is exactly the words it holds. On RV64, peripheral registers
+narrower than u64 are read with lwu so their values are zero-extended like u64 reads.
+
+A leaf function that uses no callee-saved registers and spills nothing has no frame at
+all and returns with a plain `ret` wherever it returns. Otherwise every return jumps to
+one epilogue at the end of the function, unless it already is the last instruction.
 
-frame_size = round_up_16(4 + 4 * num_s_regs) */
+A tail call moves its arguments into the argument registers, tears the frame down like the epilogue and
+leaves with `tail`, so the callee returns straight to our caller. Tail calls do not touch
+ra, a function whose only calls are tail calls does not save it. A function calling
+itself in tail position keeps its frame and jumps back to just after the prologue. */
 
 pub fn generate(
     function: &str,
     instructions: &[Instruction],
     result: &AllocationResult,
+    target: Target,
+    march: March,
 ) -> Result<String, std::fmt::Error> {
     let allocation = &result.allocation;
+    let a_regs = march.registers().args;
     let s_regs = &result.used_s_regs;
-    let raw = 4 + 4 * s_regs.len();
-    let frame_size = (raw + 15) & !15;
-    let ra_offset = frame_size - 4;
+    let outgoing = instructions.iter()
+        .filter(|i| matches!(i.operation, Op::Call(_)))
+        .map(|i| i.args.len().saturating_sub(a_regs.len()))
+        .max()
+        .unwrap_or(0);
+    let saves_ra = instructions.iter().any(|i| matches!(i.operation, Op::Call(_)));
+    let recurses = instructions.iter().any(|i| matches!(&i.operation, Op::TailCall(t) if t == function));
+    let word = target.word_size();
+    let (load, store) = target.word_ops();
+    let slot_base = word * outgoing;
+    let raw = word * (saves_ra as usize + s_regs.len() + result.spilled.len() + outgoing);
+    let frame_size = raw.next_multiple_of(march.stack_alignment());
+
+    // Saved registers from the top of the frame down
+    let saved: Vec<(&str, usize)> = saves_ra.then_some("ra").into_iter()
+        .chain(s_regs.iter().map(String::as_str))
+        .enumerate()
+        .map(|(i, reg)| (reg, frame_size - word - word * i))
+        .collect();
+    let epilogue = format!(".LBB_{}_ret", function);
+    let body = format!(".LBB_{}_body", function);
+    let mut jumps_to_epilogue = false;
 
     let mut output = String::new();
 
     writeln!(output, ".section .text")?;
     writeln!(output, ".global {}", function)?;
     writeln!(output, "{}:", function)?;
-    writeln!(output, "    addi sp, sp, -{}", frame_size)?;
-    writeln!(output, "    sw ra, {}(sp)", ra_offset)?;
+    if frame_size > 0 {
+        writeln!(output, "    addi sp, sp, -{}", frame_size)?;
+    }
 
-    for (i, reg) in s_regs.iter().enumerate() {
-        let offset = ra_offset as isize - 4 - (i as isize * 4);
-        writeln!(output, "    sw {}, {}(sp)", reg, offset)?;
+    for (reg, offset) in &saved {
+        writeln!(output, "    {} {}, {}(sp)", store, reg, offset)?;
+    }
+    if recurses {
+        writeln!(output, "{}:", body)?;
     }
 
-    for instr in instructions {
+    for (index, instr) in instructions.iter().enumerate() {
         match &instr.operation {
             Op::LoadImm(val) => {
                 let rd = allocation.get(&instr.destination.unwrap()).unwrap();
@@ -49,16 +96,29 @@ pub fn generate(
                 writeln!(output, "    li {}, 0x{:08x}", rd, addr)?;
             }
 
-            Op::LoadWord => {
+            Op::LoadWord(offset) => {
                 let rd = allocation.get(&instr.destination.unwrap()).unwrap();
                 let rs = allocation.get(&instr.args[0]).unwrap();
-                writeln!(output, "    lw {}, 0({})", rd, rs)?;
+                let lw = if target == Target::Rv64 { "lwu" } else { "lw" };
+                writeln!(output, "    {} {}, {}({})", lw, rd, offset, rs)?;
             }
 
-            Op::StoreWord => {
+            Op::StoreWord(offset) => {
                 let rs = allocation.get(&instr.args[0]).unwrap();
                 let rd = allocation.get(&instr.args[1]).unwrap();
-                writeln!(output, "    sw {}, 0({})", rs, rd)?;
+                writeln!(output, "    sw {}, {}({})", rs, offset, rd)?;
+            }
+
+            Op::LoadDouble(offset) => {
+                let rd = allocation.get(&instr.destination.unwrap()).unwrap();
+                let rs = allocation.get(&instr.args[0]).unwrap();
+                writeln!(output, "    ld {}, {}({})", rd, offset, rs)?;
+            }
+
+            Op::StoreDouble(offset) => {
+                let rs = allocation.get(&instr.args[0]).unwrap();
+                let rd = allocation.get(&instr.args[1]).unwrap();
+                writeln!(output, "    sd {}, {}({})", rs, offset, rd)?;
             }
 
             Op::Mov => {
@@ -67,18 +127,52 @@ pub fn generate(
                 writeln!(output, "    mv {}, {}", rd, rs)?;
             }
 
-            Op::MovArg(i) => {
-                let rd = allocation.get(&instr.destination.unwrap()).unwrap();
-                // TODO: Panic / Error if i >= 8 (we only have a0...a7)
-                writeln!(output, "    mv {}, a{}", rd, i)?;
+            Op::MovArg(_) => {
+                if index > 0 && matches!(instructions[index - 1].operation, Op::MovArg(_)) {
+                    continue;   /* Moved with the first of its run */
+                }
+
+                /* All arguments are read at once, a register may hold one argument
+                 * and be the destination of another. Stack arguments come last. */
+                let run: Vec<(&String, usize)> = instructions[index..].iter()
+                    .map_while(|i| match i.operation {
+                        Op::MovArg(arg) => Some((allocation.get(&i.destination.unwrap()).unwrap(), arg)),
+                        _ => None,
+                    })
+                    .collect();
+                let moves: Vec<(&str, &str)> = run.iter()
+                    .filter_map(|&(rd, arg)| a_regs.get(arg).map(|reg| (rd.as_str(), *reg)))
+                    .collect();
+                write_moves(&mut output, &moves)?;
+                for &(rd, arg) in run.iter().filter(|(_, arg)| *arg >= a_regs.len()) {
+                    writeln!(output, "    {} {}, {}(sp)", load, rd, frame_size + word * (arg - a_regs.len()))?;
+                }
             }
 
             Op::Call(target) => {
-                for (i, arg) in instr.args.iter().enumerate() {
-                    let rs = allocation.get(arg).unwrap();
-                    writeln!(output, "    mv a{}, {}", i, rs)?;
+                /* Stack arguments first, spilled ones go through a scratch register */
+                for (i, arg) in instr.args.iter().enumerate().skip(a_regs.len()) {
+                    let offset = word * (i - a_regs.len());
+                    match result.spilled.get(arg) {
+                        Some(slot) => {
+                            let scratch = &result.scratch[0];
+                            writeln!(output, "    {} {}, {}(sp)", load, scratch, slot_base + word * slot)?;
+                            writeln!(output, "    {} {}, {}(sp)", store, scratch, offset)?;
+                        }
+                        None => {
+                            let rs = allocation.get(arg).unwrap();
+                            writeln!(output, "    {} {}, {}(sp)", store, rs, offset)?;
+                        }
+                    }
                 }
 
+                /* Spilled arguments are reloaded in place */
+                let moves: Vec<(&str, &str)> = instr.args.iter()
+                    .zip(a_regs)
+                    .map(|(arg, reg)| (*reg, allocation.get(arg).unwrap().as_str()))
+                    .collect();
+                write_moves(&mut output, &moves)?;
+
                 writeln!(output, "    call {}", target)?;
 
                 if let Some(dest) = instr.destination {
@@ -87,20 +181,35 @@ pub fn generate(
                 }
             }
 
+            Op::TailCall(target) => {
+                let moves: Vec<(&str, &str)> = instr.args.iter()
+                    .zip(a_regs)
+                    .map(|(arg, reg)| (*reg, allocation.get(arg).unwrap().as_str()))
+                    .collect();
+                write_moves(&mut output, &moves)?;
+
+                if target == function {
+                    writeln!(output, "    j {}\n", body)?;
+                } else {
+                    write_teardown(&mut output, &saved, frame_size, load)?;
+                    writeln!(output, "    tail {}\n", target)?;
+                }
+            }
+
             Op::Ret(val) => {
                 if let Some(reg) = val {
                     let rs = allocation.get(reg).unwrap();
-                    writeln!(output, "    mv a0, {}", rs)?;
+                    if rs != "a0" {
+                        writeln!(output, "    mv a0, {}", rs)?;
+                    }
                 }
 
-                for (i, reg) in s_regs.iter().enumerate() {
-                    let offset = ra_offset as isize - 4 - (i as isize * 4);
-                    writeln!(output, "    lw {}, {}(sp)", reg, offset)?;
+                if frame_size == 0 {
+                    writeln!(output, "    ret\n")?;
+                } else if index + 1 != instructions.len() {
+                    writeln!(output, "    j {}", epilogue)?;
+                    jumps_to_epilogue = true;
                 }
-
-                writeln!(output, "    lw ra, {}(sp)", ra_offset)?;
-                writeln!(output, "    addi sp, sp, {}", frame_size)?;
-                writeln!(output, "    ret\n")?;
             }
 
             Op::Label(label) => {
@@ -116,6 +225,11 @@ pub fn generate(
                 writeln!(output, "    beqz {}, {}", cond_reg, target)?;
             }
 
+            Op::BranchIfTrue(target) => {
+                let cond_reg = allocation.get(&instr.args[0]).unwrap();
+                writeln!(output, "    bnez {}, {}", cond_reg, target)?;
+            }
+
             Op::BranchCond(op, label) => {
                 let lhs = allocation.get(&instr.args[0]).unwrap();
                 let rhs = allocation.get(&instr.args[1]).unwrap();
@@ -199,6 +313,20 @@ pub fn generate(
                 writeln!(output, "    srl {}, {}, {}", rd, rs1, rs2)?;
             }
             
+            Op::Slt => {
+                let rd = allocation.get(&instr.destination.unwrap()).unwrap();
+                let rs1 = allocation.get(&instr.args[0]).unwrap();
+                let rs2 = allocation.get(&instr.args[1]).unwrap();
+                writeln!(output, "    slt {}, {}, {}", rd, rs1, rs2)?;
+            }
+            
+            Op::Sltu => {
+                let rd = allocation.get(&instr.destination.unwrap()).unwrap();
+                let rs1 = allocation.get(&instr.args[0]).unwrap();
+                let rs2 = allocation.get(&instr.args[1]).unwrap();
+                writeln!(output, "    sltu {}, {}, {}", rd, rs1, rs2)?;
+            }
+            
             Op::Neg => {
                 let rd = allocation.get(&instr.destination.unwrap()).unwrap();
                 let rs = allocation.get(&instr.args[0]).unwrap();
@@ -210,8 +338,146 @@ pub fn generate(
                 let rs = allocation.get(&instr.args[0]).unwrap();
                 writeln!(output, "    not {}, {}", rd, rs)?;
             }
+
+            Op::AddImm(imm) => {
+                let rd = allocation.get(&instr.destination.unwrap()).unwrap();
+                let rs = allocation.get(&instr.args[0]).unwrap();
+                writeln!(output, "    addi {}, {}, {}", rd, rs, imm)?;
+            }
+
+            Op::AndImm(imm) => {
+                let rd = allocation.get(&instr.destination.unwrap()).unwrap();
+                let rs = allocation.get(&instr.args[0]).unwrap();
+                writeln!(output, "    andi {}, {}, {}", rd, rs, imm)?;
+            }
+
+            Op::OrImm(imm) => {
+                let rd = allocation.get(&instr.destination.unwrap()).unwrap();
+                let rs = allocation.get(&instr.args[0]).unwrap();
+                writeln!(output, "    ori {}, {}, {}", rd, rs, imm)?;
+            }
+
+            Op::XorImm(imm) => {
+                let rd = allocation.get(&instr.destination.unwrap()).unwrap();
+                let rs = allocation.get(&instr.args[0]).unwrap();
+                writeln!(output, "    xori {}, {}, {}", rd, rs, imm)?;
+            }
+
+            Op::SllImm(imm) => {
+                let rd = allocation.get(&instr.destination.unwrap()).unwrap();
+                let rs = allocation.get(&instr.args[0]).unwrap();
+                writeln!(output, "    slli {}, {}, {}", rd, rs, imm)?;
+            }
+
+            Op::SrlImm(imm) => {
+                let rd = allocation.get(&instr.destination.unwrap()).unwrap();
+                let rs = allocation.get(&instr.args[0]).unwrap();
+                writeln!(output, "    srli {}, {}, {}", rd, rs, imm)?;
+            }
+
+            Op::SltImm(imm) => {
+                let rd = allocation.get(&instr.destination.unwrap()).unwrap();
+                let rs = allocation.get(&instr.args[0]).unwrap();
+                writeln!(output, "    slti {}, {}, {}", rd, rs, imm)?;
+            }
+
+            Op::SltuImm(imm) => {
+                let rd = allocation.get(&instr.destination.unwrap()).unwrap();
+                let rs = allocation.get(&instr.args[0]).unwrap();
+                writeln!(output, "    sltiu {}, {}, {}", rd, rs, imm)?;
+            }
+
+            Op::LoadSlot(slot) => {
+                let rd = allocation.get(&instr.destination.unwrap()).unwrap();
+                writeln!(output, "    {} {}, {}(sp)", load, rd, slot_base + word * slot)?;
+            }
+
+            Op::StoreSlot(slot) => {
+                let rs = allocation.get(&instr.args[0]).unwrap();
+                writeln!(output, "    {} {}, {}(sp)", store, rs, slot_base + word * slot)?;
+            }
+
+            Op::Phi(_) => {
+                unreachable!("phis are removed by ssa::destruct before register allocation")
+            }
         }
     }
 
+    let returns = instructions.iter().any(|i| matches!(i.operation, Op::Ret(_)));
+    if frame_size > 0 && returns {
+        if jumps_to_epilogue {
+            writeln!(output, "{}:", epilogue)?;
+        }
+        write_teardown(&mut output, &saved, frame_size, load)?;
+        writeln!(output, "    ret\n")?;
+    }
+
     Ok(output)
 }
+
+#[derive(Debug, Clone, Copy)]
+pub enum Move<'a> {
+    Copy(&'a str, &'a str),     // rd <- rs
+    Swap(&'a str, &'a str),     // Three xors, no free register needed
+}
+
+/* Moves that all happen at once, as (destination, source) pairs
+ *
+ *   a0 <- t0, a1 <- a0    ->  mv a1, a0; mv a0, t0
+ *   a0 <- a1, a1 <- a0    ->  xor a0, a0, a1; xor a1, a0, a1; xor a0, a0, a1
+ *
+ * A move is made once no other pending move still reads its destination, in the order
+ * given otherwise. What is left are cycles, broken by swapping the registers of one
+ * move in place. Shared with the Thumb-2 generator. */
+pub fn order_moves<'a>(moves: &[(&'a str, &'a str)]) -> Vec<Move<'a>> {
+    let mut pending: Vec<(&str, &str)> = moves.iter().copied().filter(|(rd, rs)| rd != rs).collect();
+    let mut ordered = Vec::new();
+
+    while !pending.is_empty() {
+        let ready = pending.iter().position(|(rd, _)| !pending.iter().any(|(_, rs)| rs == rd));
+        match ready {
+            Some(i) => {
+                let (rd, rs) = pending.remove(i);
+                ordered.push(Move::Copy(rd, rs));
+            }
+            None => {
+                let (rd, rs) = pending.remove(0);
+                ordered.push(Move::Swap(rd, rs));
+                for (_, source) in &mut pending {
+                    if *source == rd {
+                        *source = rs;
+                    } else if *source == rs {
+                        *source = rd;
+                    }
+                }
+                pending.retain(|(rd, rs)| rd != rs);
+            }
+        }
+    }
+    ordered
+}
+
+fn write_moves(output: &mut String, moves: &[(&str, &str)]) -> std::fmt::Result {
+    for step in order_moves(moves) {
+        match step {
+            Move::Copy(rd, rs) => writeln!(output, "    mv {}, {}", rd, rs)?,
+            Move::Swap(rd, rs) => {
+                writeln!(output, "    xor {}, {}, {}", rd, rd, rs)?;
+                writeln!(output, "    xor {}, {}, {}", rs, rd, rs)?;
+                writeln!(output, "    xor {}, {}, {}", rd, rd, rs)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// Restore the saved registers and release the frame
+fn write_teardown(output: &mut String, saved: &[(&str, usize)], frame_size: usize, load: &str) -> std::fmt::Result {
+    for (reg, offset) in saved {
+        writeln!(output, "    {} {}, {}(sp)", load, reg, offset)?;
+    }
+    if frame_size > 0 {
+        writeln!(output, "    addi sp, sp, {}", frame_size)?;
+    }
+    Ok(())
+}
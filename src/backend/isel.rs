@@ -0,0 +1,169 @@
+use std::collections::HashMap;
+use crate::ir::{Instruction, VirtualRegister, Op};
+use crate::ir::cfg::{CmpOp, CFG, Terminator};
+use crate::backend::regalloc::RegisterClasses;
+
+/* Instruction selection on the CFG, before register allocation
+ *
+ *   li t1, 255; and t2, t0, t1          ->  andi t2, t0, 255
+ *   li t1, 0x10000000; sw t2, 0(t1)
+ *   li t3, 0x10000000; sw t4, 1(t3)     ->  li t1, 0x10000000; sw t2, 0(t1); sw t4, 1(t1)
+ *
+ * Only registers with a single definition in the function count as constants, since
+ * variables are reassigned through Op::Mov into the same register. Peripheral base
+ * addresses are shared between accesses within a basic block. LoadImm and LoadAddr
+ * left without uses are removed.
+ *
+ *   li t1, 0; beq t0, t1, .L            ->  beqz t0, .L
+ *
+ * An equality comparison with a constant 0 becomes a branch on the other operand, which
+ * needs no register for the 0 and has a compressed form (c.beqz, c.bnez).
+ *
+ *   t2 = call uart_enable_fifo(t1)
+ *   ret t2                              ->  tail uart_enable_fifo(t1)
+ *
+ * A call ending a block that returns its result, or returns nothing, becomes a tail call
+ * when all its arguments are passed in registers: the caller's frame is gone by the time
+ * the callee runs, so there is nowhere to put stack arguments. */
+pub fn select(cfg: &CFG, registers: &RegisterClasses) -> CFG {
+    let mut cfg = cfg.clone();
+
+    let mut definitions: HashMap<VirtualRegister, usize> = HashMap::new();
+    for instr in cfg.blocks.iter().flat_map(|b| &b.instructions) {
+        if let Some(dest) = instr.destination {
+            *definitions.entry(dest).or_default() += 1;
+        }
+    }
+    let single = |vreg: &VirtualRegister| definitions.get(vreg) == Some(&1);
+
+    let constants: HashMap<VirtualRegister, i32> = cfg.blocks.iter()
+        .flat_map(|b| &b.instructions)
+        .filter_map(|i| match (&i.operation, i.destination) {
+            (Op::LoadImm(value), Some(dest)) if single(&dest) => Some((dest, *value)),
+            _ => None,
+        })
+        .collect();
+
+    // Later loads of a base address already held in a register reuse that register
+    let mut replaced: HashMap<VirtualRegister, VirtualRegister> = HashMap::new();
+    for block in &mut cfg.blocks {
+        let mut bases: HashMap<u32, VirtualRegister> = HashMap::new();
+        block.instructions.retain(|instr| match (&instr.operation, instr.destination) {
+            (Op::LoadAddr(addr), Some(dest)) if single(&dest) => match bases.get(addr) {
+                Some(&existing) => {
+                    replaced.insert(dest, existing);
+                    false
+                }
+                None => {
+                    bases.insert(*addr, dest);
+                    true
+                }
+            },
+            _ => true,
+        });
+    }
+
+    for block in &mut cfg.blocks {
+        for instr in &mut block.instructions {
+            for arg in &mut instr.args {
+                if let Some(base) = replaced.get(arg) {
+                    *arg = *base;
+                }
+            }
+            select_immediate(instr, &constants);
+        }
+    }
+
+    select_zero_branches(&mut cfg, &constants);
+    remove_unused_constants(&mut cfg);
+    select_tail_calls(&mut cfg, registers);
+    cfg
+}
+
+fn fits_imm12(value: i32) -> bool {
+    (-2048..2048).contains(&value)
+}
+
+fn select_immediate(instr: &mut Instruction, constants: &HashMap<VirtualRegister, i32>) {
+    if instr.args.len() != 2 {
+        return;
+    }
+
+    // Commutative operations can take the constant from either side
+    let commutative = matches!(instr.operation, Op::Add | Op::And | Op::Or | Op::Xor);
+    if commutative && !constants.contains_key(&instr.args[1]) && constants.contains_key(&instr.args[0]) {
+        instr.args.swap(0, 1);
+    }
+
+    let Some(&value) = constants.get(&instr.args[1]) else { return };
+    let shift = (0..32).contains(&value);
+
+    let selected = match instr.operation {
+        Op::Add if fits_imm12(value) => Op::AddImm(value),
+        Op::Sub if value.checked_neg().is_some_and(fits_imm12) => Op::AddImm(-value),
+        Op::And if fits_imm12(value) => Op::AndImm(value),
+        Op::Or if fits_imm12(value) => Op::OrImm(value),
+        Op::Xor if fits_imm12(value) => Op::XorImm(value),
+        Op::Sll if shift => Op::SllImm(value),
+        Op::Srl if shift => Op::SrlImm(value),
+        Op::Slt if fits_imm12(value) => Op::SltImm(value),
+        Op::Sltu if fits_imm12(value) => Op::SltuImm(value),
+        _ => return,
+    };
+
+    instr.operation = selected;
+    instr.args.truncate(1);
+}
+
+// x == 0 and x != 0 branch on x itself, as BranchIfFalse and BranchIfTrue after flattening
+fn select_zero_branches(cfg: &mut CFG, constants: &HashMap<VirtualRegister, i32>) {
+    for block in &mut cfg.blocks {
+        let Terminator::CondBranch { op, lhs, rhs, then_block, else_block } = block.terminator else { continue };
+        let cond = match (constants.get(&lhs), constants.get(&rhs)) {
+            (_, Some(0)) => lhs,
+            (Some(0), _) => rhs,
+            _ => continue,
+        };
+        block.terminator = match op {
+            CmpOp::Ne => Terminator::Branch { cond, then_block, else_block },
+            CmpOp::Eq => Terminator::Branch { cond, then_block: else_block, else_block: then_block },
+            _ => continue,
+        };
+    }
+}
+
+fn remove_unused_constants(cfg: &mut CFG) {
+    let mut uses: HashMap<VirtualRegister, usize> = HashMap::new();
+    for block in &cfg.blocks {
+        for arg in block.instructions.iter().flat_map(|i| &i.args) {
+            *uses.entry(*arg).or_default() += 1;
+        }
+        for vreg in block.terminator.uses() {
+            *uses.entry(vreg).or_default() += 1;
+        }
+    }
+
+    for block in &mut cfg.blocks {
+        block.instructions.retain(|instr| {
+            !matches!(instr.operation, Op::LoadImm(_) | Op::LoadAddr(_))
+                || instr.destination.is_some_and(|d| uses.contains_key(&d))
+        });
+    }
+}
+
+fn select_tail_calls(cfg: &mut CFG, registers: &RegisterClasses) {
+    for block in &mut cfg.blocks {
+        let Terminator::Return(value) = block.terminator else { continue };
+        let Some(last) = block.instructions.last_mut() else { continue };
+        let Op::Call(target) = &last.operation else { continue };
+
+        let returns_result = value.is_none() || value == last.destination;
+        if !returns_result || last.args.len() > registers.args.len() {
+            continue;
+        }
+
+        last.operation = Op::TailCall(target.clone());
+        last.destination = None;
+        block.terminator = Terminator::Return(None);
+    }
+}
@@ -0,0 +1,325 @@
+use std::collections::HashMap;
+use crate::backend::assembler::{self, Object, RelocationKind};
+use crate::frontend::board::{Board, Region};
+
+/* Static linker for the objects of one program and its board's start file
+
+  code region  ->  .text     .text.start of every object, then .text and .text.*
+  (ROM or RAM)     .rodata   .rodata*
+                   ...
+  data region  ->  .data     .data*, .sdata*
+  (RAM)            .bss      .bss*, .sbss*   (no space in the .bin)
+                   ...
+
+Code goes to the board's first ROM region, or its RAM if it has none, and data to its
+first RAM; when both are the same region, data follows the code. Input sections are
+placed in the order of the objects given, each 4-byte aligned. Global symbols, and
+__bss_start, __bss_end and __stack_top as the generated linker script defines them, are
+shared between objects, anything else stays local to its object. Every relocation is
+then patched in place; a reference no object defines or a program that does not fit in
+its regions is an error. */
+
+#[derive(Debug, Clone)]
+pub struct Executable {
+    pub entry: u32,
+    pub origin: u32,                        // Start of the code region, where the .bin is loaded
+    pub sections: Vec<OutputSection>,      // In placement order
+    pub segments: Vec<Segment>,             // One per region used
+    pub symbols: Vec<(String, u32)>,        // Global symbols and their addresses
+    pub usage: Vec<(String, u32, u32)>,     // Region name, bytes used and its length
+}
+
+#[derive(Debug, Clone)]
+pub struct OutputSection {
+    pub name: &'static str,
+    pub address: u32,
+    pub data: Vec<u8>,
+    pub nobits: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Segment {
+    pub address: u32,
+    pub file_size: u32,                     // Initialised bytes, loaded from the .bin
+    pub memory_size: u32,                   // Including .bss
+}
+
+impl Executable {
+    // Raw image to load at the code origin, up to the end of the last initialised section
+    pub fn binary(&self) -> Vec<u8> {
+        let mut image = Vec::new();
+        for section in self.sections.iter().filter(|s| !s.nobits) {
+            image.resize((section.address - self.origin) as usize, 0);
+            image.extend_from_slice(&section.data);
+        }
+        image
+    }
+}
+
+// Output sections and the input sections they collect, in placement order
+const LAYOUT: [(&str, &[&str]); 5] = [
+    (".text", &[".text.start"]),
+    (".text", &[".text"]),
+    (".rodata", &[".rodata"]),
+    (".data", &[".data", ".sdata"]),
+    (".bss", &[".bss", ".sbss"]),
+];
+
+fn output_section(input: &str) -> Option<usize> {
+    if input == ".text.start" {
+        return Some(0);
+    }
+    LAYOUT.iter().skip(1).position(|(_, prefixes)| prefixes.iter().any(|p| input.starts_with(p))).map(|i| i + 1)
+}
+
+pub fn link(objects: &[(&str, Object)], board: &Board) -> Result<Executable, String> {
+    for (name, object) in objects {
+        if let Some(section) = object.sections.iter().find(|s| output_section(&s.name).is_none()) {
+            return Err(format!("{}: section '{}' has no place in the memory map of '{}'", name, section.name, board.name));
+        }
+    }
+
+    let (code, data) = (board.code_region(), board.data_region());
+    if code != data {
+        let initialised = objects.iter()
+            .flat_map(|(name, object)| object.sections.iter().map(move |s| (name, s)))
+            .find(|(_, s)| output_section(&s.name).is_some_and(|slot| LAYOUT[slot].0 == ".data") && !s.data.is_empty());
+        if let Some((name, section)) = initialised {
+            return Err(format!(
+                "{}: '{}' keeps code in ROM, so initialised data in '{}' cannot be loaded",
+                name, board.name, section.name,
+            ));
+        }
+    }
+
+    // Place input sections, remembering where each one went
+    let mut sections: Vec<OutputSection> = Vec::new();
+    let mut placement: HashMap<(usize, usize), (usize, u32)> = HashMap::new();   // -> (output, address)
+    let mut cursors: Vec<u32> = board.regions.iter().map(|r| r.origin).collect();
+    let mut bss = None;
+    for (slot, (name, _)) in LAYOUT.iter().enumerate() {
+        let region = if *name == ".data" || *name == ".bss" { data } else { code };
+        for (o, (_, object)) in objects.iter().enumerate() {
+            for (s, section) in object.sections.iter().enumerate() {
+                if output_section(&section.name) != Some(slot) {
+                    continue;
+                }
+                let address = cursors[region].next_multiple_of(4);
+                if sections.last().is_none_or(|last| last.name != *name) {
+                    sections.push(OutputSection { name, address, data: Vec::new(), nobits: *name == ".bss" });
+                }
+                let output = sections.last_mut().unwrap();
+                output.data.resize((address - output.address) as usize, 0);
+                output.data.extend_from_slice(&section.data);
+                placement.insert((o, s), (sections.len() - 1, address));
+                cursors[region] = address + section.data.len() as u32;
+            }
+        }
+        if *name == ".bss" {
+            let start = sections.last().filter(|s| s.nobits).map_or(cursors[data], |s| s.address).next_multiple_of(4);
+            cursors[data] = cursors[data].next_multiple_of(4);
+            bss = Some((start, cursors[data]));
+        }
+    }
+    let (bss_start, bss_end) = bss.unwrap();
+
+    let mut usage = Vec::new();
+    let mut segments = Vec::new();
+    for region in if code == data { vec![code] } else { vec![code, data] } {
+        let Region { name, origin, length, .. } = &board.regions[region];
+        let used = cursors[region] - origin;
+        if used > *length {
+            return Err(format!(
+                "program needs {} bytes of {} but '{}' has {} ({} bytes too many)",
+                used, name, board.name, length, used - length,
+            ));
+        }
+        usage.push((name.clone(), used, *length));
+
+        let placed: Vec<&OutputSection> = sections.iter()
+            .filter(|s| (*origin..origin + length).contains(&s.address))
+            .collect();
+        if let Some(first) = placed.first() {
+            let end = |s: &&OutputSection| s.address + s.data.len() as u32;
+            segments.push(Segment {
+                address: first.address,
+                file_size: placed.iter().filter(|s| !s.nobits).map(end).max().map_or(0, |e| e - first.address),
+                memory_size: placed.iter().map(end).max().unwrap() - first.address,
+            });
+        }
+    }
+
+    // Global symbols, which must be defined once
+    let mut globals: HashMap<&str, (u32, &str)> = [
+        ("__bss_start", bss_start),
+        ("__bss_end", bss_end),
+        ("__stack_top", board.stack_top),
+    ].into_iter().map(|(name, value)| (name, (value, "the linker script"))).collect();
+    for (o, (file, object)) in objects.iter().enumerate() {
+        for symbol in object.symbols.iter().filter(|s| s.global) {
+            let Some(section) = symbol.section else { continue };
+            let value = placement[&(o, section)].1 + symbol.value;
+            if let Some((_, other)) = globals.insert(&symbol.name, (value, file)) {
+                return Err(format!("'{}' is defined in both {} and {}", symbol.name, other, file));
+            }
+        }
+    }
+
+    let resolve = |o: usize, index: usize| -> Result<u32, String> {
+        let (file, object) = &objects[o];
+        let symbol = &object.symbols[index];
+        match symbol.section {
+            Some(section) => Ok(placement[&(o, section)].1 + symbol.value),
+            None => globals.get(symbol.name.as_str())
+                .map(|&(value, _)| value)
+                .ok_or_else(|| format!("undefined reference to '{}' in {}", symbol.name, file)),
+        }
+    };
+
+    // The low half of a PC-relative pair refers to the label of its high half
+    let mut pcrel_high: HashMap<u32, i32> = HashMap::new();
+    for (o, (_, object)) in objects.iter().enumerate() {
+        for (s, section) in object.sections.iter().enumerate() {
+            for relocation in section.relocations.iter().filter(|r| r.kind == RelocationKind::PcrelHi20) {
+                let place = placement[&(o, s)].1 + relocation.offset;
+                let target = resolve(o, relocation.symbol)?.wrapping_add(relocation.addend as u32);
+                pcrel_high.insert(place, target.wrapping_sub(place) as i32);
+            }
+        }
+    }
+
+    for (o, (file, object)) in objects.iter().enumerate() {
+        for (s, section) in object.sections.iter().enumerate() {
+            let (index, base) = placement[&(o, s)];
+            let output = &mut sections[index];
+
+            for relocation in &section.relocations {
+                let place = base + relocation.offset;
+                let target = resolve(o, relocation.symbol)?.wrapping_add(relocation.addend as u32);
+                let offset = target.wrapping_sub(place) as i32;
+                let at = (place - output.address) as usize;
+                let in_range = |range: i32| -> Result<i32, String> {
+                    if (-range..range).contains(&offset) {
+                        Ok(offset)
+                    } else {
+                        let name = &object.symbols[relocation.symbol].name;
+                        Err(format!("{}: '{}' is out of range of the instruction at 0x{:08x}", file, name, place))
+                    }
+                };
+
+                // Compressed instructions are only two bytes, and may end their section
+                if let RelocationKind::RvcBranch | RelocationKind::RvcJump = relocation.kind {
+                    let half = u16::from_le_bytes(output.data[at..at + 2].try_into().unwrap()) as u32;
+                    let patched = match relocation.kind {
+                        RelocationKind::RvcBranch => half & 0xe383 | assembler::c_branch_offset(in_range(1 << 8)?),
+                        _ => half & 0xe003 | assembler::c_jump_offset(in_range(1 << 11)?),
+                    };
+                    output.data[at..at + 2].copy_from_slice(&(patched as u16).to_le_bytes());
+                    continue;
+                }
+
+                let word = u32::from_le_bytes(output.data[at..at + 4].try_into().unwrap());
+
+                let patched = match relocation.kind {
+                    RelocationKind::Abs32 => vec![target],
+                    RelocationKind::Branch => vec![word & 0x01ff_f07f | assembler::branch_offset(in_range(1 << 12)?)],
+                    RelocationKind::Jal => vec![word & 0x0000_0fff | assembler::jump_offset(in_range(1 << 20)?)],
+                    RelocationKind::CallPlt => {
+                        let next = u32::from_le_bytes(output.data[at + 4..at + 8].try_into().unwrap());
+                        let (high, low) = split(offset);
+                        vec![word & 0xfff | high << 12, next & 0x000f_ffff | (low as u32) << 20]
+                    }
+                    RelocationKind::PcrelHi20 => vec![word & 0xfff | split(offset).0 << 12],
+                    RelocationKind::PcrelLo12I => {
+                        let high = pcrel_high.get(&target).ok_or_else(|| {
+                            format!("{}: '%pcrel_lo' at 0x{:08x} has no matching '%pcrel_hi'", file, place)
+                        })?;
+                        vec![word & 0x000f_ffff | (split(*high).1 as u32) << 20]
+                    }
+                    RelocationKind::RvcBranch | RelocationKind::RvcJump => unreachable!(),
+                };
+
+                for (i, word) in patched.into_iter().enumerate() {
+                    output.data[at + 4 * i..at + 4 * i + 4].copy_from_slice(&word.to_le_bytes());
+                }
+            }
+        }
+    }
+
+    let entry = globals.get("_start")
+        .map(|&(value, _)| value)
+        .ok_or_else(|| format!("no '_start' symbol, the start file of '{}' must define it", board.name))?;
+
+    let mut symbols: Vec<(String, u32)> = globals.iter()
+        .map(|(name, &(value, _))| (name.to_string(), value))
+        .collect();
+    symbols.sort_by(|a, b| (a.1, &a.0).cmp(&(b.1, &b.0)));
+
+    Ok(Executable { entry, origin: board.regions[code].origin, sections, segments, symbols, usage })
+}
+
+// Upper 20 bits for auipc and the sign-extended lower 12 for the instruction after it
+fn split(offset: i32) -> (u32, i32) {
+    let high = (offset as u32).wrapping_add(0x800) >> 12;
+    let low = offset.wrapping_sub((high << 12) as i32);
+    (high & 0xfffff, low)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frontend::board;
+
+    const START: &str = "
+        .section .text.start
+        .globl _start
+    _start:
+        call main
+    .Lhang:
+        j .Lhang
+    ";
+
+    fn fpga() -> Board {
+        board::parse(include_str!("../../boards/fpga.board")).unwrap()
+    }
+
+    fn link_sources(sources: &[(&'static str, String)]) -> Result<Executable, String> {
+        let objects: Vec<(&str, Object)> = sources.iter()
+            .map(|(name, source)| (*name, assembler::assemble(source).unwrap()))
+            .collect();
+        link(&objects, &fpga())
+    }
+
+    #[test]
+    fn start_file_first_and_calls_resolved() {
+        let program = ".text\n.globl main\nmain:\n    ret\n".to_string();
+        let executable = link_sources(&[("start.o", START.to_string()), ("main.o", program)]).unwrap();
+
+        assert_eq!(executable.entry, 0);
+        let main = executable.symbols.iter().find(|(name, _)| name == "main").unwrap().1;
+        assert_eq!(main, 12);   /* auipc, jalr, j */
+
+        // call main is auipc ra, 0; jalr ra, 12(ra)
+        let text = &executable.sections[0].data;
+        let words: Vec<u32> = text.chunks(4).map(|w| u32::from_le_bytes(w.try_into().unwrap())).collect();
+        assert_eq!(words[..2], [0x00000097, 0x00c080e7]);
+        assert_eq!(executable.usage, [("SYSTEM".to_string(), 16, 16 * 1024)]);
+    }
+
+    #[test]
+    fn undefined_reference() {
+        let error = link_sources(&[("start.o", START.to_string())]).unwrap_err();
+        assert_eq!(error, "undefined reference to 'main' in start.o");
+    }
+
+    #[test]
+    fn program_larger_than_its_region() {
+        // 16 bytes of code, then data up to the end of the 16K of SYSTEM and past it
+        let program = |data: u32| format!(".text\n.globl main\nmain:\n    ret\n.data\n.space {}\n", data);
+        let fits = link_sources(&[("start.o", START.to_string()), ("main.o", program(16 * 1024 - 16))]).unwrap();
+        assert_eq!(fits.usage, [("SYSTEM".to_string(), 16 * 1024, 16 * 1024)]);
+
+        let error = link_sources(&[("start.o", START.to_string()), ("main.o", program(16 * 1024 - 12))]).unwrap_err();
+        assert_eq!(error, "program needs 16388 bytes of SYSTEM but 'fpga' has 16384 (4 bytes too many)");
+    }
+}
@@ -36,6 +36,13 @@ pub fn analyse(cfg: &CFG) -> LivenessResult {
                     block_liveness.use_set.insert(*cond);
                 }
             }
+            Terminator::CondBranch { lhs, rhs, .. } => {
+                for reg in [lhs, rhs] {
+                    if !block_liveness.def_set.contains(reg) {
+                        block_liveness.use_set.insert(*reg);
+                    }
+                }
+            }
             Terminator::Return(Some(reg)) => {
                 if !block_liveness.def_set.contains(reg) {
                     block_liveness.use_set.insert(*reg);
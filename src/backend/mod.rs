@@ -1,21 +1,208 @@
 pub mod regalloc;
 pub mod generator;
+pub mod isel;
 pub mod liveness;
+pub mod assembler;
+pub mod elf;
+pub mod linker;
+pub mod board;
+pub mod runtime;
+pub mod compress;
+pub mod thumb;
 
 use crate::ir;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Target {
+    #[default]
+    Rv32,       // ILP32 (ILP32E on RV32E)
+    Rv64,       // LP64: 64-bit registers and stack slots
+    CortexM,    // Thumb-2 for ARMv7-M, AAPCS
+}
+
+impl Target {
+    pub fn parse(name: &str) -> Result<Target, String> {
+        match name {
+            "rv32" => Ok(Target::Rv32),
+            "rv64" => Ok(Target::Rv64),
+            "cortex-m" => Ok(Target::CortexM),
+            _ => Err(format!("unknown target '{}' (expected rv32, rv64 or cortex-m)", name)),
+        }
+    }
+
+    pub fn xlen(self) -> u32 {
+        match self {
+            Target::Rv32 | Target::CortexM => 32,
+            Target::Rv64 => 64,
+        }
+    }
+
+    // Bytes in a register, and so in each saved register, spill slot and stack argument
+    pub fn word_size(self) -> usize {
+        self.xlen() as usize / 8
+    }
+
+    // Loads and stores of whole RISC-V registers
+    pub fn word_ops(self) -> (&'static str, &'static str) {
+        match self {
+            Target::Rv64 => ("ld", "sd"),
+            _ => ("lw", "sw"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum March {
+    Rv32i,      // Base integer instructions only
+    #[default]
+    Rv32im,     // With multiply and divide
+    Rv32imc,    // With multiply, divide and compressed instructions
+    Rv32e,      // Embedded base: 16 registers, no multiply or divide
+}
+
+impl March {
+    pub fn parse(name: &str) -> Result<March, String> {
+        match name {
+            "rv32i" => Ok(March::Rv32i),
+            "rv32im" => Ok(March::Rv32im),
+            "rv32imc" => Ok(March::Rv32imc),
+            "rv32e" => Ok(March::Rv32e),
+            _ => Err(format!("unknown architecture '{}' (expected rv32i, rv32im, rv32imc or rv32e)", name)),
+        }
+    }
+
+    pub fn has_m(self) -> bool {
+        matches!(self, March::Rv32im | March::Rv32imc)
+    }
+
+    pub fn registers(self) -> &'static regalloc::RegisterClasses {
+        match self {
+            March::Rv32e => &regalloc::RV32E,
+            _ => &regalloc::RV32I,
+        }
+    }
+
+    // Register classes preferring those compressed instructions can name, for -Os
+    pub fn compact_registers(self) -> &'static regalloc::RegisterClasses {
+        match self {
+            March::Rv32e => &regalloc::RV32E_COMPACT,
+            _ => &regalloc::RV32I_COMPACT,
+        }
+    }
+
+    pub fn has_c(self) -> bool {
+        self == March::Rv32imc
+    }
+
+    // e_flags of the ELF files built for it
+    pub fn elf_flags(self) -> u32 {
+        match self {
+            March::Rv32e => elf::EF_RISCV_RVE,
+            March::Rv32imc => elf::EF_RISCV_RVC,
+            _ => 0,
+        }
+    }
+
+    // ILP32 keeps sp 16-byte aligned, ILP32E only 4-byte
+    pub fn stack_alignment(self) -> usize {
+        match self {
+            March::Rv32e => 4,
+            _ => 16,
+        }
+    }
+}
+
+pub fn generate(
+    functions: &[(String, ir::cfg::CFG)],
+    target: Target,
+    march: March,
+    opt_level: ir::opt::OptLevel,
+) -> Result<String, String> {
+    check_calls(functions)?;
+    check_widths(functions, target)?;
+    let registers = match (target, opt_level) {
+        (Target::CortexM, _) => &regalloc::THUMB,
+        (_, ir::opt::OptLevel::Os) => march.compact_registers(),
+        _ => march.registers(),
+    };
 
-pub fn generate(functions: &[(String, ir::cfg::CFG)]) -> Result<String, String> {
     let mut assembly = String::new();
+    let mut routines = Vec::new();
+    if target == Target::CortexM {
+        assembly.push_str(thumb::PREAMBLE);
+    }
 
     for (function, cfg) in functions {
-        let allocation = regalloc::allocate(cfg);
+        let mut cfg = cfg.clone();
+        ir::ssa::destruct(&mut cfg);
+        if target == Target::CortexM {
+            thumb::expand_rem(&mut cfg);
+        } else if !march.has_m() {
+            for routine in runtime::replace_muldiv(&mut cfg) {
+                if !routines.contains(&routine) {
+                    routines.push(routine);
+                }
+            }
+        }
+        let cfg = isel::select(&cfg, registers);
+        let mut allocation = regalloc::allocate(&cfg, registers);
         
-        let instructions = cfg.flatten(function);
-        let asm = generator::generate(function, &instructions, &allocation)
-            .map_err(|e| e.to_string())?;
+        let instructions = cfg.flatten(function)?;
+        let instructions = regalloc::insert_spill_code(&instructions, &mut allocation, registers);
+        let asm = match target {
+            Target::CortexM => thumb::generate(function, &instructions, &allocation),
+            _ => generator::generate(function, &instructions, &allocation, target, march),
+        };
+        let asm = asm.map_err(|e| e.to_string())?;
 
         assembly.push_str(&asm);
     }
 
+    assembly.push_str(&runtime::routines(&routines));
+    if march.has_c() {
+        assembly = compress::compress(&assembly);
+    }
     Ok(assembly)
+}
+
+// u64 registers only fit in the registers of RV64
+fn check_widths(functions: &[(String, ir::cfg::CFG)], target: Target) -> Result<(), String> {
+    if target == Target::Rv64 {
+        return Ok(());
+    }
+    for (function, cfg) in functions {
+        if cfg.accesses_u64() {
+            return Err(format!("'{}' accesses a u64 register, which needs '--target rv64'", function));
+        }
+    }
+    Ok(())
+}
+
+/* Arguments beyond the argument registers are passed on the stack, so a call passing a different number of
+ * arguments than its callee reads would silently read or clobber the wrong stack words */
+fn check_calls(functions: &[(String, ir::cfg::CFG)]) -> Result<(), String> {
+    let params: HashMap<&str, usize> = functions.iter()
+        .map(|(name, cfg)| (name.as_str(), cfg.params))
+        .collect();
+
+    for (function, cfg) in functions {
+        for instr in cfg.blocks.iter().flat_map(|b| &b.instructions) {
+            let ir::Op::Call(target) = &instr.operation else { continue };
+            match params.get(target.as_str()) {
+                None => {
+                    return Err(format!("'{}' calls undefined function '{}'", function, target));
+                }
+                Some(&expected) if expected != instr.args.len() => {
+                    return Err(format!(
+                        "'{}' calls '{}' with {} argument(s), but it is defined with {}",
+                        function, target, instr.args.len(), expected,
+                    ));
+                }
+                Some(_) => {}
+            }
+        }
+    }
+
+    Ok(())
 }
\ No newline at end of file
@@ -1,13 +1,13 @@
 use std::collections::{HashMap, HashSet};
-use crate::ir::{VirtualRegister, Op};
-use crate::ir::cfg::{CFG, Terminator};
+use crate::ir::{Instruction, VirtualRegister, Op};
+use crate::ir::cfg::CFG;
 use crate::backend::liveness;
 
 pub type Allocation = HashMap<VirtualRegister, String>;
 
 /* RV32I Register Classes */
 
-#[allow(dead_code)] /* Currently hardcoded in generator.rs, TODO: Properly handle >8 arguments */
+/* Arguments beyond a7 are passed on the stack, see generator.rs */
 pub const A_REGS: [&str; 8] = ["a0", "a1", "a2", "a3", "a4", "a5", "a6", "a7"];
 
 pub const T_REGS: [&str; 7] = ["t0", "t1", "t2", "t3", "t4", "t5", "t6"];
@@ -15,6 +15,87 @@ pub const T_REGS: [&str; 7] = ["t0", "t1", "t2", "t3", "t4", "t5", "t6"];
 pub const S_REGS: [&str; 11] = ["s1", "s2", "s3", "s4", "s5", "s6",
                                   "s7", "s8", "s9", "s10", "s11"];  // TODO: Check if s0/fp can be used
 
+/* RV32E Register Classes, x0-x15 only
+ *
+ * ILP32E passes six arguments in registers. With a single callee-saved register left
+ * besides s0, s0 is allocated too: peric never sets up a frame pointer. */
+
+pub const E_A_REGS: [&str; 6] = ["a0", "a1", "a2", "a3", "a4", "a5"];
+
+pub const E_T_REGS: [&str; 3] = ["t0", "t1", "t2"];
+
+pub const E_S_REGS: [&str; 2] = ["s0", "s1"];
+
+/* -Os Register Classes
+ *
+ * Compressed loads, stores and branches can only name x8-x15, so a0-a5 come before the
+ * other temporaries and s0-s1 before the other callee-saved registers. Argument registers
+ * are only allocated in functions that do not spill, see allocate. */
+
+pub const COMPACT_T_REGS: [&str; 13] = ["a0", "a1", "a2", "a3", "a4", "a5",
+                                        "t0", "t1", "t2", "t3", "t4", "t5", "t6"];
+
+pub const COMPACT_S_REGS: [&str; 12] = ["s0", "s1", "s2", "s3", "s4", "s5",
+                                        "s6", "s7", "s8", "s9", "s10", "s11"];
+
+pub const E_COMPACT_T_REGS: [&str; 9] = ["a0", "a1", "a2", "a3", "a4", "a5", "t0", "t1", "t2"];
+
+/* Thumb-2 Register Classes (AAPCS)
+ *
+ * r0-r3 pass the first four arguments and, with r12, are caller-saved; r4-r11 are
+ * callee-saved. r12 (ip) is never allocated: thumb.rs uses it for immediates that do not
+ * encode. Spill reloads cannot go through argument registers, so they use r10 and r11,
+ * which functions that spill save like any other callee-saved register. */
+
+pub const THUMB_A_REGS: [&str; 4] = ["r0", "r1", "r2", "r3"];
+
+pub const THUMB_S_REGS: [&str; 8] = ["r4", "r5", "r6", "r7", "r8", "r9", "r10", "r11"];
+
+pub const THUMB_SCRATCH_REGS: [&str; 2] = ["r10", "r11"];
+
+#[derive(Debug, Clone, Copy)]
+pub struct RegisterClasses {
+    pub args: &'static [&'static str],
+    pub temporaries: &'static [&'static str],
+    pub saved: &'static [&'static str],
+    pub scratch: &'static [&'static str],      /* Taken from the temporaries, see SCRATCH_REGS */
+}
+
+pub const RV32I: RegisterClasses = RegisterClasses {
+    args: &A_REGS,
+    temporaries: &T_REGS,
+    saved: &S_REGS,
+    scratch: &SCRATCH_REGS,
+};
+
+pub const RV32E: RegisterClasses = RegisterClasses {
+    args: &E_A_REGS,
+    temporaries: &E_T_REGS,
+    saved: &E_S_REGS,
+    scratch: &E_SCRATCH_REGS,
+};
+
+pub const THUMB: RegisterClasses = RegisterClasses {
+    args: &THUMB_A_REGS,
+    temporaries: &THUMB_A_REGS,
+    saved: &THUMB_S_REGS,
+    scratch: &THUMB_SCRATCH_REGS,
+};
+
+pub const RV32I_COMPACT: RegisterClasses = RegisterClasses {
+    args: &A_REGS,
+    temporaries: &COMPACT_T_REGS,
+    saved: &COMPACT_S_REGS,
+    scratch: &SCRATCH_REGS,
+};
+
+pub const RV32E_COMPACT: RegisterClasses = RegisterClasses {
+    args: &E_A_REGS,
+    temporaries: &E_COMPACT_T_REGS,
+    saved: &E_S_REGS,
+    scratch: &E_SCRATCH_REGS,
+};
+
 #[derive(Debug, Clone)]
 pub struct LiveInterval {
     pub vreg: VirtualRegister,
@@ -26,19 +107,44 @@ pub struct LiveInterval {
 pub struct AllocationResult {
     pub allocation: Allocation,
     pub used_s_regs: Vec<String>,   /* Which S_REGs this function uses */
+    pub spilled: HashMap<VirtualRegister, usize>,   /* Spilled vreg -> stack slot */
+    pub scratch: Vec<String>,       /* Registers reserved for reloads, empty if nothing spilled */
 }
 
-pub fn allocate(cfg: &CFG) -> AllocationResult {
+/* Reloading spilled operands needs registers of their own. They are only held back from
+ * the T_REG pool in functions that spill, so other functions keep all of them. Spilled
+ * call arguments are reloaded straight into argument registers, so functions that spill
+ * do not allocate those either. */
+pub const SCRATCH_REGS: [&str; 2] = ["t5", "t6"];
+
+pub const E_SCRATCH_REGS: [&str; 2] = ["t1", "t2"];
+
+pub fn allocate(cfg: &CFG, registers: &RegisterClasses) -> AllocationResult {
     let liveness = liveness::analyse(cfg);
-    let (intervals, call_points) = build_intervals(cfg, &liveness);
-    linear_scan(intervals, &call_points)
+    let (intervals, uses) = build_intervals(cfg, &liveness);
+
+    let result = linear_scan(&intervals, &uses, registers.temporaries, registers.saved);
+    if result.spilled.is_empty() {
+        return result;
+    }
+
+    let pool: Vec<&str> = registers.temporaries.iter()
+        .copied()
+        .filter(|r| !registers.scratch.contains(r) && !registers.args.contains(r))
+        .collect();
+    let saved: Vec<&str> = registers.saved.iter().copied().filter(|r| !registers.scratch.contains(r)).collect();
+    let mut result = linear_scan(&intervals, &uses, &pool, &saved);
+    result.scratch = registers.scratch.iter().map(|s| s.to_string()).collect();
+    result
 }
 
+/* Intervals sorted by start, and the program points at which each vreg is used or defined */
 fn build_intervals(
     cfg: &CFG,
     liveness: &liveness::LivenessResult,
-) -> (Vec<LiveInterval>, HashSet<usize>) {
+) -> (Vec<LiveInterval>, HashMap<VirtualRegister, Vec<usize>>) {
     let mut intervals: HashMap<VirtualRegister, (usize, usize)> = HashMap::new();
+    let mut uses: HashMap<VirtualRegister, Vec<usize>> = HashMap::new();
     let mut call_points: HashSet<usize> = HashSet::new();
     let mut program_point = 0;
 
@@ -53,23 +159,16 @@ fn build_intervals(
             if matches!(&instr.operation, Op::Call(_)) {
                 call_points.insert(program_point);
             }
-            if let Some(dest) = instr.destination {
-                extend(&mut intervals, dest, program_point, program_point);
-            }
-            for arg in &instr.args {
-                extend(&mut intervals, *arg, program_point, program_point);
+            for vreg in instr.destination.iter().chain(&instr.args) {
+                extend(&mut intervals, *vreg, program_point, program_point);
+                uses.entry(*vreg).or_default().push(program_point);
             }
             program_point += 1;
         }
 
-        match &block.terminator {
-            Terminator::Branch { cond, .. } => {
-                extend(&mut intervals, *cond, program_point, program_point);
-            }
-            Terminator::Return(Some(reg)) => {
-                extend(&mut intervals, *reg, program_point, program_point);
-            }
-            _ => {}
+        for vreg in block.terminator.uses() {
+            extend(&mut intervals, vreg, program_point, program_point);
+            uses.entry(vreg).or_default().push(program_point);
         }
 
         if let Some(b) = liveness.get(&block.id) {
@@ -88,7 +187,7 @@ fn build_intervals(
         .collect();
 
     result.sort_by_key(|i| i.start);
-    (result, call_points)
+    (result, uses)
 }
 
 fn extend(
@@ -106,46 +205,71 @@ fn extend(
         .or_insert((point, point));
 }
 
-fn linear_scan(intervals: Vec<LiveInterval>, _call_points: &HashSet<usize>) -> AllocationResult {
+/* Linear scan over intervals sorted by start
+ *
+ * Intervals crossing a call need an S_REG, others prefer a T_REG and fall back to an
+ * S_REG. When the pool is exhausted, whichever of the current interval and the active
+ * intervals it could replace has the furthest next use is spilled to a stack slot
+ * for its whole lifetime, and insert_spill_code reloads it around each use. */
+fn linear_scan(
+    intervals: &[LiveInterval],
+    uses: &HashMap<VirtualRegister, Vec<usize>>,
+    t_regs: &[&str],
+    s_regs: &[&str],
+) -> AllocationResult {
     let mut allocation = Allocation::new();
+    let mut spilled: HashMap<VirtualRegister, usize> = HashMap::new();
 
-    let mut free_t: Vec<String> = T_REGS.iter().rev().map(|s| s.to_string()).collect();
-    let mut free_s: Vec<String> = S_REGS.iter().rev().map(|s| s.to_string()).collect();
+    let mut free_t: Vec<String> = t_regs.iter().rev().map(|s| s.to_string()).collect();
+    let mut free_s: Vec<String> = s_regs.iter().rev().map(|s| s.to_string()).collect();
     let mut active_t: Vec<(LiveInterval, String)> = Vec::new();
     let mut active_s: Vec<(LiveInterval, String)> = Vec::new();
     let mut used_s: HashSet<String> = HashSet::new();
 
-    for interval in intervals {
+    let next_use = |vreg: &VirtualRegister, point: usize| -> usize {
+        uses.get(vreg)
+            .and_then(|points| points.iter().copied().find(|&p| p >= point))
+            .unwrap_or(usize::MAX)
+    };
+
+    for interval in intervals.iter().cloned() {
         expire(&mut active_t, &mut free_t, interval.start);
         expire(&mut active_s, &mut free_s, interval.start);
 
-        if interval.crosses_call { /* Allocate to S_REG */
-            allocate_from(
-                interval,
-                &mut free_s,
-                &mut active_s,
-                &mut allocation,
-                &mut used_s,
-                S_REGS.len(),
-                /* fallback_pool */ S_REGS.as_slice(),
-                /* is_s */ true,
-            );
-
-        } else { /* Allocate to T_REG */
+        if !interval.crosses_call {
             if let Some(reg) = free_t.pop() {
                 allocation.insert(interval.vreg, reg.clone());
                 active_t.push((interval, reg));
+                continue;
+            }
+        }
 
-            } else if let Some(reg) = free_s.pop() { /* T_REGs exhausted, spill into S_REG. */
-                used_s.insert(reg.clone());
-                allocation.insert(interval.vreg, reg.clone());
-                active_s.push((interval, reg));
+        if let Some(reg) = free_s.pop() { /* Interval crosses a call, or T_REGs exhausted */
+            used_s.insert(reg.clone());
+            allocation.insert(interval.vreg, reg.clone());
+            active_s.push((interval, reg));
+            continue;
+        }
+
+        /* All usable registers taken, an interval crossing a call can only take an S_REG */
+        let candidates = active_s.iter()
+            .map(|(i, _)| (i.vreg, true))
+            .chain(active_t.iter().filter(|_| !interval.crosses_call).map(|(i, _)| (i.vreg, false)));
+        let victim = candidates.max_by_key(|(vreg, _)| next_use(vreg, interval.start));
 
-            } else { /* All T_REGs and S_REGs exhausted, TODO: implement proper stack spilling */
-                eprintln!("WARNING: register count exceeded, using modulo fallback");
-                let id = interval.vreg.id;
-                let reg = T_REGS[id % T_REGS.len()].to_string();
-                allocation.insert(interval.vreg, reg);
+        let slot = spilled.len();
+        match victim {
+            Some((vreg, is_s)) if next_use(&vreg, interval.start) > next_use(&interval.vreg, interval.start) => {
+                let active = if is_s { &mut active_s } else { &mut active_t };
+                let index = active.iter().position(|(i, _)| i.vreg == vreg).unwrap();
+                let (_, reg) = active.remove(index);
+                allocation.remove(&vreg);
+                spilled.insert(vreg, slot);
+                allocation.insert(interval.vreg, reg.clone());
+                active.push((interval, reg));
+            }
+            _ => {
+                spilled.insert(interval.vreg, slot);
             }
         }
     }
@@ -153,29 +277,73 @@ fn linear_scan(intervals: Vec<LiveInterval>, _call_points: &HashSet<usize>) -> A
     let mut used_s_regs: Vec<String> = used_s.into_iter().collect::<Vec<_>>();
     used_s_regs.sort();
 
-    AllocationResult { allocation, used_s_regs }
+    AllocationResult { allocation, used_s_regs, spilled, scratch: Vec::new() }
 }
 
-fn allocate_from(
-    interval: LiveInterval,
-    free: &mut Vec<String>,
-    active: &mut Vec<(LiveInterval, String)>,
-    allocation: &mut Allocation,
-    used_s: &mut HashSet<String>,
-    pool_len: usize,
-    fallback: &[&str],
-    is_s: bool,
-) {
-    if let Some(reg) = free.pop() {
-        if is_s { used_s.insert(reg.clone()); }
-        allocation.insert(interval.vreg, reg.clone());
-        active.push((interval, reg));
-    } else {
-        eprintln!("WARNING: out of {:?} registers, using modulo fallback", fallback);
-        let reg = fallback[interval.vreg.id % pool_len].to_string();
-        if is_s { used_s.insert(reg.clone()); }
-        allocation.insert(interval.vreg, reg);
+/* Rewrite spilled operands of the flattened function
+ *
+ *   add v3, v1, v2        lw t5, 4*slot(v1)(sp)
+ *   (v1, v3 spilled)  ->  add t5, t5, v2
+ *                         sw t5, 4*slot(v3)(sp)
+ *
+ * Operands are reloaded into the scratch registers, call arguments straight into their
+ * argument register. Stack-passed call arguments stay spilled, the generator copies
+ * them to the outgoing area. A spilled result is computed into the first scratch register. */
+pub fn insert_spill_code(
+    instructions: &[Instruction],
+    result: &mut AllocationResult,
+    registers: &RegisterClasses,
+) -> Vec<Instruction> {
+    if result.spilled.is_empty() {
+        return instructions.to_vec();
+    }
+
+    let mut next_id = instructions.iter()
+        .flat_map(|i| i.destination.iter().chain(&i.args))
+        .map(|v| v.id + 1)
+        .max()
+        .unwrap_or(0);
+    let mut fresh = |reg: &str, allocation: &mut Allocation| {
+        let vreg = VirtualRegister { id: next_id };
+        next_id += 1;
+        allocation.insert(vreg, reg.to_string());
+        vreg
+    };
+
+    let mut output = Vec::new();
+    for instr in instructions {
+        let mut instr = instr.clone();
+        let is_call = matches!(instr.operation, Op::Call(_) | Op::TailCall(_));
+        let mut scratch = result.scratch.iter();
+
+        for (i, arg) in instr.args.iter_mut().enumerate() {
+            let Some(&slot) = result.spilled.get(arg) else { continue };
+            if is_call && i >= registers.args.len() {
+                continue;   /* Copied slot to slot by the generator */
+            }
+            let reg = if is_call { registers.args[i].to_string() } else { scratch.next().unwrap().clone() };
+            let reload = fresh(&reg, &mut result.allocation);
+            output.push(Instruction::new(Op::LoadSlot(slot), Some(reload), vec![]));
+            *arg = reload;
+        }
+        if let Op::Ret(Some(val)) = &mut instr.operation {
+            *val = instr.args[0];
+        }
+
+        let store = match instr.destination {
+            Some(dest) => result.spilled.get(&dest).map(|&slot| {
+                let temp = fresh(&result.scratch[0], &mut result.allocation);
+                instr.destination = Some(temp);
+                Instruction::new(Op::StoreSlot(slot), None, vec![temp])
+            }),
+            None => None,
+        };
+
+        output.push(instr);
+        output.extend(store);
     }
+
+    output
 }
 
 fn expire(
@@ -193,4 +361,97 @@ fn expire(
         }
     });
     free.extend(freed);
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::cfg::{Terminator, CFG};
+
+    // v0..v19 = li i, call f, return the sum: twenty values live across a call, more than the S_REGs
+    fn live_across_call() -> CFG {
+        let mut cfg = CFG::new();
+        let entry = cfg.add_block();
+        let block = cfg.block_mut(entry);
+        let reg = |id| VirtualRegister { id };
+        for i in 0..20 {
+            block.instructions.push(Instruction::new(Op::LoadImm(i as i32), Some(reg(i)), vec![]));
+        }
+        block.instructions.push(Instruction::new(Op::Call("f".to_string()), None, vec![]));
+        let mut sum = reg(0);
+        for i in 1..20 {
+            block.instructions.push(Instruction::new(Op::Add, Some(reg(19 + i)), vec![sum, reg(i)]));
+            sum = reg(19 + i);
+        }
+        block.terminator = Terminator::Return(Some(sum));
+        cfg
+    }
+
+    // Runs the allocated code on physical registers, a call clobbering the T_REGs and A_REGs
+    fn run(instructions: &[Instruction], allocation: &Allocation) -> i32 {
+        let mut regs: HashMap<&str, i32> = HashMap::new();
+        let mut slots: HashMap<usize, i32> = HashMap::new();
+        let phys = |vreg: &VirtualRegister| allocation[vreg].as_str();
+        for instr in instructions {
+            let arg = |regs: &HashMap<&str, i32>, i: usize| regs[phys(&instr.args[i])];
+            match &instr.operation {
+                Op::LoadImm(val) => { regs.insert(phys(&instr.destination.unwrap()), *val); }
+                Op::Add => {
+                    let val = arg(&regs, 0).wrapping_add(arg(&regs, 1));
+                    regs.insert(phys(&instr.destination.unwrap()), val);
+                }
+                Op::LoadSlot(slot) => { regs.insert(phys(&instr.destination.unwrap()), slots[slot]); }
+                Op::StoreSlot(slot) => { slots.insert(*slot, arg(&regs, 0)); }
+                Op::Call(_) => regs.retain(|reg, _| !T_REGS.contains(reg) && !A_REGS.contains(reg)),
+                Op::Ret(_) => return arg(&regs, 0),
+                op => panic!("unexpected {:?}", op),
+            }
+        }
+        panic!("no return")
+    }
+
+    #[test]
+    fn spills_values_live_across_a_call() {
+        let cfg = live_across_call();
+        let mut result = allocate(&cfg, &RV32I);
+
+        // Only S_REGs survive the call, the values that do not fit each get a slot of their own
+        assert_eq!(result.spilled.len(), 20 - S_REGS.len());
+        let mut slots: Vec<usize> = result.spilled.values().copied().collect();
+        slots.sort();
+        assert_eq!(slots, (0..slots.len()).collect::<Vec<_>>());
+        for i in 0..20 {
+            let vreg = VirtualRegister { id: i };
+            match result.allocation.get(&vreg) {
+                Some(reg) => assert!(S_REGS.contains(&reg.as_str()) && !result.spilled.contains_key(&vreg)),
+                None => assert!(result.spilled.contains_key(&vreg)),
+            }
+        }
+        assert_eq!(result.scratch, SCRATCH_REGS);
+        assert!(result.allocation.values().all(|reg| !SCRATCH_REGS.contains(&reg.as_str())));
+
+        let instructions = insert_spill_code(&cfg.flatten("spill").unwrap(), &mut result, &RV32I);
+
+        // Spilled values are stored once where they are defined and reloaded into a scratch register for each use
+        let stores: Vec<usize> = instructions.iter()
+            .filter_map(|i| match i.operation { Op::StoreSlot(slot) => Some(slot), _ => None })
+            .collect();
+        assert_eq!(stores.len(), result.spilled.len());
+        let reloads = instructions.iter().filter(|i| matches!(i.operation, Op::LoadSlot(_))).count();
+        assert_eq!(reloads, result.spilled.len());
+        for (i, instr) in instructions.iter().enumerate() {
+            match instr.operation {
+                Op::StoreSlot(_) => assert_eq!(instructions[i - 1].destination, Some(instr.args[0])),
+                Op::LoadSlot(_) => {
+                    let reload = instr.destination.unwrap();
+                    assert!(SCRATCH_REGS.contains(&result.allocation[&reload].as_str()));
+                    assert!(instructions[i + 1..].iter().find(|i| !matches!(i.operation, Op::LoadSlot(_)))
+                        .is_some_and(|i| i.args.contains(&reload)));
+                }
+                _ => {}
+            }
+            assert!(instr.destination.iter().chain(&instr.args).all(|v| !result.spilled.contains_key(v)));
+        }
+
+        assert_eq!(run(&instructions, &result.allocation), (0..20).sum::<i32>());
+    }
+}
@@ -0,0 +1,137 @@
+use crate::ir::Op;
+use crate::ir::cfg::CFG;
+
+/* Multiply, divide and remainder for cores without the M extension
+
+  t2 = mul t0, t1        ->  t2 = call __mulsi3(t0, t1)
+  t2 = div t0, t1        ->  t2 = call __divsi3(t0, t1)
+  t2 = rem t0, t1        ->  t2 = call __modsi3(t0, t1)
+
+The routines take libgcc's names and calling convention, and are appended to the program
+once for each one it calls. They give the same results as the M instructions, including
+division by zero (-1, remainder the dividend) and overflow (i32::MIN / -1 is i32::MIN),
+and only use a0-a4 and t0-t2, so they also run on RV32E. */
+
+const ROUTINES: [(&str, &str); 3] = [
+    ("__mulsi3", MULSI3),
+    ("__divsi3", DIVSI3),
+    ("__modsi3", MODSI3),
+];
+
+// Replace mul, div and rem with calls, returning the routines called
+pub fn replace_muldiv(cfg: &mut CFG) -> Vec<&'static str> {
+    let mut called = Vec::new();
+    for instr in cfg.blocks.iter_mut().flat_map(|b| &mut b.instructions) {
+        let routine = match instr.operation {
+            Op::Mul => "__mulsi3",
+            Op::Div => "__divsi3",
+            Op::Rem => "__modsi3",
+            _ => continue,
+        };
+        instr.operation = Op::Call(routine.to_string());
+        if !called.contains(&routine) {
+            called.push(routine);
+        }
+    }
+    called
+}
+
+// Assembly for the routines called, in a fixed order
+pub fn routines(called: &[&str]) -> String {
+    let mut assembly = String::new();
+    for (name, body) in ROUTINES {
+        if called.contains(&name) {
+            assembly.push_str(body);
+        }
+    }
+    if called.iter().any(|name| *name != "__mulsi3") {
+        assembly.push_str(UDIVMOD);
+    }
+    assembly
+}
+
+// a0 * a1, one bit of a1 at a time
+const MULSI3: &str = "
+.section .text
+.global __mulsi3
+__mulsi3:
+    mv a2, a0
+    li a0, 0
+.L__mulsi3_loop:
+    andi a3, a1, 1
+    beqz a3, .L__mulsi3_skip
+    add a0, a0, a2
+.L__mulsi3_skip:
+    srli a1, a1, 1
+    slli a2, a2, 1
+    bnez a1, .L__mulsi3_loop
+    ret
+";
+
+// a0 / a1, rounding towards zero
+const DIVSI3: &str = "
+.section .text
+.global __divsi3
+__divsi3:
+    beqz a1, .L__divsi3_zero
+    xor a4, a0, a1
+    bgez a0, .L__divsi3_dividend
+    neg a0, a0
+.L__divsi3_dividend:
+    bgez a1, .L__divsi3_divisor
+    neg a1, a1
+.L__divsi3_divisor:
+    jal t0, .L__udivmod
+    bgez a4, .L__divsi3_done
+    neg a0, a0
+.L__divsi3_done:
+    ret
+.L__divsi3_zero:
+    li a0, -1
+    ret
+";
+
+// a0 % a1, with the sign of a0
+const MODSI3: &str = "
+.section .text
+.global __modsi3
+__modsi3:
+    mv a4, a0
+    bgez a0, .L__modsi3_dividend
+    neg a0, a0
+.L__modsi3_dividend:
+    bgez a1, .L__modsi3_divisor
+    neg a1, a1
+.L__modsi3_divisor:
+    jal t0, .L__udivmod
+    mv a0, a1
+    bgez a4, .L__modsi3_done
+    neg a0, a0
+.L__modsi3_done:
+    ret
+";
+
+/* Unsigned a0 / a1 into a0 and a0 % a1 into a1 by shifting and subtracting, returning
+ * through t0 so ra is left alone. Dividing by zero gives 0xffffffff and the dividend. */
+const UDIVMOD: &str = "
+.section .text
+.L__udivmod:
+    li a2, 0
+    li a3, 0
+    li t1, 32
+.L__udivmod_loop:
+    srli t2, a0, 31
+    slli a0, a0, 1
+    slli a3, a3, 1
+    or a3, a3, t2
+    slli a2, a2, 1
+    bltu a3, a1, .L__udivmod_skip
+    sub a3, a3, a1
+    ori a2, a2, 1
+.L__udivmod_skip:
+    addi t1, t1, -1
+    bnez t1, .L__udivmod_loop
+    mv a0, a2
+    mv a1, a3
+    jr t0
+";
@@ -0,0 +1,542 @@
+use crate::ir::{Instruction, Op, VirtualRegister};
+use crate::ir::cfg::{CmpOp, CFG};
+use crate::backend::generator::{order_moves, Move};
+use crate::backend::regalloc::{AllocationResult, THUMB_A_REGS};
+use crate::frontend::board::{Board, Exit};
+use std::fmt::Write;
+
+/* Thumb-2 code for ARMv7-M cores (Cortex-M3 and up), AAPCS calling convention
+
+  li t0, 0x40004000         ->  movw r0, #0x4000; movt r0, #0x4000
+  beqz t0, .L               ->  cmp r0, #0; beq .L
+  blt t0, t1, .L            ->  cmp r0, r1; blt .L
+  slt t2, t0, t1            ->  cmp r0, r1; ite lt; movlt r2, #1; movge r2, #0
+  sll t2, t0, t1            ->  and ip, r1, #31; lsl r2, r0, ip
+  div t2, t0, t1            ->  cmp r1, #0; ite eq; mvneq r2, #0; sdivne r2, r0, r1
+  rem t2, t0, t1            ->  div, mul and sub, before register allocation (expand_rem)
+
+Shift amounts are masked and division by zero gives -1, so programs compute the same
+values as on RISC-V. ip (r12) holds masked shift amounts and immediates that are not
+Thumb-2 modified immediates, and is never allocated.
+
+Stack frame layout (grows downward, 8-byte aligned at calls)
+
+  sp + adjust + 4 * pushed + 4 <- incoming argument 6  (caller's outgoing area)
+  sp + adjust + 4 * pushed     <- incoming argument 5
+  sp + adjust                  <- registers pushed by `push {r4, ..., lr}`
+  ...
+  sp + 4 * out + 4             <- spill slot 1
+  sp + 4 * out                 <- spill slot 0
+  ...
+  sp + 0                       <- outgoing argument 5
+
+pushed = used callee-saved registers, the spill scratch registers in functions that spill,
+and lr in functions that make calls; adjust makes 4 * pushed + adjust a multiple of 8.
+Functions return with `pop {..., pc}` when they pushed lr and `bx lr` otherwise. As on
+RISC-V, a frameless leaf returns wherever it returns, a tail call restores lr before
+leaving with `b`, and a function calling itself in tail position jumps past the prologue. */
+
+pub const PREAMBLE: &str = ".syntax unified\n.thumb\n\n";
+
+pub fn generate(
+    function: &str,
+    instructions: &[Instruction],
+    result: &AllocationResult,
+) -> Result<String, std::fmt::Error> {
+    let allocation = &result.allocation;
+    let a_regs = &THUMB_A_REGS;
+    let outgoing = instructions.iter()
+        .filter(|i| matches!(i.operation, Op::Call(_)))
+        .map(|i| i.args.len().saturating_sub(a_regs.len()))
+        .max()
+        .unwrap_or(0);
+    let saves_lr = instructions.iter().any(|i| matches!(i.operation, Op::Call(_)));
+    let recurses = instructions.iter().any(|i| matches!(&i.operation, Op::TailCall(t) if t == function));
+
+    // push and pop take registers in ascending order
+    let mut saved: Vec<&str> = result.used_s_regs.iter().map(String::as_str).collect();
+    if !result.spilled.is_empty() {
+        saved.extend(result.scratch.iter().map(String::as_str));
+    }
+    saved.sort_by_key(|reg| reg[1..].parse::<u32>().unwrap());
+    saved.dedup();
+    let pushed = 4 * (saved.len() + saves_lr as usize);
+    let slot_base = 4 * outgoing;
+    let adjust = (pushed + 4 * (result.spilled.len() + outgoing)).next_multiple_of(8) - pushed;
+    let has_frame = pushed > 0 || adjust > 0;
+
+    let epilogue = format!(".LBB_{}_ret", function);
+    let body = format!(".LBB_{}_body", function);
+    let mut jumps_to_epilogue = false;
+
+    let mut output = String::new();
+
+    writeln!(output, ".section .text")?;
+    writeln!(output, ".global {}", function)?;
+    writeln!(output, ".thumb_func")?;
+    writeln!(output, "{}:", function)?;
+    if pushed > 0 {
+        let lr = saves_lr.then_some("lr");
+        writeln!(output, "    push {{{}}}", saved.iter().copied().chain(lr).collect::<Vec<_>>().join(", "))?;
+    }
+    if adjust > 0 {
+        writeln!(output, "    sub sp, sp, #{}", adjust)?;
+    }
+    if recurses {
+        writeln!(output, "{}:", body)?;
+    }
+
+    for (index, instr) in instructions.iter().enumerate() {
+        match &instr.operation {
+            Op::LoadImm(val) => {
+                let rd = allocation.get(&instr.destination.unwrap()).unwrap();
+                write_imm(&mut output, rd, *val as u32)?;
+            }
+
+            Op::LoadAddr(addr) => {
+                let rd = allocation.get(&instr.destination.unwrap()).unwrap();
+                writeln!(output, "    movw {}, #0x{:04x}", rd, addr & 0xffff)?;
+                if addr >> 16 != 0 {
+                    writeln!(output, "    movt {}, #0x{:04x}", rd, addr >> 16)?;
+                }
+            }
+
+            Op::LoadWord(offset) => {
+                let rd = allocation.get(&instr.destination.unwrap()).unwrap();
+                let rs = allocation.get(&instr.args[0]).unwrap();
+                writeln!(output, "    ldr {}, [{}, #{}]", rd, rs, offset)?;
+            }
+
+            Op::StoreWord(offset) => {
+                let rs = allocation.get(&instr.args[0]).unwrap();
+                let rd = allocation.get(&instr.args[1]).unwrap();
+                writeln!(output, "    str {}, [{}, #{}]", rs, rd, offset)?;
+            }
+
+            Op::LoadDouble(_) | Op::StoreDouble(_) => {
+                unreachable!("u64 registers are rejected for 32-bit targets by check_widths")
+            }
+
+            Op::Mov => {
+                let rd = allocation.get(&instr.destination.unwrap()).unwrap();
+                let rs = allocation.get(&instr.args[0]).unwrap();
+                writeln!(output, "    mov {}, {}", rd, rs)?;
+            }
+
+            Op::MovArg(_) => {
+                if index > 0 && matches!(instructions[index - 1].operation, Op::MovArg(_)) {
+                    continue;   /* Moved with the first of its run */
+                }
+
+                let run: Vec<(&String, usize)> = instructions[index..].iter()
+                    .map_while(|i| match i.operation {
+                        Op::MovArg(arg) => Some((allocation.get(&i.destination.unwrap()).unwrap(), arg)),
+                        _ => None,
+                    })
+                    .collect();
+                let moves: Vec<(&str, &str)> = run.iter()
+                    .filter_map(|&(rd, arg)| a_regs.get(arg).map(|reg| (rd.as_str(), *reg)))
+                    .collect();
+                write_moves(&mut output, &moves)?;
+                for &(rd, arg) in run.iter().filter(|(_, arg)| *arg >= a_regs.len()) {
+                    writeln!(output, "    ldr {}, [sp, #{}]", rd, adjust + pushed + 4 * (arg - a_regs.len()))?;
+                }
+            }
+
+            Op::Call(target) => {
+                for (i, arg) in instr.args.iter().enumerate().skip(a_regs.len()) {
+                    let offset = 4 * (i - a_regs.len());
+                    match result.spilled.get(arg) {
+                        Some(slot) => {
+                            let scratch = &result.scratch[0];
+                            writeln!(output, "    ldr {}, [sp, #{}]", scratch, slot_base + 4 * slot)?;
+                            writeln!(output, "    str {}, [sp, #{}]", scratch, offset)?;
+                        }
+                        None => {
+                            let rs = allocation.get(arg).unwrap();
+                            writeln!(output, "    str {}, [sp, #{}]", rs, offset)?;
+                        }
+                    }
+                }
+
+                let moves: Vec<(&str, &str)> = instr.args.iter()
+                    .zip(a_regs)
+                    .map(|(arg, reg)| (*reg, allocation.get(arg).unwrap().as_str()))
+                    .collect();
+                write_moves(&mut output, &moves)?;
+
+                writeln!(output, "    bl {}", target)?;
+
+                if let Some(dest) = instr.destination {
+                    let rd = allocation.get(&dest).unwrap();
+                    if rd != "r0" {
+                        writeln!(output, "    mov {}, r0", rd)?;
+                    }
+                }
+            }
+
+            Op::TailCall(target) => {
+                let moves: Vec<(&str, &str)> = instr.args.iter()
+                    .zip(a_regs)
+                    .map(|(arg, reg)| (*reg, allocation.get(arg).unwrap().as_str()))
+                    .collect();
+                write_moves(&mut output, &moves)?;
+
+                if target == function {
+                    writeln!(output, "    b {}\n", body)?;
+                } else {
+                    write_teardown(&mut output, &saved, saves_lr, adjust, "lr")?;
+                    writeln!(output, "    b {}\n", target)?;
+                }
+            }
+
+            Op::Ret(val) => {
+                if let Some(reg) = val {
+                    let rs = allocation.get(reg).unwrap();
+                    if rs != "r0" {
+                        writeln!(output, "    mov r0, {}", rs)?;
+                    }
+                }
+
+                if !has_frame {
+                    writeln!(output, "    bx lr\n")?;
+                } else if index + 1 != instructions.len() {
+                    writeln!(output, "    b {}", epilogue)?;
+                    jumps_to_epilogue = true;
+                }
+            }
+
+            Op::Label(label) => {
+                writeln!(output, "{}:", label)?;
+            }
+
+            Op::Jump(target) => {
+                writeln!(output, "    b {}", target)?;
+            }
+
+            Op::BranchIfFalse(target) => {
+                let cond_reg = allocation.get(&instr.args[0]).unwrap();
+                writeln!(output, "    cmp {}, #0", cond_reg)?;
+                writeln!(output, "    beq {}", target)?;
+            }
+
+            Op::BranchIfTrue(target) => {
+                let cond_reg = allocation.get(&instr.args[0]).unwrap();
+                writeln!(output, "    cmp {}, #0", cond_reg)?;
+                writeln!(output, "    bne {}", target)?;
+            }
+
+            Op::BranchCond(op, label) => {
+                let lhs = allocation.get(&instr.args[0]).unwrap();
+                let rhs = allocation.get(&instr.args[1]).unwrap();
+                writeln!(output, "    cmp {}, {}", lhs, rhs)?;
+                match op {
+                    CmpOp::Eq => writeln!(output, "    bne {}", label)?,
+                    CmpOp::Ne => writeln!(output, "    beq {}", label)?,
+                    CmpOp::Lt => writeln!(output, "    bge {}", label)?,
+                    CmpOp::Ge => writeln!(output, "    blt {}", label)?,
+                    CmpOp::Le => writeln!(output, "    bgt {}", label)?,
+                    CmpOp::Gt => writeln!(output, "    ble {}", label)?,
+                }
+            }
+
+            Op::Add | Op::Sub | Op::Mul | Op::And | Op::Or | Op::Xor => {
+                let rd = allocation.get(&instr.destination.unwrap()).unwrap();
+                let rs1 = allocation.get(&instr.args[0]).unwrap();
+                let rs2 = allocation.get(&instr.args[1]).unwrap();
+                let mnemonic = match instr.operation {
+                    Op::Add => "add",
+                    Op::Sub => "sub",
+                    Op::Mul => "mul",
+                    Op::And => "and",
+                    Op::Or => "orr",
+                    _ => "eor",
+                };
+                writeln!(output, "    {} {}, {}, {}", mnemonic, rd, rs1, rs2)?;
+            }
+
+            Op::Div => {
+                let rd = allocation.get(&instr.destination.unwrap()).unwrap();
+                let rs1 = allocation.get(&instr.args[0]).unwrap();
+                let rs2 = allocation.get(&instr.args[1]).unwrap();
+                writeln!(output, "    cmp {}, #0", rs2)?;
+                writeln!(output, "    ite eq")?;
+                writeln!(output, "    mvneq {}, #0", rd)?;
+                writeln!(output, "    sdivne {}, {}, {}", rd, rs1, rs2)?;
+            }
+
+            Op::Rem => {
+                unreachable!("rem is expanded by thumb::expand_rem before instruction selection")
+            }
+
+            Op::Sll | Op::Srl => {
+                let rd = allocation.get(&instr.destination.unwrap()).unwrap();
+                let rs1 = allocation.get(&instr.args[0]).unwrap();
+                let rs2 = allocation.get(&instr.args[1]).unwrap();
+                let mnemonic = if matches!(instr.operation, Op::Sll) { "lsl" } else { "lsr" };
+                writeln!(output, "    and ip, {}, #31", rs2)?;
+                writeln!(output, "    {} {}, {}, ip", mnemonic, rd, rs1)?;
+            }
+
+            Op::Slt | Op::Sltu => {
+                let rd = allocation.get(&instr.destination.unwrap()).unwrap();
+                let rs1 = allocation.get(&instr.args[0]).unwrap();
+                let rs2 = allocation.get(&instr.args[1]).unwrap();
+                writeln!(output, "    cmp {}, {}", rs1, rs2)?;
+                write_set(&mut output, rd, matches!(instr.operation, Op::Sltu))?;
+            }
+
+            Op::Neg => {
+                let rd = allocation.get(&instr.destination.unwrap()).unwrap();
+                let rs = allocation.get(&instr.args[0]).unwrap();
+                writeln!(output, "    rsb {}, {}, #0", rd, rs)?;
+            }
+
+            Op::Not => {
+                let rd = allocation.get(&instr.destination.unwrap()).unwrap();
+                let rs = allocation.get(&instr.args[0]).unwrap();
+                writeln!(output, "    mvn {}, {}", rd, rs)?;
+            }
+
+            Op::AddImm(imm) => {
+                let rd = allocation.get(&instr.destination.unwrap()).unwrap();
+                let rs = allocation.get(&instr.args[0]).unwrap();
+                if *imm < 0 {
+                    writeln!(output, "    subw {}, {}, #{}", rd, rs, -imm)?;
+                } else {
+                    writeln!(output, "    addw {}, {}, #{}", rd, rs, imm)?;
+                }
+            }
+
+            Op::AndImm(imm) | Op::OrImm(imm) | Op::XorImm(imm) => {
+                let rd = allocation.get(&instr.destination.unwrap()).unwrap();
+                let rs = allocation.get(&instr.args[0]).unwrap();
+                let (mnemonic, inverted) = match instr.operation {
+                    Op::AndImm(_) => ("and", Some("bic")),
+                    Op::OrImm(_) => ("orr", Some("orn")),
+                    _ => ("eor", None),
+                };
+                let value = *imm as u32;
+                match inverted {
+                    _ if encodable(value) => writeln!(output, "    {} {}, {}, #{}", mnemonic, rd, rs, imm)?,
+                    Some(inverted) if encodable(!value) => {
+                        writeln!(output, "    {} {}, {}, #{}", inverted, rd, rs, !value)?
+                    }
+                    _ => {
+                        write_imm(&mut output, "ip", value)?;
+                        writeln!(output, "    {} {}, {}, ip", mnemonic, rd, rs)?;
+                    }
+                }
+            }
+
+            Op::SllImm(imm) | Op::SrlImm(imm) => {
+                let rd = allocation.get(&instr.destination.unwrap()).unwrap();
+                let rs = allocation.get(&instr.args[0]).unwrap();
+                let mnemonic = if matches!(instr.operation, Op::SllImm(_)) { "lsl" } else { "lsr" };
+                if *imm == 0 {
+                    writeln!(output, "    mov {}, {}", rd, rs)?;     /* lsr #0 would shift by 32 */
+                } else {
+                    writeln!(output, "    {} {}, {}, #{}", mnemonic, rd, rs, imm)?;
+                }
+            }
+
+            Op::SltImm(imm) | Op::SltuImm(imm) => {
+                let rd = allocation.get(&instr.destination.unwrap()).unwrap();
+                let rs = allocation.get(&instr.args[0]).unwrap();
+                let value = *imm as u32;
+                if encodable(value) {
+                    writeln!(output, "    cmp {}, #{}", rs, imm)?;
+                } else if encodable(value.wrapping_neg()) {
+                    writeln!(output, "    cmn {}, #{}", rs, -imm)?;
+                } else {
+                    write_imm(&mut output, "ip", value)?;
+                    writeln!(output, "    cmp {}, ip", rs)?;
+                }
+                write_set(&mut output, rd, matches!(instr.operation, Op::SltuImm(_)))?;
+            }
+
+            Op::LoadSlot(slot) => {
+                let rd = allocation.get(&instr.destination.unwrap()).unwrap();
+                writeln!(output, "    ldr {}, [sp, #{}]", rd, slot_base + 4 * slot)?;
+            }
+
+            Op::StoreSlot(slot) => {
+                let rs = allocation.get(&instr.args[0]).unwrap();
+                writeln!(output, "    str {}, [sp, #{}]", rs, slot_base + 4 * slot)?;
+            }
+
+            Op::Phi(_) => {
+                unreachable!("phis are removed by ssa::destruct before register allocation")
+            }
+        }
+    }
+
+    let returns = instructions.iter().any(|i| matches!(i.operation, Op::Ret(_)));
+    if has_frame && returns {
+        if jumps_to_epilogue {
+            writeln!(output, "{}:", epilogue)?;
+        }
+        write_teardown(&mut output, &saved, saves_lr, adjust, "pc")?;
+        if !saves_lr {
+            writeln!(output, "    bx lr")?;
+        }
+        writeln!(output)?;
+    }
+
+    Ok(output)
+}
+
+/* Thumb-2 has no remainder instruction, so before instruction selection
+ *
+ *   t2 = rem t0, t1    ->  t3 = div t0, t1; t4 = mul t3, t1; t2 = sub t0, t4
+ *
+ * which keeps RISC-V's results, including the dividend for a zero divisor. */
+pub fn expand_rem(cfg: &mut CFG) {
+    let mut next = cfg.next_register();
+    for block in &mut cfg.blocks {
+        let mut expanded = Vec::with_capacity(block.instructions.len());
+        for instr in block.instructions.drain(..) {
+            if !matches!(instr.operation, Op::Rem) {
+                expanded.push(instr);
+                continue;
+            }
+            let (dividend, divisor) = (instr.args[0], instr.args[1]);
+            let quotient = VirtualRegister { id: next };
+            let product = VirtualRegister { id: next + 1 };
+            next += 2;
+            expanded.push(Instruction::new(Op::Div, Some(quotient), vec![dividend, divisor]));
+            expanded.push(Instruction::new(Op::Mul, Some(product), vec![quotient, divisor]));
+            expanded.push(Instruction::new(Op::Sub, instr.destination, vec![dividend, product]));
+        }
+        block.instructions = expanded;
+    }
+}
+
+// Whether a value is a Thumb-2 modified immediate: a byte, a repeated byte pattern or a rotated byte
+fn encodable(value: u32) -> bool {
+    let [b0, b1, b2, b3] = value.to_le_bytes();
+    value <= 0xff
+        || (b1 == 0 && b3 == 0 && b0 == b2)
+        || (b0 == 0 && b2 == 0 && b1 == b3)
+        || (b0 == b1 && b1 == b2 && b2 == b3)
+        || (8..32).any(|rotation| (0x80..=0xff).contains(&value.rotate_left(rotation)))
+}
+
+fn write_imm(output: &mut String, rd: &str, value: u32) -> std::fmt::Result {
+    if value <= 0xffff {
+        return writeln!(output, "    movw {}, #{}", rd, value);
+    }
+    writeln!(output, "    movw {}, #0x{:04x}", rd, value & 0xffff)?;
+    writeln!(output, "    movt {}, #0x{:04x}", rd, value >> 16)
+}
+
+// rd = 1 if the last comparison was less than, signed or unsigned, 0 otherwise
+fn write_set(output: &mut String, rd: &str, unsigned: bool) -> std::fmt::Result {
+    let (less, not_less) = if unsigned { ("lo", "hs") } else { ("lt", "ge") };
+    writeln!(output, "    ite {}", less)?;
+    writeln!(output, "    mov{} {}, #1", less, rd)?;
+    writeln!(output, "    mov{} {}, #0", not_less, rd)
+}
+
+fn write_moves(output: &mut String, moves: &[(&str, &str)]) -> std::fmt::Result {
+    for step in order_moves(moves) {
+        match step {
+            Move::Copy(rd, rs) => writeln!(output, "    mov {}, {}", rd, rs)?,
+            Move::Swap(rd, rs) => {
+                writeln!(output, "    eor {}, {}, {}", rd, rd, rs)?;
+                writeln!(output, "    eor {}, {}, {}", rs, rd, rs)?;
+                writeln!(output, "    eor {}, {}, {}", rd, rd, rs)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// Release the frame and pop the saved registers, with the return address into `link`
+fn write_teardown(output: &mut String, saved: &[&str], saves_lr: bool, adjust: usize, link: &str) -> std::fmt::Result {
+    if adjust > 0 {
+        writeln!(output, "    add sp, sp, #{}", adjust)?;
+    }
+    let link = saves_lr.then_some(link);
+    let popped: Vec<&str> = saved.iter().copied().chain(link).collect();
+    if !popped.is_empty() {
+        writeln!(output, "    pop {{{}}}", popped.join(", "))?;
+    }
+    Ok(())
+}
+
+/* Startup for Cortex-M boards: the core loads sp and the reset handler from the first two
+ * words of the vector table at address 0, so there is no code to set up the stack. The
+ * other system exceptions spin in Default_Handler; device interrupts are left out. */
+pub fn startup(board: &Board) -> Result<String, std::fmt::Error> {
+    let mut output = String::new();
+
+    writeln!(output, "/* Startup for board '{}' (Cortex-M), generated by peric from its board description", board.name)?;
+    writeln!(output, " *")?;
+    writeln!(output, " *   1. The vector table gives the initial stack pointer and reset handler")?;
+    writeln!(output, " *   2. Clears .bss")?;
+    writeln!(output, " *   3. Calls main()")?;
+    match board.exit {
+        Exit::Loop => writeln!(output, " *   4. Spins once main() returns")?,
+        Exit::Finisher(_) => writeln!(output, " *   4. Exits through the test finisher with main()'s return value")?,
+    }
+    writeln!(output, " */")?;
+    writeln!(output)?;
+    output.push_str(PREAMBLE);
+    writeln!(output, ".section .text.start")?;
+    writeln!(output, ".global _start")?;
+    writeln!(output)?;
+    writeln!(output, "vectors:")?;
+    writeln!(output, "    .word 0x{:08x}          /* Initial stack pointer */", board.stack_top)?;
+    writeln!(output, "    .word _start              /* Reset */")?;
+    writeln!(output, "    .word Default_Handler     /* NMI */")?;
+    writeln!(output, "    .word Default_Handler     /* HardFault */")?;
+    writeln!(output, "    .word Default_Handler     /* MemManage */")?;
+    writeln!(output, "    .word Default_Handler     /* BusFault */")?;
+    writeln!(output, "    .word Default_Handler     /* UsageFault */")?;
+    writeln!(output, "    .word 0, 0, 0, 0          /* Reserved */")?;
+    writeln!(output, "    .word Default_Handler     /* SVCall */")?;
+    writeln!(output, "    .word Default_Handler     /* DebugMonitor */")?;
+    writeln!(output, "    .word 0                   /* Reserved */")?;
+    writeln!(output, "    .word Default_Handler     /* PendSV */")?;
+    writeln!(output, "    .word Default_Handler     /* SysTick */")?;
+    writeln!(output)?;
+    writeln!(output, ".thumb_func")?;
+    writeln!(output, "_start:")?;
+    writeln!(output, "    ldr r0, =__bss_start")?;
+    writeln!(output, "    ldr r1, =__bss_end")?;
+    writeln!(output, "    movs r2, #0")?;
+    writeln!(output, ".Lclear_bss:")?;
+    writeln!(output, "    cmp r0, r1")?;
+    writeln!(output, "    bhs .Lmain")?;
+    writeln!(output, "    str r2, [r0], #4")?;
+    writeln!(output, "    b .Lclear_bss")?;
+    writeln!(output)?;
+    writeln!(output, ".Lmain:")?;
+    writeln!(output, "    bl main")?;
+    writeln!(output)?;
+
+    if let Exit::Finisher(address) = board.exit {
+        writeln!(output, "    /* 0x5555 passes, exit_code << 16 | 0x3333 fails with exit_code */")?;
+        writeln!(output, "    ldr r1, =0x{:08x}", address)?;
+        writeln!(output, "    movw r2, #0x5555")?;
+        writeln!(output, "    cbz r0, .Lexit")?;
+        writeln!(output, "    lsls r0, r0, #16")?;
+        writeln!(output, "    movw r2, #0x3333")?;
+        writeln!(output, "    orrs r2, r0, r2")?;
+        writeln!(output, ".Lexit:")?;
+        writeln!(output, "    str r2, [r1]")?;
+        writeln!(output)?;
+    }
+
+    writeln!(output, ".Lhang:")?;
+    writeln!(output, "    b .Lhang")?;
+    writeln!(output)?;
+    writeln!(output, ".thumb_func")?;
+    writeln!(output, "Default_Handler:")?;
+    writeln!(output, "    b Default_Handler")?;
+    writeln!(output)?;
+    writeln!(output, ".ltorg")?;
+
+    Ok(output)
+}
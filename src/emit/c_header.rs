@@ -0,0 +1,132 @@
+use crate::analysis::reachability::StateGraph;
+use crate::analysis::typestate::fmt_typestate_set;
+use crate::frontend::ast::{self, TypeStateSet};
+use std::fmt::Write;
+
+/* C11 header for calling peri-compiled drivers with typestate-checked handles
+ *
+ *   typedef struct { int _unused; } Timer_Disabled;         one per reachable state set
+ *   static inline Timer_Disabled* get_timer(void);          initial state constructor
+ *   int32_t enable_timer(void);                             real driver symbol
+ *   #define enable_timer(h) _Generic((h), Timer_Disabled*: ...)
+ *
+ * Handles carry no data, so drivers keep their peri calling convention and the macro
+ * of the same name only checks and converts the handle. A macro does not expand
+ * recursively, so the call inside it reaches the real symbol. _Generic has one
+ * association per transition in the reachable state graph, which covers multiple
+ * input alternatives and every instantiation of a parametric driver; passing a
+ * handle in any other state has no matching association and fails to compile. */
+
+const HANDLE: &str = "peri_h";
+
+pub fn state_type(peripheral: &str, set: &TypeStateSet) -> String {
+    if set.is_empty() {
+        format!("{}_Empty", peripheral)
+    } else {
+        format!("{}_{}", peripheral, set.iter().cloned().collect::<Vec<_>>().join("_"))
+    }
+}
+
+fn c_type(ty: &ast::Type) -> &'static str {
+    match ty {
+        ast::Type::I32 => "int32_t",
+        ast::Type::U8  => "uint8_t",
+        ast::Type::U16 => "uint16_t",
+        ast::Type::U32 => "uint32_t",
+    }
+}
+
+pub fn prototype(func: &ast::Function) -> String {
+    let params = if func.args.is_empty() {
+        "void".to_string()
+    } else {
+        func.args.iter()
+            .map(|a| format!("{} {}", c_type(&a.ty), a.name))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    format!("int32_t {}({})", func.name, params)
+}
+
+pub fn generate(program: &ast::Program, graphs: &[StateGraph], guard: &str) -> Result<String, std::fmt::Error> {
+    let mut output = String::new();
+
+    writeln!(output, "/* Generated by peric, do not edit */")?;
+    writeln!(output, "#ifndef {}", guard)?;
+    writeln!(output, "#define {}", guard)?;
+    writeln!(output)?;
+    writeln!(output, "#include <stdint.h>")?;
+    writeln!(output)?;
+
+    for graph in graphs {
+        writeln!(output, "/* {} */", graph.peripheral)?;
+        for set in &graph.states {
+            writeln!(output, "typedef struct {{ int _unused; }} {};", state_type(&graph.peripheral, set))?;
+        }
+        for state in &graph.unreachable {
+            writeln!(output, "/* {} is unreachable from the initial state */", state)?;
+        }
+        writeln!(output)?;
+
+        let initial = state_type(&graph.peripheral, &graph.states[0]);
+        writeln!(output, "static inline {}* get_{}(void) {{", initial, graph.peripheral.to_lowercase())?;
+        writeln!(output, "    static {} handle;", initial)?;
+        writeln!(output, "    return &handle;")?;
+        writeln!(output, "}}")?;
+        writeln!(output)?;
+    }
+
+    // Real symbols must be declared before the macros that shadow them
+    writeln!(output, "/* Driver symbols */")?;
+    for func in program.functions.iter().filter(|f| f.name != "main") {
+        writeln!(output, "{};", prototype(func))?;
+    }
+    writeln!(output)?;
+
+    for func in &program.functions {
+        let Some(sig) = &func.signature else { continue };
+        let Some(graph) = graphs.iter().find(|g| g.peripheral == sig.peripheral) else { continue };
+
+        let transitions: Vec<_> = graph.transitions.iter()
+            .filter(|t| t.driver == func.name)
+            .collect();
+
+        writeln!(
+            output,
+            "/* fn {}({}) :: {}<{}> -> {}<{}> */",
+            func.name,
+            func.args.iter().map(|a| a.name.as_str()).collect::<Vec<_>>().join(", "),
+            sig.peripheral,
+            sig.input_states.iter().map(fmt_typestate_set).collect::<Vec<_>>().join(" | "),
+            sig.peripheral,
+            fmt_typestate_set(&sig.output_state),
+        )?;
+
+        if transitions.is_empty() {
+            writeln!(output, "/* No reachable input state, {} has no typed wrapper */", func.name)?;
+            writeln!(output)?;
+            continue;
+        }
+
+        let args: Vec<&str> = func.args.iter().map(|a| a.name.as_str()).collect();
+        let mut params = vec![HANDLE];
+        params.extend(&args);
+
+        writeln!(output, "#define {}({}) _Generic(({}), \\", func.name, params.join(", "), HANDLE)?;
+        for (i, t) in transitions.iter().enumerate() {
+            let from = state_type(&graph.peripheral, &graph.states[t.from]);
+            let to = state_type(&graph.peripheral, &graph.states[t.to]);
+            let sep = if i + 1 == transitions.len() { ")" } else { ", \\" };
+            writeln!(
+                output,
+                "    {}*: ((void){}({}), ({}*)(void*)({})){}",
+                from, func.name, args.join(", "), to, HANDLE, sep,
+            )?;
+        }
+        writeln!(output)?;
+    }
+
+    writeln!(output, "#endif /* {} */", guard)?;
+
+    Ok(output)
+}
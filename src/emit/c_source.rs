@@ -0,0 +1,479 @@
+use crate::emit::c_header;
+use crate::frontend::ast;
+use crate::ir::{self, Op, VirtualRegister};
+use crate::ir::cfg::{BlockId, CmpOp, Terminator, CFG};
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+
+/* Portable C99 translation of the typestate-checked program, for architectures without a backend
+ *
+ *   x1 = li 0x10000000
+ *   x2 = lw 5(x1)                       ->  ready = *(volatile uint8_t *)0x10000005u;  /* UART.LSR */
+ *   x3 = li 32                              if ((int32_t)ready < 32) goto bb2;
+ *   blt x2, x3, 2, 3
+ *
+ * Each function is translated from its CFG after SSA destruction, in the block order
+ * CFG::flatten uses: registers become uint32_t locals named after the variables they
+ * hold, blocks become labels and terminators gotos, and values nothing reads are left
+ * out. Constants, addresses and arguments defined once are written where they are used.
+ * Peripheral accesses go through volatile pointers of the declared register width, found
+ * from the address the access reaches. Operations keep their RV32IM results: arithmetic
+ * wraps, shift amounts are masked to five bits and division by zero gives -1 (peri_div
+ * and peri_rem). A function accessing u64 registers computes in 64 bits with uint64_t
+ * locals, as it would on RV64. Functions have the prototypes of the c-header output, so
+ * the header can call them. */
+
+// Address -> (register width, PERIPHERAL.REGISTER), banked registers named together
+fn register_map(program: &ast::Program) -> HashMap<u32, (&ast::RegisterType, String)> {
+    let mut registers: HashMap<u32, (&ast::RegisterType, String)> = HashMap::new();
+    for p in &program.peripherals {
+        let Some(base) = p.base_address else { continue };
+        for block in &p.register_blocks {
+            for reg in &block.registers {
+                let name = format!("{}.{}", p.name, reg.name);
+                registers.entry(base + reg.offset)
+                    .and_modify(|(_, names)| *names = format!("{} / {}", names, name))
+                    .or_insert((&block.reg_type, name));
+            }
+        }
+    }
+    registers
+}
+
+fn width_type(reg_type: &ast::RegisterType) -> &'static str {
+    match reg_type {
+        ast::RegisterType::U8 => "uint8_t",
+        ast::RegisterType::U16 => "uint16_t",
+        ast::RegisterType::U32 => "uint32_t",
+        ast::RegisterType::U64 => "uint64_t",
+    }
+}
+
+const PERI_DIV: &str = "\
+/* RV32IM division: -1 for a zero divisor, the dividend for INT32_MIN / -1 */
+static uint32_t peri_div(uint32_t a, uint32_t b) {
+    if (b == 0) return UINT32_MAX;
+    if (a == 0x80000000u && b == UINT32_MAX) return a;
+    return (uint32_t)((int32_t)a / (int32_t)b);
+}
+
+";
+
+const PERI_REM: &str = "\
+/* RV32IM remainder: the dividend for a zero divisor, 0 for INT32_MIN % -1 */
+static uint32_t peri_rem(uint32_t a, uint32_t b) {
+    if (b == 0) return a;
+    if (a == 0x80000000u && b == UINT32_MAX) return 0;
+    return (uint32_t)((int32_t)a % (int32_t)b);
+}
+
+";
+
+const PERI_DIV64: &str = "\
+/* RV64IM division: -1 for a zero divisor, the dividend for INT64_MIN / -1 */
+static uint64_t peri_div64(uint64_t a, uint64_t b) {
+    if (b == 0) return UINT64_MAX;
+    if (a == 0x8000000000000000u && b == UINT64_MAX) return a;
+    return (uint64_t)((int64_t)a / (int64_t)b);
+}
+
+";
+
+const PERI_REM64: &str = "\
+/* RV64IM remainder: the dividend for a zero divisor, 0 for INT64_MIN % -1 */
+static uint64_t peri_rem64(uint64_t a, uint64_t b) {
+    if (b == 0) return a;
+    if (a == 0x8000000000000000u && b == UINT64_MAX) return 0;
+    return (uint64_t)((int64_t)a % (int64_t)b);
+}
+
+";
+
+// Names locals may not take: C keywords, the types and helpers used, and the functions
+const RESERVED: [&str; 45] = [
+    "auto", "break", "case", "char", "const", "continue", "default", "do", "double", "else", "enum",
+    "extern", "float", "for", "goto", "if", "inline", "int", "long", "register", "restrict", "return",
+    "short", "signed", "sizeof", "static", "struct", "switch", "typedef", "union", "unsigned", "void",
+    "volatile", "while", "uint8_t", "uint16_t", "uint32_t", "uint64_t", "int32_t", "int64_t",
+    "uintptr_t", "peri_div", "peri_rem", "peri_div64", "peri_rem64",
+];
+
+pub fn generate(program: &ast::Program, functions: &[(String, CFG)]) -> Result<String, String> {
+    let registers = register_map(program);
+
+    let mut translations = Vec::new();
+    for (name, cfg) in functions {
+        let func = program.functions.iter()
+            .find(|f| &f.name == name)
+            .ok_or_else(|| format!("internal error: '{}' has no declaration", name))?;
+        let mut cfg = cfg.clone();
+        ir::ssa::destruct(&mut cfg);
+        let (cfg, order) = ir::layout::arrange(&cfg, name)?;
+        translations.push(Translation::new(program, func, cfg, order));
+    }
+
+    // Only the helpers the program calls, unused static functions are warnings
+    let uses = |op: fn(&Op) -> bool, wide: bool| translations.iter().filter(|t| t.wide == wide).any(|t| {
+        t.cfg.blocks.iter()
+            .flat_map(|b| &b.instructions)
+            .filter(|i| op(&i.operation))
+            .filter_map(|i| i.destination)
+            .any(|dest| t.used.contains(&dest))
+    });
+    let helpers = [
+        (uses(|op| matches!(op, Op::Div), false), PERI_DIV),
+        (uses(|op| matches!(op, Op::Rem), false), PERI_REM),
+        (uses(|op| matches!(op, Op::Div), true), PERI_DIV64),
+        (uses(|op| matches!(op, Op::Rem), true), PERI_REM64),
+    ];
+
+    let mut output = String::new();
+    write_header(&mut output, program, &helpers).map_err(|e| e.to_string())?;
+    for translation in &translations {
+        translation.write(&mut output, &registers).map_err(|e| e.to_string())?;
+    }
+
+    Ok(output)
+}
+
+// Driver prototypes as in the c-header output, main is defined as C's entry point
+fn write_header(output: &mut String, program: &ast::Program, helpers: &[(bool, &str)]) -> std::fmt::Result {
+    writeln!(output, "/* Generated by peric, do not edit */")?;
+    writeln!(output, "#include <stdint.h>")?;
+    writeln!(output)?;
+    for (_, helper) in helpers.iter().filter(|(used, _)| *used) {
+        output.push_str(helper);
+    }
+    for func in program.functions.iter().filter(|f| f.name != "main") {
+        writeln!(output, "{};", c_header::prototype(func))?;
+    }
+    writeln!(output)
+}
+
+// How a register is written in C
+#[derive(Debug, Clone)]
+enum Value {
+    Local(String),
+    Constant(i32),      /* li defining the register once */
+    Address(u32),       /* la defining the register once */
+    Argument(String),   /* The parameter a register defined once by MovArg copies, as uint32_t */
+}
+
+struct Translation<'a> {
+    func: &'a ast::Function,
+    cfg: CFG,
+    order: Vec<BlockId>,
+    used: HashSet<VirtualRegister>,             // Registers something reads
+    wide: bool,                                 // Computes in 64 bits
+    values: HashMap<VirtualRegister, Value>,
+}
+
+impl<'a> Translation<'a> {
+    fn new(program: &ast::Program, func: &'a ast::Function, cfg: CFG, order: Vec<BlockId>) -> Self {
+        let mut counts: HashMap<VirtualRegister, usize> = HashMap::new();
+        for dest in cfg.blocks.iter().flat_map(|b| &b.instructions).filter_map(|i| i.destination) {
+            *counts.entry(dest).or_default() += 1;
+        }
+
+        // Values nothing reads are left out, calls and loads still happen
+        let used: HashSet<VirtualRegister> = cfg.blocks.iter()
+            .flat_map(|b| b.instructions.iter().flat_map(|i| i.args.iter().copied()).chain(b.terminator.uses()))
+            .collect();
+
+        let mut values = HashMap::new();
+        for instr in cfg.blocks.iter().flat_map(|b| &b.instructions) {
+            let Some(dest) = instr.destination.filter(|d| counts[d] == 1) else { continue };
+            let value = match instr.operation {
+                Op::LoadImm(val) => Value::Constant(val),
+                Op::LoadAddr(address) => Value::Address(address),
+                Op::MovArg(index) => Value::Argument(argument(&func.args[index])),
+                _ => continue,
+            };
+            values.insert(dest, value);
+        }
+
+        // Locals are named after their variables, made unique, then the rest v<id>
+        let mut taken: HashSet<String> = RESERVED.iter().map(|s| s.to_string())
+            .chain(program.functions.iter().map(|f| f.name.clone()))
+            .chain(func.args.iter().map(|a| a.name.clone()))
+            .collect();
+        let mut locals: Vec<VirtualRegister> = used.iter()
+            .filter(|reg| !values.contains_key(reg))
+            .copied()
+            .collect();
+        locals.sort_by_key(|reg| (!cfg.names.contains_key(reg), reg.id));
+        for reg in locals {
+            let base = cfg.names.get(&reg).cloned().unwrap_or_else(|| format!("v{}", reg.id));
+            let name = std::iter::once(base.clone())
+                .chain((1..).map(|n| format!("{}_{}", base, n)))
+                .find(|name| !taken.contains(name))
+                .unwrap();
+            taken.insert(name.clone());
+            values.insert(reg, Value::Local(name));
+        }
+
+        let wide = cfg.accesses_u64();
+        Translation { func, cfg, order, used, wide, values }
+    }
+
+    // A register as an unsigned operand
+    fn operand(&self, reg: VirtualRegister) -> String {
+        match &self.values[&reg] {
+            Value::Local(name) => name.clone(),
+            Value::Constant(val) => constant(*val, self.wide),
+            Value::Address(address) => format!("0x{:08x}u", address),
+            Value::Argument(text) => text.clone(),
+        }
+    }
+
+    // A register as an operand of a signed comparison
+    fn signed(&self, reg: VirtualRegister) -> String {
+        match &self.values[&reg] {
+            Value::Constant(val) => val.to_string(),
+            _ => format!("({}){}", if self.wide { "int64_t" } else { "int32_t" }, self.operand(reg)),
+        }
+    }
+
+    // Masked to the width shifted, as sll and srl do
+    fn shift_amount(&self, reg: VirtualRegister) -> String {
+        let mask = if self.wide { 63 } else { 31 };
+        match &self.values[&reg] {
+            Value::Constant(val) => (val & mask).to_string(),
+            _ => format!("({} & {})", self.operand(reg), mask),
+        }
+    }
+
+    fn write(&self, output: &mut String, registers: &HashMap<u32, (&ast::RegisterType, String)>) -> std::fmt::Result {
+        let (cfg, order) = (&self.cfg, &self.order);
+
+        // Only blocks something jumps to get a label, unused labels are warnings
+        let mut targets: HashSet<usize> = HashSet::new();
+        for (position, &id) in order.iter().enumerate() {
+            let next = order.get(position + 1).copied();
+            targets.extend(cfg.block(id).terminator.successors().into_iter().filter(|&t| Some(t) != next));
+        }
+
+        let main = self.func.name == "main" && self.func.args.is_empty();
+        if main {
+            writeln!(output, "int main(void) {{")?;
+        } else {
+            writeln!(output, "{} {{", c_header::prototype(self.func))?;
+        }
+        let mut locals: Vec<(usize, &str)> = self.values.iter()
+            .filter_map(|(reg, value)| match value {
+                Value::Local(name) => Some((reg.id, name.as_str())),
+                _ => None,
+            })
+            .collect();
+        if !locals.is_empty() {
+            locals.sort();
+            let names: Vec<&str> = locals.iter().map(|(_, name)| *name).collect();
+            writeln!(output, "    {} {};", if self.wide { "uint64_t" } else { "uint32_t" }, names.join(", "))?;
+            writeln!(output)?;
+        }
+
+        for (position, &id) in order.iter().enumerate() {
+            let block = cfg.block(id);
+            let next = order.get(position + 1).copied();
+
+            if targets.contains(&id) {
+                writeln!(output, "bb{}:", id)?;
+            }
+
+            for instr in &block.instructions {
+                if instr.destination.is_some_and(|dest| !matches!(self.values.get(&dest), None | Some(Value::Local(_)))) {
+                    continue;
+                }
+                let unused = instr.destination.is_some_and(|dest| !self.used.contains(&dest));
+                if unused && !matches!(instr.operation, Op::Call(_) | Op::LoadWord(_) | Op::LoadDouble(_)) {
+                    continue;
+                }
+
+                let rd = || self.operand(instr.destination.unwrap());
+                let arg = |i: usize| self.operand(instr.args[i]);
+
+                let binary = |operator: &str| format!("{} = {} {} {};", rd(), arg(0), operator, arg(1));
+                let mut register = None;
+                let statement = match &instr.operation {
+                    Op::LoadImm(val) => format!("{} = {};", rd(), constant(*val, self.wide)),
+                    Op::LoadAddr(addr) => format!("{} = 0x{:08x}u;", rd(), addr),
+                    Op::LoadWord(offset) | Op::LoadDouble(offset) => {
+                        let double = matches!(instr.operation, Op::LoadDouble(_));
+                        let (access, name) = self.access(instr.args[0], *offset, double, registers);
+                        register = name;
+                        if unused {
+                            format!("(void){};", access)
+                        } else {
+                            format!("{} = {};", rd(), access)
+                        }
+                    }
+                    Op::StoreWord(offset) | Op::StoreDouble(offset) => {
+                        let double = matches!(instr.operation, Op::StoreDouble(_));
+                        let (access, name) = self.access(instr.args[1], *offset, double, registers);
+                        register = name;
+                        format!("{} = {};", access, arg(0))
+                    }
+                    Op::Mov => format!("{} = {};", rd(), arg(0)),
+                    Op::MovArg(index) => format!("{} = {};", rd(), argument(&self.func.args[*index])),
+                    Op::Call(target) => {
+                        let args: Vec<String> = instr.args.iter().map(|a| self.operand(*a)).collect();
+                        if unused {
+                            format!("{}({});", target, args.join(", "))
+                        } else {
+                            format!("{} = (uint32_t){}({});", rd(), target, args.join(", "))
+                        }
+                    }
+                    Op::Add => binary("+"),
+                    Op::Sub => binary("-"),
+                    Op::Mul => binary("*"),
+                    Op::And => binary("&"),
+                    Op::Or => binary("|"),
+                    Op::Xor => binary("^"),
+                    Op::Div if self.wide => format!("{} = peri_div64({}, {});", rd(), arg(0), arg(1)),
+                    Op::Rem if self.wide => format!("{} = peri_rem64({}, {});", rd(), arg(0), arg(1)),
+                    Op::Div => format!("{} = peri_div({}, {});", rd(), arg(0), arg(1)),
+                    Op::Rem => format!("{} = peri_rem({}, {});", rd(), arg(0), arg(1)),
+                    Op::Sll => format!("{} = {} << {};", rd(), arg(0), self.shift_amount(instr.args[1])),
+                    Op::Srl => format!("{} = {} >> {};", rd(), arg(0), self.shift_amount(instr.args[1])),
+                    Op::Slt => format!("{} = {} < {};", rd(), self.signed(instr.args[0]), self.signed(instr.args[1])),
+                    Op::Sltu => binary("<"),
+                    Op::Neg => format!("{} = 0u - {};", rd(), arg(0)),
+                    Op::Not => format!("{} = ~{};", rd(), arg(0)),
+
+                    Op::AddImm(_) | Op::AndImm(_) | Op::OrImm(_) | Op::XorImm(_)
+                    | Op::SllImm(_) | Op::SrlImm(_) | Op::SltImm(_) | Op::SltuImm(_)
+                    | Op::TailCall(_) => {
+                        unreachable!("immediate forms and tail calls are selected by backend::isel")
+                    }
+                    Op::Ret(_) | Op::Label(_) | Op::Jump(_)
+                    | Op::BranchIfFalse(_) | Op::BranchIfTrue(_) | Op::BranchCond(..) => {
+                        unreachable!("control flow instructions are only made by CFG::flatten")
+                    }
+                    Op::LoadSlot(_) | Op::StoreSlot(_) => {
+                        unreachable!("spill code is only inserted after register allocation")
+                    }
+                    Op::Phi(_) => {
+                        unreachable!("phis are removed by ssa::destruct before translation")
+                    }
+                };
+                match register {
+                    Some(name) => writeln!(output, "    {}  /* {} */", statement, name)?,
+                    None => writeln!(output, "    {}", statement)?,
+                }
+            }
+
+            let goto = |target: usize| if Some(target) == next { None } else { Some(format!("goto bb{};", target)) };
+            match &block.terminator {
+                Terminator::Jump(target) => {
+                    if let Some(goto) = goto(*target) {
+                        writeln!(output, "    {}", goto)?;
+                    }
+                }
+
+                Terminator::Branch { cond, then_block, else_block } => {
+                    let cond = self.operand(*cond);
+                    let (taken, not_taken) = (format!("{} != 0", cond), format!("{} == 0", cond));
+                    write_branch(output, &taken, &not_taken, goto(*then_block), goto(*else_block))?;
+                }
+
+                Terminator::CondBranch { op, lhs, rhs, then_block, else_block } => {
+                    let (taken, not_taken) = (self.compare(*op, *lhs, *rhs), self.compare(op.negate(), *lhs, *rhs));
+                    write_branch(output, &taken, &not_taken, goto(*then_block), goto(*else_block))?;
+                }
+
+                Terminator::Return(Some(val)) => {
+                    let cast = if main { "int" } else { "int32_t" };
+                    writeln!(output, "    return ({}){};", cast, self.operand(*val))?;
+                }
+
+                Terminator::Return(None) => {
+                    writeln!(output, "    return 0;")?;
+                }
+
+                Terminator::None => {
+                    unreachable!("layout::arrange rejects reachable blocks without a terminator")
+                }
+            }
+        }
+
+        writeln!(output, "}}")?;
+        writeln!(output)
+    }
+
+    // Signed, as blt and bge compare
+    fn compare(&self, op: CmpOp, lhs: VirtualRegister, rhs: VirtualRegister) -> String {
+        let operator = match op {
+            CmpOp::Eq => "==",
+            CmpOp::Ne => "!=",
+            CmpOp::Lt => "<",
+            CmpOp::Le => "<=",
+            CmpOp::Gt => ">",
+            CmpOp::Ge => ">=",
+        };
+        match op {
+            CmpOp::Eq | CmpOp::Ne => format!("{} {} {}", self.operand(lhs), operator, self.operand(rhs)),
+            _ => format!("{} {} {}", self.signed(lhs), operator, self.signed(rhs)),
+        }
+    }
+
+    // A volatile access of the width the register reached is declared with, and its name
+    fn access(
+        &self,
+        base: VirtualRegister,
+        offset: i32,
+        double: bool,
+        registers: &HashMap<u32, (&ast::RegisterType, String)>,
+    ) -> (String, Option<String>) {
+        let fallback = if double { "uint64_t" } else { "uint32_t" };
+        if let Some(Value::Address(address)) = self.values.get(&base) {
+            let address = address.wrapping_add(offset as u32);
+            return match registers.get(&address) {
+                Some((reg_type, name)) => {
+                    (format!("*(volatile {} *)0x{:08x}u", width_type(reg_type), address), Some(name.clone()))
+                }
+                None => (format!("*(volatile {} *)0x{:08x}u", fallback, address), None),
+            };
+        }
+        let address = match offset {
+            0 => self.operand(base),
+            _ => format!("({} + {})", self.operand(base), offset),
+        };
+        (format!("*(volatile {} *)(uintptr_t){}", fallback, address), None)
+    }
+}
+
+// A parameter as uint32_t, the type registers hold
+fn argument(arg: &ast::Argument) -> String {
+    match arg.ty {
+        ast::Type::U32 => arg.name.clone(),
+        _ => format!("(uint32_t){}", arg.name),
+    }
+}
+
+// Branch away from whichever side does not follow, jumping to it if neither does
+fn write_branch(
+    output: &mut String,
+    taken: &str,
+    not_taken: &str,
+    then_goto: Option<String>,
+    else_goto: Option<String>,
+) -> std::fmt::Result {
+    match (then_goto, else_goto) {
+        (Some(then_goto), None) => writeln!(output, "    if ({}) {}", taken, then_goto),
+        (None, Some(else_goto)) => writeln!(output, "    if ({}) {}", not_taken, else_goto),
+        (Some(then_goto), Some(else_goto)) => {
+            writeln!(output, "    if ({}) {}", taken, then_goto)?;
+            writeln!(output, "    {}", else_goto)
+        }
+        (None, None) => Ok(()),     /* Both sides are the next block */
+    }
+}
+
+// Small values in decimal, the rest as the bit pattern they load, sign-extended to 64 bits as li does on RV64
+fn constant(value: i32, wide: bool) -> String {
+    match value {
+        0..=0xffff => format!("{}u", value),
+        _ if wide => format!("0x{:016x}u", value as i64 as u64),
+        _ => format!("0x{:08x}u", value as u32),
+    }
+}
@@ -0,0 +1,6 @@
+pub mod c_header;
+pub mod c_source;
+pub mod peri;
+pub mod rust_api;
+pub mod state_graph;
+pub mod svd;
@@ -0,0 +1,103 @@
+use crate::frontend::ast;
+use std::fmt::Write;
+
+/* Peripheral declarations in peri syntax, as written by `peric import-svd`
+ *
+ *   peripheral UART0 at 0x4000_0000 {
+ *       states: Reset;
+ *       initial: Reset;
+ *
+ *       registers u32 {
+ *           CTRL   at 0x00 {
+ *               EN   [0];
+ *               MODE [2:1];
+ *           }
+ *           STATUS at 0x04 ro;
+ *       }
+ *   }
+ */
+
+// 0x40001000 -> 0x4000_1000, as in the examples
+fn fmt_address(address: u32) -> String {
+    let hex = format!("{:08X}", address);
+    format!("0x{}_{}", &hex[..4], &hex[4..])
+}
+
+fn fmt_access(access: ast::Access) -> &'static str {
+    match access {
+        ast::Access::ReadWrite => "rw",
+        ast::Access::ReadOnly => "ro",
+        ast::Access::WriteOnly => "wo",
+    }
+}
+
+fn fmt_reg_type(reg_type: &ast::RegisterType) -> &'static str {
+    match reg_type {
+        ast::RegisterType::U8 => "u8",
+        ast::RegisterType::U16 => "u16",
+        ast::RegisterType::U32 => "u32",
+        ast::RegisterType::U64 => "u64",
+    }
+}
+
+fn fmt_bits(field: &ast::Field) -> String {
+    if field.msb == field.lsb {
+        format!("[{}]", field.lsb)
+    } else {
+        format!("[{}:{}]", field.msb, field.lsb)
+    }
+}
+
+pub fn generate(peripherals: &[ast::Peripheral], source: &str) -> Result<String, std::fmt::Error> {
+    let mut output = String::new();
+
+    writeln!(output, "// Generated by peric import-svd from {}", source)?;
+    writeln!(output, "//")?;
+    writeln!(output, "// Each peripheral has a single stub state, replace `states:` and `initial:`")?;
+    writeln!(output, "// with its usage protocol before writing drivers against it.")?;
+
+    for peripheral in peripherals {
+        writeln!(output)?;
+        match peripheral.base_address {
+            Some(base) => writeln!(output, "peripheral {} at {} {{", peripheral.name, fmt_address(base))?,
+            None => writeln!(output, "peripheral {} {{", peripheral.name)?,
+        }
+        writeln!(output, "    states: {};", peripheral.states.join(", "))?;
+        writeln!(output, "    initial: {};", peripheral.initial)?;
+        if !peripheral.finals.is_empty() {
+            writeln!(output, "    final: {};", peripheral.finals.join(", "))?;
+        }
+
+        for block in &peripheral.register_blocks {
+            writeln!(output)?;
+            writeln!(output, "    registers {} {{", fmt_reg_type(&block.reg_type))?;
+            let width = block.registers.iter().map(|r| r.name.len()).max().unwrap_or(0);
+
+            for reg in &block.registers {
+                write!(output, "        {:width$} at 0x{:02X}", reg.name, reg.offset, width = width)?;
+                if reg.access != ast::Access::ReadWrite {
+                    write!(output, " {}", fmt_access(reg.access))?;
+                }
+                if reg.fields.is_empty() {
+                    writeln!(output, ";")?;
+                    continue;
+                }
+
+                writeln!(output, " {{")?;
+                let field_width = reg.fields.iter().map(|f| f.name.len()).max().unwrap_or(0);
+                for field in &reg.fields {
+                    write!(output, "            {:width$} {}", field.name, fmt_bits(field), width = field_width)?;
+                    if let Some(access) = field.access {
+                        write!(output, " {}", fmt_access(access))?;
+                    }
+                    writeln!(output, ";")?;
+                }
+                writeln!(output, "        }}")?;
+            }
+            writeln!(output, "    }}")?;
+        }
+        writeln!(output, "}}")?;
+    }
+
+    Ok(output)
+}
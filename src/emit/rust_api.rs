@@ -0,0 +1,247 @@
+use crate::analysis::reachability::{StateGraph, Transition};
+use crate::analysis::typestate::{self, fmt_typestate_set};
+use crate::frontend::ast::{self, TypeStateSet};
+use std::collections::HashMap;
+use std::fmt::Write;
+
+/* Rust typestate API over peri-compiled drivers, for inclusion as a module in no_std crates
+ *
+ *   pub struct Uart<S>                            handle, zero-sized, S is the current state
+ *   pub mod uart { pub struct Unconfigured; }     one zero-sized type per reachable state set
+ *   Uart::<uart::Unconfigured>::take()            singleton for the initial state
+ *   unsafe extern "C" { fn uart_set_lcr() -> i32; }
+ *   impl Uart<uart::Unconfigured> { pub fn uart_set_lcr(self) -> Uart<uart::LineConfigured> }
+ *
+ * Drivers callable from exactly one reachable state get an inherent method on that state,
+ * named as declared where the signature uses an alias. Drivers callable from several
+ * states (input alternatives, type parameters, or a plain input that is a subset of
+ * several state sets) get a trait in the peripheral's module, implemented by every
+ * state they can be called from, with the resulting state as its associated Output. */
+
+// UART -> Uart, Timer -> Timer
+fn handle_type(peripheral: &str) -> String {
+    let mut chars = peripheral.chars();
+    match chars.next() {
+        Some(first) if peripheral.chars().all(|c| !c.is_ascii_lowercase()) => {
+            first.to_string() + &chars.as_str().to_ascii_lowercase()
+        }
+        Some(first) => first.to_ascii_uppercase().to_string() + chars.as_str(),
+        None => String::new(),
+    }
+}
+
+// uart_set_baud -> UartSetBaud
+fn trait_name(driver: &str) -> String {
+    driver.split('_')
+        .filter(|w| !w.is_empty())
+        .map(|w| w[..1].to_ascii_uppercase() + &w[1..])
+        .collect()
+}
+
+fn state_type(set: &TypeStateSet) -> String {
+    if set.is_empty() {
+        "Empty".to_string()
+    } else {
+        set.iter().cloned().collect::<Vec<_>>().join("_")
+    }
+}
+
+fn rust_type(ty: &ast::Type) -> &'static str {
+    match ty {
+        ast::Type::I32 => "i32",
+        ast::Type::U8  => "u8",
+        ast::Type::U16 => "u16",
+        ast::Type::U32 => "u32",
+    }
+}
+
+fn params(func: &ast::Function) -> String {
+    func.args.iter()
+        .map(|a| format!(", {}: {}", a.name, rust_type(&a.ty)))
+        .collect()
+}
+
+fn call(func: &ast::Function) -> String {
+    let args: Vec<&str> = func.args.iter().map(|a| a.name.as_str()).collect();
+    format!("ffi::{}({})", func.name, args.join(", "))
+}
+
+pub fn generate(program: &ast::Program, graphs: &[StateGraph]) -> Result<String, std::fmt::Error> {
+    let mut output = String::new();
+    let alias_map = typestate::build_alias_map(program);
+
+    writeln!(output, "//! Generated by peric, do not edit")?;
+    writeln!(output, "//!")?;
+    writeln!(output, "//! Typestate-checked bindings to peri-compiled drivers. Only depends on `core`.")?;
+    writeln!(output)?;
+    writeln!(output, "#![allow(dead_code, non_camel_case_types)]")?;
+    writeln!(output)?;
+    writeln!(output, "use core::marker::PhantomData;")?;
+    writeln!(output, "use core::sync::atomic::{{AtomicBool, Ordering}};")?;
+    writeln!(output)?;
+
+    let drivers: Vec<&ast::Function> = program.functions.iter()
+        .filter(|f| f.signature.is_some())
+        .collect();
+
+    writeln!(output, "mod ffi {{")?;
+    writeln!(output, "    unsafe extern \"C\" {{")?;
+    for func in &drivers {
+        let params: Vec<String> = func.args.iter()
+            .map(|a| format!("{}: {}", a.name, rust_type(&a.ty)))
+            .collect();
+        writeln!(output, "        pub fn {}({}) -> i32;", func.name, params.join(", "))?;
+    }
+    writeln!(output, "    }}")?;
+    writeln!(output, "}}")?;
+
+    for graph in graphs {
+        let handle = handle_type(&graph.peripheral);
+        let module = graph.peripheral.to_lowercase();
+        let taken = format!("{}_TAKEN", graph.peripheral.to_uppercase());
+        let initial = state_type(&graph.states[0]);
+
+        writeln!(output)?;
+        writeln!(output, "/* {} */", graph.peripheral)?;
+        writeln!(output)?;
+        writeln!(output, "pub struct {}<S> {{", handle)?;
+        writeln!(output, "    _state: PhantomData<S>,")?;
+        writeln!(output, "}}")?;
+        writeln!(output)?;
+
+        writeln!(output, "pub mod {} {{", module)?;
+        for set in &graph.states {
+            writeln!(output, "    pub struct {};", state_type(set))?;
+        }
+        for state in &graph.unreachable {
+            writeln!(output, "    // {} is unreachable from the initial state", state)?;
+        }
+
+        // Aliases naming exactly one reachable state set become type aliases
+        let mut aliases: HashMap<String, TypeStateSet> = HashMap::new();
+        let peripheral = program.peripherals.iter().find(|p| p.name == graph.peripheral);
+        for alias in peripheral.map(|p| p.aliases.as_slice()).unwrap_or_default() {
+            let label: TypeStateSet = std::iter::once(alias.name.clone()).collect();
+            let matching: Vec<_> = graph.states.iter()
+                .filter(|s| typestate::output_matches(s, &label, &alias_map))
+                .collect();
+            if let [set] = matching.as_slice() {
+                if state_type(set) != alias.name {
+                    writeln!(output, "    pub type {} = {};", alias.name, state_type(set))?;
+                    aliases.insert(alias.name.clone(), (*set).clone());
+                }
+            }
+        }
+
+        for func in &drivers {
+            let sig = func.signature.as_ref().unwrap();
+            if sig.peripheral != graph.peripheral || transitions(graph, func).len() < 2 {
+                continue;
+            }
+            writeln!(output)?;
+            writeln!(output, "    /// States `{}` can be called from", func.name)?;
+            writeln!(output, "    pub trait {} {{", trait_name(&func.name))?;
+            writeln!(output, "        type Output;")?;
+            writeln!(output, "    }}")?;
+            for t in transitions(graph, func) {
+                writeln!(
+                    output,
+                    "    impl {} for {} {{ type Output = {}; }}",
+                    trait_name(&func.name),
+                    state_type(&graph.states[t.from]),
+                    declared_name(&sig.output_state, &graph.states[t.to], &aliases),
+                )?;
+            }
+        }
+        writeln!(output, "}}")?;
+        writeln!(output)?;
+
+        writeln!(output, "static {}: AtomicBool = AtomicBool::new(false);", taken)?;
+        writeln!(output)?;
+        writeln!(output, "impl {}<{}::{}> {{", handle, module, initial)?;
+        writeln!(output, "    /// Take the peripheral in its initial state, returns `None` if already taken")?;
+        writeln!(output, "    ///")?;
+        writeln!(output, "    /// Not reentrant, call from a single context (e.g. at startup before enabling interrupts)")?;
+        writeln!(output, "    pub fn take() -> Option<Self> {{")?;
+        writeln!(output, "        if {}.load(Ordering::Acquire) {{", taken)?;
+        writeln!(output, "            return None;")?;
+        writeln!(output, "        }}")?;
+        writeln!(output, "        {}.store(true, Ordering::Release);", taken)?;
+        writeln!(output, "        Some({} {{ _state: PhantomData }})", handle)?;
+        writeln!(output, "    }}")?;
+        writeln!(output)?;
+        writeln!(output, "    /// # Safety")?;
+        writeln!(output, "    /// The caller must ensure no other handle to this peripheral is in use")?;
+        writeln!(output, "    pub unsafe fn steal() -> Self {{")?;
+        writeln!(output, "        {} {{ _state: PhantomData }}", handle)?;
+        writeln!(output, "    }}")?;
+        writeln!(output, "}}")?;
+
+        for func in &drivers {
+            let sig = func.signature.as_ref().unwrap();
+            if sig.peripheral != graph.peripheral {
+                continue;
+            }
+
+            writeln!(output)?;
+            writeln!(
+                output,
+                "// fn {}({}) :: {}<{}> -> {}<{}>",
+                func.name,
+                func.args.iter().map(|a| a.name.as_str()).collect::<Vec<_>>().join(", "),
+                sig.peripheral,
+                sig.input_states.iter().map(fmt_typestate_set).collect::<Vec<_>>().join(" | "),
+                sig.peripheral,
+                fmt_typestate_set(&sig.output_state),
+            )?;
+
+            match transitions(graph, func).as_slice() {
+                [] => {
+                    writeln!(output, "// No reachable input state, {} has no safe wrapper", func.name)?;
+                    continue;
+                }
+                [t] => {
+                    let from = declared_name(&sig.input_states[0], &graph.states[t.from], &aliases);
+                    let to = declared_name(&sig.output_state, &graph.states[t.to], &aliases);
+                    writeln!(output, "impl {}<{}::{}> {{", handle, module, from)?;
+                    writeln!(
+                        output,
+                        "    pub fn {}(self{}) -> {}<{}::{}> {{",
+                        func.name, params(func), handle, module, to,
+                    )?;
+                }
+                _ => {
+                    let bound = format!("{}::{}", module, trait_name(&func.name));
+                    writeln!(output, "impl<S: {}> {}<S> {{", bound, handle)?;
+                    writeln!(
+                        output,
+                        "    pub fn {}(self{}) -> {}<S::Output> {{",
+                        func.name, params(func), handle,
+                    )?;
+                }
+            }
+            writeln!(output, "        unsafe {{ {} }};", call(func))?;
+            writeln!(output, "        {} {{ _state: PhantomData }}", handle)?;
+            writeln!(output, "    }}")?;
+            writeln!(output, "}}")?;
+        }
+    }
+
+    Ok(output)
+}
+
+fn transitions<'g>(graph: &'g StateGraph, func: &ast::Function) -> Vec<&'g Transition> {
+    graph.transitions.iter().filter(|t| t.driver == func.name).collect()
+}
+
+// Use the signature's own label when it is an alias for the concrete state set
+fn declared_name(
+    declared: &TypeStateSet,
+    concrete: &TypeStateSet,
+    aliases: &HashMap<String, TypeStateSet>,
+) -> String {
+    match declared.iter().next() {
+        Some(label) if declared.len() == 1 && aliases.get(label) == Some(concrete) => label.clone(),
+        _ => state_type(concrete),
+    }
+}
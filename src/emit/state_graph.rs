@@ -0,0 +1,89 @@
+use crate::analysis::reachability::StateGraph;
+use crate::analysis::typestate::fmt_typestate_set;
+use crate::frontend::ast::TypeStateSet;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/* State machine diagrams, one per peripheral
+ *
+ *   DOT:      digraph per peripheral, render with `dot -Tsvg`
+ *   Mermaid:  Markdown with a stateDiagram-v2 block per peripheral
+ *
+ * Nodes are reachable state sets, edges are labelled with the drivers causing the
+ * transition. Declared states that are never reachable are drawn dashed in red. */
+
+fn state_label(set: &TypeStateSet) -> String {
+    if set.is_empty() { "∅".to_string() } else { fmt_typestate_set(set) }
+}
+
+// Drivers with the same source and destination share one edge
+fn merged_edges(graph: &StateGraph) -> BTreeMap<(usize, usize), Vec<&str>> {
+    let mut edges: BTreeMap<(usize, usize), Vec<&str>> = BTreeMap::new();
+    for t in &graph.transitions {
+        edges.entry((t.from, t.to)).or_default().push(&t.driver);
+    }
+    edges
+}
+
+pub fn generate_dot(graphs: &[StateGraph]) -> Result<String, std::fmt::Error> {
+    let mut output = String::new();
+
+    for graph in graphs {
+        writeln!(output, "digraph {} {{", graph.peripheral)?;
+        writeln!(output, "    rankdir=LR;")?;
+        writeln!(output, "    node [shape=box, style=rounded];")?;
+        writeln!(output)?;
+        writeln!(output, "    start [shape=point];")?;
+        writeln!(output, "    start -> s0;")?;
+
+        for (i, set) in graph.states.iter().enumerate() {
+            writeln!(output, "    s{} [label=\"{}\"];", i, state_label(set))?;
+        }
+        for (i, state) in graph.unreachable.iter().enumerate() {
+            writeln!(output, "    u{} [label=\"{}\", style=\"rounded,dashed\", color=red, fontcolor=red];", i, state)?;
+        }
+
+        for ((from, to), drivers) in merged_edges(graph) {
+            writeln!(output, "    s{} -> s{} [label=\"{}\"];", from, to, drivers.join("\\n"))?;
+        }
+        writeln!(output, "}}")?;
+        writeln!(output)?;
+    }
+
+    Ok(output)
+}
+
+pub fn generate_mermaid(graphs: &[StateGraph]) -> Result<String, std::fmt::Error> {
+    let mut output = String::new();
+
+    for graph in graphs {
+        writeln!(output, "## {}", graph.peripheral)?;
+        writeln!(output)?;
+        writeln!(output, "```mermaid")?;
+        writeln!(output, "stateDiagram-v2")?;
+        writeln!(output, "    direction LR")?;
+
+        for (i, set) in graph.states.iter().enumerate() {
+            writeln!(output, "    state \"{}\" as s{}", state_label(set), i)?;
+        }
+        for (i, state) in graph.unreachable.iter().enumerate() {
+            writeln!(output, "    state \"{}\" as u{}", state, i)?;
+        }
+
+        writeln!(output, "    [*] --> s0")?;
+        for ((from, to), drivers) in merged_edges(graph) {
+            writeln!(output, "    s{} --> s{} : {}", from, to, drivers.join(", "))?;
+        }
+
+        if !graph.unreachable.is_empty() {
+            writeln!(output, "    classDef unreachable stroke:red,stroke-dasharray:5 5,color:red")?;
+            for i in 0..graph.unreachable.len() {
+                writeln!(output, "    class u{} unreachable", i)?;
+            }
+        }
+        writeln!(output, "```")?;
+        writeln!(output)?;
+    }
+
+    Ok(output)
+}
@@ -0,0 +1,114 @@
+use crate::frontend::ast;
+use std::fmt::Write;
+
+/* CMSIS-SVD description of the declared register maps, for debuggers and IDE register views
+ *
+ *   peripheral UART at 0x1000_0000    <peripheral> with <baseAddress>
+ *   registers u8 { LCR at 0x03; }     <register> with <addressOffset> and <size>8</size>
+ *   LSR at 0x05 ro;                   <access>read-only</access>
+ *   DLAB [7];                         <field> with <bitRange>[7:7]</bitRange>
+ *
+ * Registers sharing an offset with an earlier one (e.g. 16550 RBR/THR/DLL) name it as
+ * their <alternateRegister>. SVD requires a base address, so peripherals without one
+ * are left out. */
+
+fn svd_access(access: ast::Access) -> &'static str {
+    match access {
+        ast::Access::ReadWrite => "read-write",
+        ast::Access::ReadOnly => "read-only",
+        ast::Access::WriteOnly => "write-only",
+    }
+}
+
+fn size_bits(reg_type: &ast::RegisterType) -> u32 {
+    match reg_type {
+        ast::RegisterType::U8 => 8,
+        ast::RegisterType::U16 => 16,
+        ast::RegisterType::U32 => 32,
+        ast::RegisterType::U64 => 64,
+    }
+}
+
+pub fn generate(program: &ast::Program, device: &str) -> Result<String, std::fmt::Error> {
+    let mut output = String::new();
+
+    writeln!(output, "<?xml version=\"1.0\" encoding=\"utf-8\"?>")?;
+    writeln!(output, "<!-- Generated by peric, do not edit -->")?;
+    writeln!(
+        output,
+        "<device schemaVersion=\"1.3\" xmlns:xs=\"http://www.w3.org/2001/XMLSchema-instance\" xs:noNamespaceSchemaLocation=\"CMSIS-SVD.xsd\">",
+    )?;
+    writeln!(output, "  <name>{}</name>", device)?;
+    writeln!(output, "  <version>1.0</version>")?;
+    writeln!(output, "  <description>Peripherals declared in {}</description>", device)?;
+    writeln!(output, "  <addressUnitBits>8</addressUnitBits>")?;
+    writeln!(output, "  <width>32</width>")?;
+    writeln!(output, "  <size>32</size>")?;
+    writeln!(output, "  <access>read-write</access>")?;
+    writeln!(output, "  <peripherals>")?;
+
+    for peripheral in &program.peripherals {
+        let Some(base) = peripheral.base_address else {
+            writeln!(output, "    <!-- {} has no base address -->", peripheral.name)?;
+            continue;
+        };
+
+        // Registers across all blocks, in offset order, with their block's width
+        let mut registers: Vec<(&ast::Register, u32)> = peripheral.register_blocks.iter()
+            .flat_map(|b| b.registers.iter().map(move |r| (r, size_bits(&b.reg_type))))
+            .collect();
+        registers.sort_by_key(|(r, _)| r.offset);
+
+        let block_size = registers.iter()
+            .map(|(r, bits)| r.offset + bits / 8)
+            .max()
+            .unwrap_or(0);
+
+        writeln!(output, "    <peripheral>")?;
+        writeln!(output, "      <name>{}</name>", peripheral.name)?;
+        writeln!(output, "      <baseAddress>0x{:08X}</baseAddress>", base)?;
+        writeln!(output, "      <addressBlock>")?;
+        writeln!(output, "        <offset>0x0</offset>")?;
+        writeln!(output, "        <size>0x{:X}</size>", block_size)?;
+        writeln!(output, "        <usage>registers</usage>")?;
+        writeln!(output, "      </addressBlock>")?;
+
+        if !registers.is_empty() {
+            writeln!(output, "      <registers>")?;
+        }
+        for (i, (reg, bits)) in registers.iter().enumerate() {
+            writeln!(output, "        <register>")?;
+            writeln!(output, "          <name>{}</name>", reg.name)?;
+            if let Some((first, _)) = registers[..i].iter().find(|(r, _)| r.offset == reg.offset) {
+                writeln!(output, "          <alternateRegister>{}</alternateRegister>", first.name)?;
+            }
+            writeln!(output, "          <addressOffset>0x{:02X}</addressOffset>", reg.offset)?;
+            writeln!(output, "          <size>{}</size>", bits)?;
+            writeln!(output, "          <access>{}</access>", svd_access(reg.access))?;
+
+            if !reg.fields.is_empty() {
+                writeln!(output, "          <fields>")?;
+                for field in &reg.fields {
+                    writeln!(output, "            <field>")?;
+                    writeln!(output, "              <name>{}</name>", field.name)?;
+                    writeln!(output, "              <bitRange>[{}:{}]</bitRange>", field.msb, field.lsb)?;
+                    if let Some(access) = field.access {
+                        writeln!(output, "              <access>{}</access>", svd_access(access))?;
+                    }
+                    writeln!(output, "            </field>")?;
+                }
+                writeln!(output, "          </fields>")?;
+            }
+            writeln!(output, "        </register>")?;
+        }
+        if !registers.is_empty() {
+            writeln!(output, "      </registers>")?;
+        }
+        writeln!(output, "    </peripheral>")?;
+    }
+
+    writeln!(output, "  </peripherals>")?;
+    writeln!(output, "</device>")?;
+
+    Ok(output)
+}
@@ -35,6 +35,7 @@ pub struct Peripheral {
     pub base_address: Option<u32>,
     pub states: Vec<String>,
     pub initial: String,
+    pub finals: Vec<String>,
     pub register_blocks: Vec<RegisterBlock>,
     pub aliases: Vec<TypeStateAlias>,
 }
@@ -50,21 +51,57 @@ pub struct RegisterBlock {
 pub struct Register {
     pub name: String,
     pub offset: u32,
+    pub access: Access,
+    pub fields: Vec<Field>,
 }
 
 #[derive(Debug, Clone)]
+pub struct Field {
+    pub name: String,
+    pub msb: u32,
+    pub lsb: u32,
+    pub access: Option<Access>,     /* None inherits the register's access */
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Access {
+    #[default]
+    ReadWrite,
+    ReadOnly,
+    WriteOnly,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum RegisterType {
     U8,
     U16,
     U32,
+    U64,    /* Only accessible with --target rv64 */
 }
 
 #[derive(Debug, Clone)]
 pub struct Function {
     pub name: String,
-    pub args: Vec<(String, Type)>,
+    pub args: Vec<Argument>,
     pub signature: Option<TypeState>,
     pub body: Vec<Statement>,
+    pub inline: Inline,
+}
+
+/* #[inline] / #[noinline] before `fn`, otherwise left to the inliner's size heuristic */
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Inline {
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(Debug, Clone)]
+pub struct Argument {
+    pub name: String,
+    pub ty: Type,
+    pub default: Option<Expr>,
 }
 
 #[derive(Debug, Clone)]
@@ -94,6 +131,7 @@ pub enum Statement {
     While { cond: Expr, body: Vec<Statement>},
     Return { expr: Expr },
     PeripheralWrite { peripheral: String, register: String, value: Expr },
+    Reach { peripheral: String, target: TypeStateSet },
 }
 
 #[derive(Debug, Clone)]
@@ -0,0 +1,262 @@
+use std::fmt;
+
+#[derive(Debug)]
+pub enum BoardError {
+    Syntax {
+        line: usize,
+        message: String,
+    },
+
+    InvalidNumber {
+        line: usize,
+        value: String,
+    },
+
+    Missing {
+        board: String,
+        what: &'static str,
+    },
+
+    Overlap {
+        first: String,
+        second: String,
+    },
+
+    NoMemory {
+        board: String,
+    },
+
+    StackOutsideRam {
+        board: String,
+        stack_top: u32,
+    },
+}
+
+impl fmt::Display for BoardError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BoardError::Syntax { line, message } => {
+                write!(f, "line {}: {}", line, message)
+            }
+
+            BoardError::InvalidNumber { line, value } => {
+                write!(f, "line {}: invalid number '{}'", line, value)
+            }
+
+            BoardError::Missing { board, what } => {
+                write!(f, "Board '{}' does not declare {}", board, what)
+            }
+
+            BoardError::Overlap { first, second } => {
+                write!(f, "Regions '{}' and '{}' overlap", first, second)
+            }
+
+            BoardError::NoMemory { board } => {
+                write!(f, "Board '{}' has no RAM region", board)
+            }
+
+            BoardError::StackOutsideRam { board, stack_top } => {
+                write!(f, "Stack top 0x{:08x} of board '{}' is not inside or at the end of a RAM region", stack_top, board)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Board {
+    pub name: String,
+    pub regions: Vec<Region>,
+    pub stack_top: u32,
+    pub exit: Exit,
+    pub peripherals: Vec<(String, u32)>,    /* Default bases for peripherals declared without `at` */
+}
+
+#[derive(Debug, Clone)]
+pub struct Region {
+    pub name: String,
+    pub kind: RegionKind,
+    pub origin: u32,
+    pub length: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RegionKind {
+    Ram,
+    Rom,
+    Io,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Exit {
+    Loop,               /* Spin after main returns */
+    Finisher(u32),      /* QEMU test finisher: 0x5555 on success, exit code << 16 | 0x3333 otherwise */
+}
+
+impl Board {
+    // Index of the region for code and read-only data: the first ROM, or RAM on boards without ROM
+    pub fn code_region(&self) -> usize {
+        self.regions.iter().position(|r| r.kind == RegionKind::Rom).unwrap_or(self.data_region())
+    }
+
+    // Index of the region for data, .bss and the stack: the first RAM
+    pub fn data_region(&self) -> usize {
+        self.regions.iter().position(|r| r.kind == RegionKind::Ram).unwrap()
+    }
+}
+
+/* Board descriptions
+ *
+ *   board qemu-virt {
+ *       ram RAM at 0x8000_0000 size 32M;        memory regions: ram, rom or io,
+ *       io UART0 at 0x1000_0000 size 4K;        with a name, origin and size (K/M suffixes)
+ *       stack at 0x8200_0000;                   initial stack pointer
+ *       exit finisher at 0x0010_0000;           or `exit loop;`
+ *       peripheral UART at 0x1000_0000;         base for `peripheral UART` declared without `at`
+ *   }
+ *
+ * One statement per line, `//` starts a comment. A board needs at least one RAM region,
+ * a stack whose top lies inside or at the end of a RAM region, and an exit, and its
+ * regions may not overlap. */
+pub fn parse(source: &str) -> Result<Board, BoardError> {
+    let mut name = None;
+    let mut closed = false;
+    let mut regions: Vec<Region> = Vec::new();
+    let mut stack_top = None;
+    let mut exit = None;
+    let mut peripherals = Vec::new();
+
+    for (index, line) in source.lines().enumerate() {
+        let line_number = index + 1;
+        let syntax = |message: String| BoardError::Syntax { line: line_number, message };
+        let text = line.split("//").next().unwrap_or("").trim();
+        if text.is_empty() {
+            continue;
+        }
+        if closed {
+            return Err(syntax("unexpected text after the closing '}'".to_string()));
+        }
+
+        if name.is_none() {
+            let header = text.strip_prefix("board ")
+                .and_then(|rest| rest.strip_suffix('{'))
+                .map(str::trim)
+                .filter(|n| !n.is_empty());
+            match header {
+                Some(board) => name = Some(board.to_string()),
+                None => return Err(syntax("expected 'board <name> {'".to_string())),
+            }
+            continue;
+        }
+
+        if text == "}" {
+            closed = true;
+            continue;
+        }
+
+        let statement = text.strip_suffix(';')
+            .ok_or_else(|| syntax(format!("expected ';' at the end of '{}'", text)))?;
+        let words: Vec<&str> = statement.split_whitespace().collect();
+        let number = |value: &str| parse_number(value, line_number);
+
+        match words.as_slice() {
+            [kind @ ("ram" | "rom" | "io"), region, "at", origin, "size", size] => {
+                regions.push(Region {
+                    name: region.to_string(),
+                    kind: match *kind {
+                        "ram" => RegionKind::Ram,
+                        "rom" => RegionKind::Rom,
+                        _ => RegionKind::Io,
+                    },
+                    origin: number(origin)?,
+                    length: number(size)?,
+                });
+            }
+            ["stack", "at", top] => stack_top = Some(number(top)?),
+            ["exit", "loop"] => exit = Some(Exit::Loop),
+            ["exit", "finisher", "at", address] => exit = Some(Exit::Finisher(number(address)?)),
+            ["peripheral", peripheral, "at", base] => peripherals.push((peripheral.to_string(), number(base)?)),
+            _ => return Err(syntax(format!("unknown statement '{}'", statement))),
+        }
+    }
+
+    let Some(name) = name else {
+        return Err(BoardError::Syntax { line: 1, message: "expected 'board <name> {'".to_string() });
+    };
+    if !closed {
+        return Err(BoardError::Syntax { line: source.lines().count(), message: "missing closing '}'".to_string() });
+    }
+
+    if !regions.iter().any(|r| r.kind == RegionKind::Ram) {
+        return Err(BoardError::NoMemory { board: name });
+    }
+    for (i, first) in regions.iter().enumerate() {
+        for second in &regions[i + 1..] {
+            let first_end = first.origin as u64 + first.length as u64;
+            let second_end = second.origin as u64 + second.length as u64;
+            if (first.origin as u64) < second_end && (second.origin as u64) < first_end {
+                return Err(BoardError::Overlap { first: first.name.clone(), second: second.name.clone() });
+            }
+        }
+    }
+
+    let stack_top = stack_top.ok_or_else(|| BoardError::Missing { board: name.clone(), what: "a stack" })?;
+    // The stack grows down from its top, so the top may be one past the end of the region
+    let in_ram = regions.iter().any(|r| {
+        r.kind == RegionKind::Ram && r.origin < stack_top && stack_top as u64 <= r.origin as u64 + r.length as u64
+    });
+    if !in_ram {
+        return Err(BoardError::StackOutsideRam { board: name, stack_top });
+    }
+    let exit = exit.ok_or_else(|| BoardError::Missing { board: name.clone(), what: "an exit" })?;
+
+    Ok(Board { name, regions, stack_top, exit, peripherals })
+}
+
+// 0x8000_0000, 4096, 16K or 32M
+fn parse_number(value: &str, line: usize) -> Result<u32, BoardError> {
+    let invalid = || BoardError::InvalidNumber { line, value: value.to_string() };
+    let digits = value.replace('_', "");
+    let (digits, scale) = match digits.strip_suffix('K').or_else(|| digits.strip_suffix('k')) {
+        Some(d) => (d.to_string(), 1024),
+        None => match digits.strip_suffix('M') {
+            Some(d) => (d.to_string(), 1024 * 1024),
+            None => (digits, 1),
+        },
+    };
+    let number = match digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => digits.parse::<u64>(),
+    };
+    number.ok()
+        .and_then(|n| n.checked_mul(scale))
+        .and_then(|n| u32::try_from(n).ok())
+        .ok_or_else(invalid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn board(stack: &str) -> Result<Board, BoardError> {
+        parse(&format!("board test {{\n ram RAM at 0x8000_0000 size 16K;\n stack at {};\n exit loop;\n}}\n", stack))
+    }
+
+    #[test]
+    fn stack_top_inside_or_at_the_end_of_ram() {
+        assert_eq!(board("0x8000_4000").unwrap().stack_top, 0x8000_4000);
+        assert_eq!(board("0x8000_2000").unwrap().stack_top, 0x8000_2000);
+        assert!(matches!(board("0x8000_4004"), Err(BoardError::StackOutsideRam { .. })));
+        assert!(matches!(board("0x8000_0000"), Err(BoardError::StackOutsideRam { .. })));
+    }
+
+    #[test]
+    fn builtin_boards_parse() {
+        for source in [
+            include_str!("../../boards/qemu-virt.board"),
+            include_str!("../../boards/fpga.board"),
+            include_str!("../../boards/lm3s6965.board"),
+        ] {
+            parse(source).unwrap();
+        }
+    }
+}
@@ -1,2 +1,5 @@
 pub mod ast;
-pub mod parser;
\ No newline at end of file
+pub mod board;
+pub mod parser;
+pub mod svd;
+pub mod xml;
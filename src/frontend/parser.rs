@@ -188,6 +188,17 @@ fn parser<'src>() -> impl Parser<'src, &'src str, ast::Program, extra::Err<Simpl
         ))
     });
 
+    let ts_label = just('!').padded_by(ws)
+        .ignore_then(text::ident().padded_by(ws))
+        .map(|s: &str| format!("!{s}"))
+        .or(text::ident().padded_by(ws).map(|s: &str| s.to_string()));
+
+    let ts_set = ts_label
+        .separated_by(just('&').padded_by(ws))
+        .at_least(1)
+        .collect::<Vec<String>>()
+        .map(|labels| labels.into_iter().collect::<TypeStateSet>());
+
     /* Statement Parser  */
     let statement = recursive(|statement| {
 
@@ -256,8 +267,19 @@ fn parser<'src>() -> impl Parser<'src, &'src str, ast::Program, extra::Err<Simpl
                 value 
             });
 
+        /* reach UART<Ready>;  expanded into driver calls by analysis::synthesis */
+        let reach_stmt = text::keyword("reach").padded_by(ws)
+            .ignore_then(ident)
+            .then(ts_set.delimited_by(
+                just('<').padded_by(ws),
+                just('>').padded_by(ws),
+            ))
+            .then_ignore(just(';').padded_by(ws))
+            .map(|(peripheral, target)| ast::Statement::Reach { peripheral, target });
+
         if_stmt
             .or(while_stmt)
+            .or(reach_stmt)
             .or(const_stmt)
             .or(let_stmt)
             .or(peripheral_write_stmt)
@@ -271,9 +293,13 @@ fn parser<'src>() -> impl Parser<'src, &'src str, ast::Program, extra::Err<Simpl
      * peripheral Timer at 0x4000_0000 {
      *     states: Off, On;
      *     initial: Off;
+     *     final: On;                  (optional)
      *     registers u32 {
-     *         CTRL at 0x00;
-     *         COUNT at 0x04;
+     *         CTRL at 0x00 {          (optional bitfields, [msb:lsb] or [bit])
+     *             EN   [0];
+     *             MODE [2:1];
+     *         }
+     *         COUNT at 0x04 ro;       (optional access: rw (default), ro, wo)
      *     }
      * }
      */
@@ -281,13 +307,42 @@ fn parser<'src>() -> impl Parser<'src, &'src str, ast::Program, extra::Err<Simpl
     let reg_type = text::keyword("u8").to(ast::RegisterType::U8)
         .or(text::keyword("u16").to(ast::RegisterType::U16))
         .or(text::keyword("u32").to(ast::RegisterType::U32))
+        .or(text::keyword("u64").to(ast::RegisterType::U64))
         .padded_by(ws.clone());
     
-    let register = ident.clone()
+    let access = text::keyword("rw").to(ast::Access::ReadWrite)
+        .or(text::keyword("ro").to(ast::Access::ReadOnly))
+        .or(text::keyword("wo").to(ast::Access::WriteOnly))
+        .padded_by(ws);
+
+    let bit = text::int(10)
+        .try_map(|s: &str, span| s.parse::<u32>().map_err(|_| Simple::new(None, span)))
+        .padded_by(ws);
+
+    let field = ident
+        .then(
+            bit
+                .then(just(':').padded_by(ws).ignore_then(bit).or_not())
+                .delimited_by(just('[').padded_by(ws), just(']').padded_by(ws))
+        )
+        .then(access.clone().or_not())
+        .then_ignore(semicolon)
+        .map(|((name, (msb, lsb)), access)| ast::Field { name, msb, lsb: lsb.unwrap_or(msb), access });
+
+    let register = ident
         .then_ignore(text::keyword("at").padded())
-        .then(hex_num.clone())
-        .then_ignore(semicolon.clone())
-        .map(|(name, offset)| ast::Register { name, offset });
+        .then(hex_num)
+        .then(access.or_not().map(Option::unwrap_or_default))
+        .then(
+            semicolon.to(Vec::new())
+                .or(
+                    field
+                        .repeated()
+                        .collect()
+                        .delimited_by(just('{').padded_by(ws), just('}').padded_by(ws))
+                )
+        )
+        .map(|(((name, offset), access), fields)| ast::Register { name, offset, access, fields });
     
     let register_block = text::keyword("registers").padded()
         .ignore_then(reg_type)
@@ -299,17 +354,6 @@ fn parser<'src>() -> impl Parser<'src, &'src str, ast::Program, extra::Err<Simpl
         )
         .map(|(reg_type, registers)| ast::RegisterBlock { reg_type, registers });
 
-    let ts_label = just('!').padded_by(ws.clone())
-        .ignore_then(text::ident().padded_by(ws.clone()))
-        .map(|s: &str| format!("!{s}"))
-        .or(text::ident().padded_by(ws.clone()).map(|s: &str| s.to_string()));
-
-    let ts_set = ts_label.clone()
-        .separated_by(just('&').padded_by(ws.clone()))
-        .at_least(1)
-        .collect::<Vec<String>>()
-        .map(|labels| labels.into_iter().collect::<TypeStateSet>());
-
     let ts_set_vec = ts_set.clone()
         .separated_by(just('|').padded_by(ws.clone()))
         .at_least(1)
@@ -336,6 +380,19 @@ fn parser<'src>() -> impl Parser<'src, &'src str, ast::Program, extra::Err<Simpl
         .then_ignore(just(':').padded())
         .then(ident.clone())
         .then_ignore(semicolon)
+        .then(
+            text::keyword("final").padded()
+                .ignore_then(just(':').padded())
+                .ignore_then(
+                    ident
+                        .separated_by(comma)
+                        .at_least(1)
+                        .collect::<Vec<String>>()
+                )
+                .then_ignore(semicolon)
+                .or_not()
+                .map(Option::unwrap_or_default)
+        )
         .then(
             register_block
                 .repeated()
@@ -352,11 +409,12 @@ fn parser<'src>() -> impl Parser<'src, &'src str, ast::Program, extra::Err<Simpl
                 .collect::<Vec<ast::TypeStateAlias>>()
         )
         .then_ignore(just('}').padded())
-        .map(|(((((name, base_address), states), initial), register_blocks), aliases)| ast::Peripheral {
+        .map(|((((((name, base_address), states), initial), finals), register_blocks), aliases)| ast::Peripheral {
             name,
             base_address,
             states,
             initial,
+            finals,
             register_blocks,
             aliases,
         });
@@ -413,7 +471,8 @@ fn parser<'src>() -> impl Parser<'src, &'src str, ast::Program, extra::Err<Simpl
 
     /* 
      * Function Parser 
-     * 'fn func(arg1, arg2) :: Type<InputState> -> Type<OutputState> { 
+     * '[#[inline] | #[noinline]]
+     *  fn func(arg1: u8, arg2: u32 = DEFAULT) :: Type<InputState> -> Type<OutputState> { 
      *      statements 
      *  }'
      */
@@ -426,7 +485,9 @@ fn parser<'src>() -> impl Parser<'src, &'src str, ast::Program, extra::Err<Simpl
 
     let argument = ident
         .then_ignore(just(':')).padded()
-        .then(type_label.clone());
+        .then(type_label.clone())
+        .then(equals.ignore_then(expr.clone()).or_not())
+        .map(|((name, ty), default)| ast::Argument { name, ty, default });
 
     let signature_body = just("::").padded_by(ws.clone())
         .ignore_then(sig_input)
@@ -436,8 +497,19 @@ fn parser<'src>() -> impl Parser<'src, &'src str, ast::Program, extra::Err<Simpl
             ast::TypeState { peripheral: periph, type_params: vec![], input_states, output_state }
         });
 
-    let function = text::keyword("fn").padded_by(ws.clone())
-        .ignore_then(ident)
+    let inline_attribute = just("#[").padded_by(ws)
+        .ignore_then(
+            text::keyword("inline").to(ast::Inline::Always)
+                .or(text::keyword("noinline").to(ast::Inline::Never))
+                .padded_by(ws)
+        )
+        .then_ignore(just(']').padded_by(ws))
+        .or_not()
+        .map(Option::unwrap_or_default);
+
+    let function = inline_attribute
+        .then_ignore(text::keyword("fn").padded_by(ws))
+        .then(ident)
         .then(
             argument
                 .separated_by(comma)
@@ -454,9 +526,9 @@ fn parser<'src>() -> impl Parser<'src, &'src str, ast::Program, extra::Err<Simpl
                 .collect()
                 .delimited_by(just('{').padded_by(ws.clone()), just('}').padded_by(ws.clone())),
         )
-        .map(|((((name, args), type_params), sig_opt), body)| {
+        .map(|(((((inline, name), args), type_params), sig_opt), body)| {
             let signature = sig_opt.map(|mut sig| { sig.type_params = type_params; sig });
-            ast::Function { name, args, signature, body }
+            ast::Function { name, args, signature, body, inline }
         });
 
     let global_const = text::keyword("const").padded_by(ws.clone())
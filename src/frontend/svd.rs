@@ -0,0 +1,440 @@
+use crate::frontend::ast;
+use crate::frontend::xml::{self, Element, XmlError};
+use std::fmt;
+
+#[derive(Debug)]
+pub enum SvdError {
+    Xml(XmlError),
+
+    NotADevice {
+        root: String,
+    },
+
+    MissingElement {
+        context: String,
+        element: &'static str,
+    },
+
+    InvalidNumber {
+        context: String,
+        value: String,
+    },
+
+    UnsupportedWidth {
+        context: String,
+        size: u32,
+    },
+
+    UnknownAccess {
+        context: String,
+        value: String,
+    },
+
+    UnknownBase {
+        peripheral: String,
+        base: String,
+    },
+}
+
+impl fmt::Display for SvdError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SvdError::Xml(err) => {
+                write!(f, "{}", err)
+            }
+
+            SvdError::NotADevice { root } => {
+                write!(f, "Expected a <device> root element, found <{}>", root)
+            }
+
+            SvdError::MissingElement { context, element } => {
+                write!(f, "'{}' has no <{}>", context, element)
+            }
+
+            SvdError::InvalidNumber { context, value } => {
+                write!(f, "Invalid number '{}' in '{}'", value, context)
+            }
+
+            SvdError::UnsupportedWidth { context, size } => {
+                write!(f, "Register '{}' is {} bits wide, peri registers are u8, u16, u32 or u64", context, size)
+            }
+
+            SvdError::UnknownAccess { context, value } => {
+                write!(f, "Unknown access '{}' in '{}'", value, context)
+            }
+
+            SvdError::UnknownBase { peripheral, base } => {
+                write!(f, "Peripheral '{}' is derived from unknown peripheral '{}'", peripheral, base)
+            }
+        }
+    }
+}
+
+impl From<XmlError> for SvdError {
+    fn from(err: XmlError) -> Self {
+        SvdError::Xml(err)
+    }
+}
+
+/* Register properties inherited from the device down through peripherals and clusters */
+#[derive(Clone, Copy)]
+struct Properties {
+    size: Option<u32>,
+    access: Option<ast::Access>,
+}
+
+impl Properties {
+    fn inherit(self, element: &Element, context: &str) -> Result<Properties, SvdError> {
+        Ok(Properties {
+            size: match element.child_text("size") {
+                Some(size) => Some(number(size, context)?),
+                None => self.size,
+            },
+            access: match element.child_text("access") {
+                Some(access) => Some(parse_access(access, context)?),
+                None => self.access,
+            },
+        })
+    }
+}
+
+/* Convert a CMSIS-SVD device description into peripheral declarations
+ *
+ *   <peripheral>   -> peripheral NAME at baseAddress, with a stub `Reset` state
+ *   <register>     -> NAME at addressOffset, grouped into `registers uN` blocks by size
+ *   <cluster>      -> flattened, register names prefixed with the cluster name
+ *   <dim>          -> one register per index, %s replaced by the index
+ *   <field>        -> NAME [msb:lsb], from bitOffset/bitWidth, lsb/msb or bitRange
+ *
+ * Access is inherited from enclosing elements. Field access is only kept where it
+ * differs from its register's. */
+pub fn import(source: &str) -> Result<Vec<ast::Peripheral>, SvdError> {
+    let device = xml::parse(source)?;
+    if device.name != "device" {
+        return Err(SvdError::NotADevice { root: device.name });
+    }
+
+    let device_name = device.child_text("name").unwrap_or("device").to_string();
+    let defaults = Properties { size: None, access: None }.inherit(&device, &device_name)?;
+
+    let Some(peripherals) = device.child("peripherals") else {
+        return Ok(Vec::new());
+    };
+    let elements: Vec<&Element> = peripherals.children_named("peripheral").collect();
+
+    elements.iter()
+        .map(|p| import_peripheral(p, &elements, defaults))
+        .collect()
+}
+
+fn import_peripheral(
+    element: &Element,
+    all: &[&Element],
+    defaults: Properties,
+) -> Result<ast::Peripheral, SvdError> {
+    let name = required_text(element, "name", "peripheral")?;
+    let base_address = number(&required_text(element, "baseAddress", &name)?, &name)?;
+
+    // A derived peripheral reuses its base's registers unless it declares its own
+    let base = match element.attribute("derivedFrom") {
+        Some(base_name) => Some(
+            all.iter()
+                .find(|p| p.child_text("name") == Some(base_name))
+                .ok_or_else(|| SvdError::UnknownBase { peripheral: name.clone(), base: base_name.to_string() })?,
+        ),
+        None => None,
+    };
+
+    let mut props = defaults;
+    if let Some(base) = base {
+        props = props.inherit(base, &name)?;
+    }
+    props = props.inherit(element, &name)?;
+
+    let registers = element.child("registers")
+        .or_else(|| base.and_then(|b| b.child("registers")));
+
+    let mut flat = Vec::new();
+    if let Some(registers) = registers {
+        flatten(registers, "", 0, props, &mut flat)?;
+    }
+    flat.sort_by_key(|(_, reg)| reg.offset);
+
+    let mut register_blocks: Vec<ast::RegisterBlock> = Vec::new();
+    for (reg_type, register) in flat {
+        match register_blocks.iter_mut().find(|b| b.reg_type == reg_type) {
+            Some(block) => block.registers.push(register),
+            None => register_blocks.push(ast::RegisterBlock { reg_type, registers: vec![register] }),
+        }
+    }
+
+    Ok(ast::Peripheral {
+        name: identifier(&name),
+        base_address: Some(base_address),
+        states: vec!["Reset".to_string()],
+        initial: "Reset".to_string(),
+        finals: Vec::new(),
+        register_blocks,
+        aliases: Vec::new(),
+    })
+}
+
+fn flatten(
+    parent: &Element,
+    prefix: &str,
+    offset: u32,
+    props: Properties,
+    out: &mut Vec<(ast::RegisterType, ast::Register)>,
+) -> Result<(), SvdError> {
+    for element in &parent.children {
+        if element.name != "register" && element.name != "cluster" {
+            continue;
+        }
+
+        let raw_name = required_text(element, "name", &element.name)?;
+        let context = format!("{}{}", prefix, raw_name);
+        let element_offset = number(&required_text(element, "addressOffset", &context)?, &context)?;
+        let props = props.inherit(element, &context)?;
+
+        for (name, increment) in dim_instances(element, &raw_name, &context)? {
+            let name = format!("{}{}", prefix, identifier(&name));
+            let address = offset.wrapping_add(element_offset).wrapping_add(increment);
+
+            if element.name == "cluster" {
+                flatten(element, &format!("{}_", name), address, props, out)?;
+                continue;
+            }
+
+            let size = props.size.unwrap_or(32);
+            let reg_type = match size {
+                8 => ast::RegisterType::U8,
+                16 => ast::RegisterType::U16,
+                32 => ast::RegisterType::U32,
+                64 => ast::RegisterType::U64,
+                _ => return Err(SvdError::UnsupportedWidth { context: name, size }),
+            };
+            let access = props.access.unwrap_or_default();
+            let fields = match element.child("fields") {
+                Some(fields) => import_fields(fields, &name, access)?,
+                None => Vec::new(),
+            };
+
+            out.push((reg_type, ast::Register { name, offset: address, access, fields }));
+        }
+    }
+    Ok(())
+}
+
+fn import_fields(
+    fields: &Element,
+    register: &str,
+    register_access: ast::Access,
+) -> Result<Vec<ast::Field>, SvdError> {
+    let mut out = Vec::new();
+
+    for element in fields.children_named("field") {
+        let raw_name = required_text(element, "name", register)?;
+        let context = format!("{}.{}", register, raw_name);
+
+        let (lsb, msb) = if let Some(range) = element.child_text("bitRange") {
+            // [msb:lsb]
+            let inner = range.trim_start_matches('[').trim_end_matches(']');
+            let (msb, lsb) = inner.split_once(':').ok_or_else(|| SvdError::InvalidNumber {
+                context: context.clone(),
+                value: range.to_string(),
+            })?;
+            (number(lsb, &context)?, number(msb, &context)?)
+        } else if let (Some(lsb), Some(msb)) = (element.child_text("lsb"), element.child_text("msb")) {
+            (number(lsb, &context)?, number(msb, &context)?)
+        } else {
+            let lsb = number(&required_text(element, "bitOffset", &context)?, &context)?;
+            let width = match element.child_text("bitWidth") {
+                Some(width) => number(width, &context)?,
+                None => 1,
+            };
+            (lsb, lsb + width.max(1) - 1)
+        };
+
+        let access = match element.child_text("access") {
+            Some(access) => Some(parse_access(access, &context)?).filter(|a| *a != register_access),
+            None => None,
+        };
+
+        for (name, index) in dim_instances(element, &raw_name, &context)? {
+            // dimIncrement of a field array is in bits
+            out.push(ast::Field {
+                name: identifier(&name),
+                msb: msb + index,
+                lsb: lsb + index,
+                access,
+            });
+        }
+    }
+
+    out.sort_by_key(|f| f.lsb);
+    Ok(out)
+}
+
+/* Instances of a possibly dimensioned element as (name, offset increment)
+ *
+ *   <dim>4</dim><dimIncrement>4</dimIncrement>           0, 1, 2, 3
+ *   <dimIndex>A,B,C</dimIndex>                            A, B, C
+ *   <dimIndex>2-5</dimIndex>                              2, 3, 4, 5
+ *
+ * `NAME[%s]` and `NAME%s` both become NAME0, NAME1, ... */
+fn dim_instances(element: &Element, name: &str, context: &str) -> Result<Vec<(String, u32)>, SvdError> {
+    let Some(dim) = element.child_text("dim") else {
+        return Ok(vec![(name.to_string(), 0)]);
+    };
+    let dim = number(dim, context)?;
+    let increment = match element.child_text("dimIncrement") {
+        Some(increment) => number(increment, context)?,
+        None => 0,
+    };
+
+    let indices: Vec<String> = match element.child_text("dimIndex") {
+        Some(index) if index.contains('-') && !index.contains(',') => {
+            let (first, last) = index.split_once('-').unwrap();
+            let first = number(first, context)?;
+            let last = number(last, context)?;
+            (first..=last).map(|i| i.to_string()).collect()
+        }
+        Some(index) => index.split(',').map(|s| s.trim().to_string()).collect(),
+        None => (0..dim).map(|i| i.to_string()).collect(),
+    };
+
+    let base = name.replace("[%s]", "%s");
+    Ok(indices.iter()
+        .take(dim as usize)
+        .enumerate()
+        .map(|(i, index)| (base.replace("%s", index), increment * i as u32))
+        .collect())
+}
+
+fn required_text(element: &Element, child: &'static str, context: &str) -> Result<String, SvdError> {
+    element.child_text(child)
+        .map(str::to_string)
+        .ok_or_else(|| SvdError::MissingElement { context: context.to_string(), element: child })
+}
+
+// SVD scaled non-negative integers: decimal, 0x hexadecimal or # binary
+fn number(value: &str, context: &str) -> Result<u32, SvdError> {
+    let value = value.trim();
+    let parsed = if let Some(hex) = value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+        u32::from_str_radix(hex, 16)
+    } else if let Some(bin) = value.strip_prefix('#') {
+        u32::from_str_radix(bin, 2)
+    } else {
+        value.parse()
+    };
+    parsed.map_err(|_| SvdError::InvalidNumber { context: context.to_string(), value: value.to_string() })
+}
+
+fn parse_access(value: &str, context: &str) -> Result<ast::Access, SvdError> {
+    match value {
+        "read-write" | "read-writeOnce" => Ok(ast::Access::ReadWrite),
+        "read-only" => Ok(ast::Access::ReadOnly),
+        "write-only" | "writeOnce" => Ok(ast::Access::WriteOnly),
+        _ => Err(SvdError::UnknownAccess { context: context.to_string(), value: value.to_string() }),
+    }
+}
+
+// SVD names are C identifiers in practice, anything else is replaced by '_'
+fn identifier(name: &str) -> String {
+    let mut ident: String = name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' { c } else { '_' })
+        .collect();
+    if ident.starts_with(|c: char| c.is_ascii_digit()) {
+        ident.insert(0, '_');
+    }
+    ident
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emit;
+
+    const FIXTURE: &str = include_str!("../../tests/fixtures/small.svd");
+
+    fn registers(peripheral: &ast::Peripheral) -> Vec<(String, u32, ast::RegisterType)> {
+        peripheral.register_blocks.iter()
+            .flat_map(|b| b.registers.iter().map(|r| (r.name.clone(), r.offset, b.reg_type.clone())))
+            .collect()
+    }
+
+    fn fields(register: &ast::Register) -> Vec<(&str, u32, u32, Option<ast::Access>)> {
+        register.fields.iter().map(|f| (f.name.as_str(), f.msb, f.lsb, f.access)).collect()
+    }
+
+    #[test]
+    fn registers_widths_and_access() {
+        let peripherals = import(FIXTURE).unwrap();
+        let uart = &peripherals[0];
+        assert_eq!(uart.name, "UART0");
+        assert_eq!(uart.base_address, Some(0x4000_1000));
+        assert_eq!(registers(uart), vec![
+            ("DATA".to_string(), 0x0, ast::RegisterType::U8),
+            ("STATUS".to_string(), 0x4, ast::RegisterType::U8),
+            ("BAUD".to_string(), 0x8, ast::RegisterType::U16),
+        ]);
+
+        let status = &uart.register_blocks[0].registers[1];
+        assert_eq!(status.access, ast::Access::ReadOnly);
+        assert_eq!(fields(status), vec![
+            ("RXNE", 0, 0, None),
+            ("ERR", 3, 1, None),
+            ("CLR", 7, 7, Some(ast::Access::WriteOnly)),
+        ]);
+        assert_eq!(fields(&uart.register_blocks[1].registers[0]), vec![("DIV", 11, 0, None)]);
+    }
+
+    #[test]
+    fn derived_peripheral_reuses_registers() {
+        let peripherals = import(FIXTURE).unwrap();
+        assert_eq!(peripherals[1].name, "UART1");
+        assert_eq!(peripherals[1].base_address, Some(0x4000_2000));
+        assert_eq!(registers(&peripherals[1]), registers(&peripherals[0]));
+    }
+
+    #[test]
+    fn dim_and_clusters_are_flattened() {
+        let peripherals = import(FIXTURE).unwrap();
+        let timer = &peripherals[2];
+        let names: Vec<(String, u32)> = registers(timer).into_iter().map(|(n, o, _)| (n, o)).collect();
+        assert_eq!(names, vec![
+            ("CMP0".to_string(), 0x10),
+            ("CMP1".to_string(), 0x14),
+            ("CHA_CTRL".to_string(), 0x20),
+            ("CHA_COUNT".to_string(), 0x24),
+            ("CHB_CTRL".to_string(), 0x28),
+            ("CHB_COUNT".to_string(), 0x2c),
+        ]);
+        assert_eq!(fields(&timer.register_blocks[0].registers[2]), vec![("EN0", 4, 4, None), ("EN1", 5, 5, None)]);
+    }
+
+    #[test]
+    fn unknown_base_and_bad_numbers() {
+        let derived = "<device><peripherals><peripheral derivedFrom=\"NOPE\"><name>A</name>\
+                       <baseAddress>0</baseAddress></peripheral></peripherals></device>";
+        assert!(matches!(import(derived), Err(SvdError::UnknownBase { .. })));
+        let number = "<device><peripherals><peripheral><name>A</name>\
+                      <baseAddress>0xZZ</baseAddress></peripheral></peripherals></device>";
+        assert!(matches!(import(number), Err(SvdError::InvalidNumber { .. })));
+        assert!(matches!(import("<board/>"), Err(SvdError::NotADevice { .. })));
+    }
+
+    // import -> --emit=svd -> import gives the same declarations
+    #[test]
+    fn round_trip_through_svd_output() {
+        let peripherals = import(FIXTURE).unwrap();
+        let program = ast::Program { peripherals, constants: Vec::new(), functions: Vec::new() };
+        let svd = emit::svd::generate(&program, "SMALL").unwrap();
+        let reimported = import(&svd).unwrap();
+
+        assert_eq!(
+            emit::peri::generate(&reimported, "small.svd").unwrap(),
+            emit::peri::generate(&program.peripherals, "small.svd").unwrap(),
+        );
+    }
+}
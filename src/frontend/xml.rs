@@ -0,0 +1,316 @@
+use std::fmt;
+
+/* Minimal XML reader, enough for CMSIS-SVD device descriptions
+ *
+ * Supports elements, attributes, text, CDATA, comments, processing instructions,
+ * a DOCTYPE without an internal subset, and the five predefined entities plus
+ * numeric character references. Namespaces are kept as part of the name. */
+
+#[derive(Debug)]
+pub enum XmlError {
+    UnexpectedEnd,
+
+    Expected {
+        line: usize,
+        expected: &'static str,
+    },
+
+    MismatchedTag {
+        line: usize,
+        open: String,
+        close: String,
+    },
+
+    UnknownEntity {
+        line: usize,
+        entity: String,
+    },
+}
+
+impl fmt::Display for XmlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            XmlError::UnexpectedEnd => {
+                write!(f, "Unexpected end of document")
+            }
+
+            XmlError::Expected { line, expected } => {
+                write!(f, "Expected {} on line {}", expected, line)
+            }
+
+            XmlError::MismatchedTag { line, open, close } => {
+                write!(f, "Closing tag '</{}>' on line {} does not match '<{}>'", close, line, open)
+            }
+
+            XmlError::UnknownEntity { line, entity } => {
+                write!(f, "Unknown entity '&{};' on line {}", entity, line)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Element {
+    pub name: String,
+    pub attributes: Vec<(String, String)>,
+    pub children: Vec<Element>,
+    pub text: String,
+}
+
+impl Element {
+    pub fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|c| c.name == name)
+    }
+
+    pub fn children_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Element> {
+        self.children.iter().filter(move |c| c.name == name)
+    }
+
+    // Trimmed text content of a direct child
+    pub fn child_text(&self, name: &str) -> Option<&str> {
+        self.child(name).map(|c| c.text.trim())
+    }
+
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+}
+
+pub fn parse(source: &str) -> Result<Element, XmlError> {
+    let mut reader = Reader { source, pos: 0 };
+    reader.skip_misc()?;
+    let root = reader.element()?;
+    reader.skip_misc()?;
+    if reader.pos < source.len() {
+        return Err(reader.expected("end of document"));
+    }
+    Ok(root)
+}
+
+struct Reader<'a> {
+    source: &'a str,
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn rest(&self) -> &str {
+        &self.source[self.pos..]
+    }
+
+    fn line(&self) -> usize {
+        self.source[..self.pos].matches('\n').count() + 1
+    }
+
+    fn expected(&self, expected: &'static str) -> XmlError {
+        if self.pos >= self.source.len() {
+            XmlError::UnexpectedEnd
+        } else {
+            XmlError::Expected { line: self.line(), expected }
+        }
+    }
+
+    fn eat(&mut self, s: &str) -> bool {
+        if self.rest().starts_with(s) {
+            self.pos += s.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, s: &'static str) -> Result<(), XmlError> {
+        if self.eat(s) {
+            Ok(())
+        } else {
+            Err(self.expected(s))
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        let trimmed = self.rest().trim_start();
+        self.pos = self.source.len() - trimmed.len();
+    }
+
+    // Consume everything up to and including `end`, returning what came before it
+    fn until(&mut self, end: &'static str) -> Result<&str, XmlError> {
+        let start = self.pos;
+        match self.rest().find(end) {
+            Some(i) => {
+                self.pos += i + end.len();
+                Ok(&self.source[start..start + i])
+            }
+            None => {
+                self.pos = self.source.len();
+                Err(XmlError::UnexpectedEnd)
+            }
+        }
+    }
+
+    // Whitespace, comments, processing instructions and DOCTYPE outside the root element
+    fn skip_misc(&mut self) -> Result<(), XmlError> {
+        loop {
+            self.skip_whitespace();
+            if self.eat("<?") {
+                self.until("?>")?;
+            } else if self.eat("<!--") {
+                self.until("-->")?;
+            } else if self.eat("<!DOCTYPE") {
+                self.until(">")?;
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    fn name(&mut self) -> Result<String, XmlError> {
+        let len = self.rest()
+            .find(|c: char| !(c.is_alphanumeric() || matches!(c, '_' | '-' | '.' | ':')))
+            .unwrap_or(self.rest().len());
+        if len == 0 {
+            return Err(self.expected("a name"));
+        }
+        let name = self.rest()[..len].to_string();
+        self.pos += len;
+        Ok(name)
+    }
+
+    fn element(&mut self) -> Result<Element, XmlError> {
+        self.expect("<")?;
+        let name = self.name()?;
+        let mut attributes = Vec::new();
+
+        loop {
+            self.skip_whitespace();
+            if self.eat("/>") {
+                return Ok(Element { name, attributes, children: Vec::new(), text: String::new() });
+            }
+            if self.eat(">") {
+                break;
+            }
+            let attr = self.name()?;
+            self.skip_whitespace();
+            self.expect("=")?;
+            self.skip_whitespace();
+            let quote = if self.eat("\"") {
+                "\""
+            } else if self.eat("'") {
+                "'"
+            } else {
+                return Err(self.expected("a quoted attribute value"));
+            };
+            let line = self.line();
+            let raw = self.until(quote)?;
+            attributes.push((attr, unescape(raw, line)?));
+        }
+
+        let mut children = Vec::new();
+        let mut text = String::new();
+        loop {
+            if self.eat("</") {
+                let line = self.line();
+                let close = self.name()?;
+                self.skip_whitespace();
+                self.expect(">")?;
+                if close != name {
+                    return Err(XmlError::MismatchedTag { line, open: name, close });
+                }
+                return Ok(Element { name, attributes, children, text });
+            } else if self.eat("<!--") {
+                self.until("-->")?;
+            } else if self.eat("<![CDATA[") {
+                text.push_str(self.until("]]>")?);
+            } else if self.eat("<?") {
+                self.until("?>")?;
+            } else if self.rest().starts_with('<') {
+                children.push(self.element()?);
+            } else if self.rest().is_empty() {
+                return Err(XmlError::UnexpectedEnd);
+            } else {
+                let line = self.line();
+                let len = self.rest().find('<').unwrap_or(self.rest().len());
+                let raw = &self.rest()[..len];
+                text.push_str(&unescape(raw, line)?);
+                self.pos += len;
+            }
+        }
+    }
+}
+
+// `line` is where `raw` starts, errors give the line of the entity itself
+fn unescape(raw: &str, line: usize) -> Result<String, XmlError> {
+    let mut out = String::with_capacity(raw.len());
+    let mut rest = raw;
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        rest = &rest[amp + 1..];
+        let line = line + raw[..raw.len() - rest.len()].matches('\n').count();
+        let Some(semi) = rest.find(';') else {
+            return Err(XmlError::Expected { line, expected: "';' after entity" });
+        };
+        let entity = &rest[..semi];
+        let c = match entity {
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "amp" => Some('&'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ if entity.starts_with("#x") => u32::from_str_radix(&entity[2..], 16).ok().and_then(char::from_u32),
+            _ if entity.starts_with('#') => entity[1..].parse().ok().and_then(char::from_u32),
+            _ => None,
+        };
+        match c {
+            Some(c) => out.push(c),
+            None => return Err(XmlError::UnknownEntity { line, entity: entity.to_string() }),
+        }
+        rest = &rest[semi + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn elements_attributes_and_text() {
+        let root = parse("<device a=\"1\" b='two'><name> UART </name><empty/></device>").unwrap();
+        assert_eq!(root.name, "device");
+        assert_eq!(root.attribute("a"), Some("1"));
+        assert_eq!(root.attribute("b"), Some("two"));
+        assert_eq!(root.child_text("name"), Some("UART"));
+        assert_eq!(root.child("empty").map(|e| e.children.len()), Some(0));
+        assert_eq!(root.children.len(), 2);
+    }
+
+    #[test]
+    fn prolog_doctype_and_comments_are_skipped() {
+        let source = "<?xml version=\"1.0\"?>\n<!DOCTYPE device>\n<!-- before -->\n\
+                      <device><!-- inside --><?pi ignored?><name>x</name></device>\n<!-- after -->\n";
+        let root = parse(source).unwrap();
+        assert_eq!(root.children.len(), 1);
+        assert_eq!(root.child_text("name"), Some("x"));
+    }
+
+    #[test]
+    fn entities_in_text_and_attributes() {
+        let root = parse("<d t=\"&quot;&apos;\">&lt;a&gt; &amp; &#65;&#x42;</d>").unwrap();
+        assert_eq!(root.text, "<a> & AB");
+        assert_eq!(root.attribute("t"), Some("\"'"));
+    }
+
+    #[test]
+    fn cdata_is_kept_verbatim() {
+        let root = parse("<d>a<![CDATA[<b> & &c;]]>d</d>").unwrap();
+        assert_eq!(root.text, "a<b> & &c;d");
+    }
+
+    #[test]
+    fn errors() {
+        assert!(matches!(parse("<a></b>"), Err(XmlError::MismatchedTag { line: 1, .. })));
+        assert!(matches!(parse("<a>\n&nbsp;</a>"), Err(XmlError::UnknownEntity { line: 2, .. })));
+        assert!(matches!(parse("<a><b>"), Err(XmlError::UnexpectedEnd)));
+        assert!(matches!(parse("<a x=1/>"), Err(XmlError::Expected { .. })));
+        assert!(matches!(parse("<a/><b/>"), Err(XmlError::Expected { .. })));
+    }
+}
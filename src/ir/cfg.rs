@@ -1,5 +1,6 @@
 use crate::ir::{Instruction, Op, VirtualRegister};
-use crate::frontend::ast::{TypeStateSet, TypeParam};
+use crate::frontend::ast::{Inline, TypeStateSet, TypeParam};
+use std::collections::HashMap;
 
 pub type BlockId = usize;
 
@@ -7,6 +8,9 @@ pub type BlockId = usize;
 pub struct CFG {
     pub blocks: Vec<BasicBlock>,
     pub entry: BlockId,
+    pub params: usize,      // Number of arguments the function is defined with
+    pub inline: Inline,     // #[inline] / #[noinline] on the function
+    pub names: HashMap<VirtualRegister, String>,    // Source variable a register holds, where known
 }
 
 impl CFG {
@@ -14,6 +18,9 @@ impl CFG {
         Self {
             blocks: Vec::new(),
             entry: 0,
+            params: 0,
+            inline: Inline::Auto,
+            names: HashMap::new(),
         }
     }
 
@@ -31,22 +38,88 @@ impl CFG {
         &self.blocks[id]
     }
 
+    // Whether any instruction reads or writes a u64 register
+    pub fn accesses_u64(&self) -> bool {
+        self.blocks.iter()
+            .flat_map(|b| &b.instructions)
+            .any(|i| matches!(i.operation, Op::LoadDouble(_) | Op::StoreDouble(_)))
+    }
+
+    // First register id not used anywhere in the function
+    pub fn next_register(&self) -> usize {
+        self.blocks.iter()
+            .flat_map(|b| {
+                b.instructions.iter()
+                    .flat_map(|i| i.destination.iter().chain(&i.args).copied().collect::<Vec<_>>())
+                    .chain(b.terminator.uses())
+            })
+            .map(|v| v.id + 1)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /* Drop blocks not reachable from the entry and renumber the rest, keeping their order.
+     * Phis lose the operands of blocks that no longer jump to them, and a phi left with a
+     * single operand becomes a Mov. */
+    pub fn remove_unreachable(&mut self) {
+        let mut reachable = vec![false; self.blocks.len()];
+        let mut stack = vec![self.entry];
+        while let Some(id) = stack.pop() {
+            if !std::mem::replace(&mut reachable[id], true) {
+                stack.extend(self.blocks[id].terminator.successors());
+            }
+        }
+
+        let mut renumber = vec![0; self.blocks.len()];
+        let mut next = 0;
+        for (id, &live) in reachable.iter().enumerate() {
+            renumber[id] = next;
+            next += live as usize;
+        }
+
+        self.blocks.retain(|b| reachable[b.id]);
+        for block in &mut self.blocks {
+            block.id = renumber[block.id];
+            block.terminator.retarget(|target| renumber[target]);
+        }
+        self.entry = renumber[self.entry];
+
+        let predecessors = crate::ir::dominators::predecessors(self);
+        for block in &mut self.blocks {
+            for instr in &mut block.instructions {
+                let Op::Phi(preds) = &instr.operation else { continue };
+                let (preds, args): (Vec<BlockId>, Vec<VirtualRegister>) = preds.iter()
+                    .zip(&instr.args)
+                    .filter(|(pred, _)| reachable[**pred])
+                    .map(|(pred, arg)| (renumber[*pred], *arg))
+                    .filter(|(pred, _)| predecessors[block.id].contains(pred))
+                    .unzip();
+
+                instr.operation = if args.len() == 1 { Op::Mov } else { Op::Phi(preds) };
+                instr.args = args;
+            }
+        }
+    }
+
     /*
-     * TODO: Fix temporary fix below
-     * 
      * Flatten CFG back to linear instruction stream (backend currently uses this)
-     * - For each block (except entry which uses function name), give a label
-     * - Emit all instructions in the block
-     * - Convert the terminator to instruction(s)
+     * - Order the blocks with layout::arrange, the entry first (labelled by the function name)
+     * - Give every other block a label and emit its instructions
+     * - Convert the terminator to instruction(s), leaving out jumps to the next block
      */
-    pub fn flatten(&self, func_name: &str) -> Vec<Instruction> {
+    pub fn flatten(&self, func_name: &str) -> Result<Vec<Instruction>, String> {
+        let (cfg, order) = crate::ir::layout::arrange(self, func_name)?;
         let mut instructions = Vec::new();
         let label = |id: usize| format!(".LBB_{}_{}", func_name, id);
+        let jump = |target: BlockId| Instruction::new(Op::Jump(label(target)), None, vec![]);
 
-        for block in &self.blocks {
-            if block.id != self.entry {
+        for (position, &id) in order.iter().enumerate() {
+            let block = cfg.block(id);
+            let next = order.get(position + 1).copied();
+
+            if id != cfg.entry {
                 instructions.push(Instruction::new(
-                    Op::Label(label(block.id)),
+                    Op::Label(label(id)),
                     None,
                     vec![],
                 ));
@@ -56,45 +129,57 @@ impl CFG {
 
             match &block.terminator {
                 Terminator::Jump(target) => {
-                    instructions.push(Instruction::new(
-                        Op::Jump(label(*target)),
-                        None,
-                        vec![],
-                    ));
+                    if Some(*target) != next {
+                        instructions.push(jump(*target));
+                    }
                 }
 
+                // Branch away from whichever side does not follow, jumping to it if neither does
                 Terminator::Branch { cond, then_block, else_block } => {
-                    instructions.push(Instruction::new(
-                        Op::BranchIfFalse(label(*else_block)),
-                        None,
-                        vec![*cond],
-                    ));
-
-                    if *then_block != block.id + 1 {
+                    if Some(*else_block) == next {
+                        instructions.push(Instruction::new(
+                            Op::BranchIfTrue(label(*then_block)),
+                            None,
+                            vec![*cond],
+                        ));
+                    } else {
                         instructions.push(Instruction::new(
-                            Op::Jump(label(*then_block)),
+                            Op::BranchIfFalse(label(*else_block)),
                             None,
-                            vec![],
+                            vec![*cond],
                         ));
+                        if Some(*then_block) != next {
+                            instructions.push(jump(*then_block));
+                        }
                     }
                 }
 
+                // BranchCond branches when the comparison is false
                 Terminator::CondBranch { op, lhs, rhs, then_block, else_block } => {
-                    instructions.push(Instruction::new(
-                        Op::BranchCond(*op, label(*else_block)),
-                        None,
-                        vec![*lhs, *rhs],
-                    ));
-
-                    if *then_block != block.id + 1 {
+                    if Some(*else_block) == next {
+                        instructions.push(Instruction::new(
+                            Op::BranchCond(op.negate(), label(*then_block)),
+                            None,
+                            vec![*lhs, *rhs],
+                        ));
+                    } else {
                         instructions.push(Instruction::new(
-                            Op::Jump(label(*then_block)),
+                            Op::BranchCond(*op, label(*else_block)),
                             None,
-                            vec![],
+                            vec![*lhs, *rhs],
                         ));
+                        if Some(*then_block) != next {
+                            instructions.push(jump(*then_block));
+                        }
                     }
                 }
 
+                // A tail call already left the function
+                Terminator::Return(_) if matches!(
+                    block.instructions.last().map(|i| &i.operation),
+                    Some(Op::TailCall(_)),
+                ) => {}
+
                 Terminator::Return(val) => {
                     instructions.push(Instruction::new(
                         Op::Ret(*val),
@@ -104,12 +189,12 @@ impl CFG {
                 }
 
                 Terminator::None => {
-                    // No terminator shouldn't happen in a well formed CFG :)
+                    unreachable!("layout::arrange rejects reachable blocks without a terminator")
                 }
             }
         }
-        
-        instructions
+
+        Ok(instructions)
     }
 }
 
@@ -195,6 +280,19 @@ pub enum Expr {
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CmpOp { Eq, Ne, Lt, Le, Gt, Ge }
 
+impl CmpOp {
+    pub fn negate(self) -> CmpOp {
+        match self {
+            CmpOp::Eq => CmpOp::Ne,
+            CmpOp::Ne => CmpOp::Eq,
+            CmpOp::Lt => CmpOp::Ge,
+            CmpOp::Ge => CmpOp::Lt,
+            CmpOp::Le => CmpOp::Gt,
+            CmpOp::Gt => CmpOp::Le,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum Terminator {
     Jump(BlockId),          // Unconditional jump to another block
@@ -213,3 +311,45 @@ pub enum Terminator {
     Return(Option<VirtualRegister>),    // Return from the function, optionally with a value
     None,
 }
+
+impl Terminator {
+    pub fn successors(&self) -> Vec<BlockId> {
+        match self {
+            Terminator::Jump(target) => vec![*target],
+            Terminator::Branch { then_block, else_block, .. } => vec![*then_block, *else_block],
+            Terminator::CondBranch { then_block, else_block, .. } => vec![*then_block, *else_block],
+            Terminator::Return(_) | Terminator::None => vec![],
+        }
+    }
+
+    // Registers read by the terminator
+    pub fn uses(&self) -> Vec<VirtualRegister> {
+        match self {
+            Terminator::Branch { cond, .. } => vec![*cond],
+            Terminator::CondBranch { lhs, rhs, .. } => vec![*lhs, *rhs],
+            Terminator::Return(Some(reg)) => vec![*reg],
+            _ => vec![],
+        }
+    }
+
+    pub fn uses_mut(&mut self) -> Vec<&mut VirtualRegister> {
+        match self {
+            Terminator::Branch { cond, .. } => vec![cond],
+            Terminator::CondBranch { lhs, rhs, .. } => vec![lhs, rhs],
+            Terminator::Return(Some(reg)) => vec![reg],
+            _ => vec![],
+        }
+    }
+
+    pub fn retarget(&mut self, mut f: impl FnMut(BlockId) -> BlockId) {
+        match self {
+            Terminator::Jump(target) => *target = f(*target),
+            Terminator::Branch { then_block, else_block, .. }
+            | Terminator::CondBranch { then_block, else_block, .. } => {
+                *then_block = f(*then_block);
+                *else_block = f(*else_block);
+            }
+            Terminator::Return(_) | Terminator::None => {}
+        }
+    }
+}
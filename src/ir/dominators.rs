@@ -0,0 +1,210 @@
+use std::collections::HashSet;
+use crate::ir::cfg::{CFG, BlockId};
+
+/* Dominator tree and dominance frontiers of a CFG
+ *
+ *        0             idom(1) = idom(2) = idom(3) = 0
+ *       / \
+ *      1   2           DF(1) = DF(2) = {3}
+ *       \ /
+ *        3
+ *
+ * Immediate dominators are computed with the iterative algorithm of Cooper, Harvey and
+ * Kennedy ("A Simple, Fast Dominance Algorithm") over reverse postorder. Blocks not
+ * reachable from the entry have no immediate dominator and an empty frontier. */
+#[derive(Debug, Clone)]
+pub struct Dominators {
+    pub idom: Vec<Option<BlockId>>,         // Immediate dominator, None for the entry
+    pub children: Vec<Vec<BlockId>>,        // Dominator tree edges
+    pub frontiers: Vec<HashSet<BlockId>>,   // Dominance frontier of each block
+    pub predecessors: Vec<Vec<BlockId>>,
+}
+
+impl Dominators {
+    pub fn compute(cfg: &CFG) -> Dominators {
+        let n = cfg.blocks.len();
+        let predecessors = predecessors(cfg);
+
+        let order = reverse_postorder(cfg);
+        let mut rpo_index = vec![usize::MAX; n];
+        for (i, &block) in order.iter().enumerate() {
+            rpo_index[block] = i;
+        }
+
+        let mut idom: Vec<Option<BlockId>> = vec![None; n];
+        idom[cfg.entry] = Some(cfg.entry);
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &block in order.iter().skip(1) {
+                let mut new_idom = None;
+                for &pred in &predecessors[block] {
+                    if idom[pred].is_none() {
+                        continue;
+                    }
+                    new_idom = Some(match new_idom {
+                        None => pred,
+                        Some(current) => intersect(&idom, &rpo_index, pred, current),
+                    });
+                }
+                if new_idom.is_some() && idom[block] != new_idom {
+                    idom[block] = new_idom;
+                    changed = true;
+                }
+            }
+        }
+        idom[cfg.entry] = None;
+
+        let mut children = vec![Vec::new(); n];
+        for &block in &order {
+            if let Some(parent) = idom[block] {
+                children[parent].push(block);
+            }
+        }
+
+        // A join point is in the frontier of every block between its predecessors and its idom
+        let mut frontiers = vec![HashSet::new(); n];
+        for &block in &order {
+            if predecessors[block].len() < 2 {
+                continue;
+            }
+            for &pred in &predecessors[block] {
+                if rpo_index[pred] == usize::MAX {
+                    continue;
+                }
+                let mut runner = pred;
+                while Some(runner) != idom[block] {
+                    frontiers[runner].insert(block);
+                    match idom[runner] {
+                        Some(parent) => runner = parent,
+                        None => break,
+                    }
+                }
+            }
+        }
+
+        Dominators { idom, children, frontiers, predecessors }
+    }
+
+    // Whether every path from the entry to `block` passes through `dominator`
+    pub fn dominates(&self, dominator: BlockId, mut block: BlockId) -> bool {
+        loop {
+            if block == dominator {
+                return true;
+            }
+            match self.idom[block] {
+                Some(parent) => block = parent,
+                None => return false,
+            }
+        }
+    }
+}
+
+fn intersect(idom: &[Option<BlockId>], rpo_index: &[usize], mut a: BlockId, mut b: BlockId) -> BlockId {
+    while a != b {
+        while rpo_index[a] > rpo_index[b] {
+            a = idom[a].unwrap();
+        }
+        while rpo_index[b] > rpo_index[a] {
+            b = idom[b].unwrap();
+        }
+    }
+    a
+}
+
+pub fn predecessors(cfg: &CFG) -> Vec<Vec<BlockId>> {
+    let mut predecessors = vec![Vec::new(); cfg.blocks.len()];
+    for block in &cfg.blocks {
+        for succ in block.terminator.successors() {
+            if !predecessors[succ].contains(&block.id) {
+                predecessors[succ].push(block.id);
+            }
+        }
+    }
+    predecessors
+}
+
+// Blocks reachable from the entry, each before its successors except along back edges
+fn reverse_postorder(cfg: &CFG) -> Vec<BlockId> {
+    let mut visited = vec![false; cfg.blocks.len()];
+    let mut postorder = Vec::new();
+    let mut stack = vec![(cfg.entry, 0)];
+    visited[cfg.entry] = true;
+
+    while let Some((block, next)) = stack.pop() {
+        let successors = cfg.blocks[block].terminator.successors();
+        match successors.get(next) {
+            Some(&succ) => {
+                stack.push((block, next + 1));
+                if !visited[succ] {
+                    visited[succ] = true;
+                    stack.push((succ, 0));
+                }
+            }
+            None => postorder.push(block),
+        }
+    }
+
+    postorder.reverse();
+    postorder
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::VirtualRegister;
+    use crate::ir::cfg::Terminator;
+
+    // A CFG of empty blocks with the given terminators, block 0 the entry
+    fn graph(terminators: Vec<Terminator>) -> CFG {
+        let mut cfg = CFG::new();
+        for terminator in terminators {
+            let id = cfg.add_block();
+            cfg.block_mut(id).terminator = terminator;
+        }
+        cfg
+    }
+
+    fn branch(then_block: BlockId, else_block: BlockId) -> Terminator {
+        Terminator::Branch { cond: VirtualRegister { id: 0 }, then_block, else_block }
+    }
+
+    fn set(blocks: &[BlockId]) -> HashSet<BlockId> {
+        blocks.iter().copied().collect()
+    }
+
+    #[test]
+    fn diamond() {
+        let cfg = graph(vec![branch(1, 2), Terminator::Jump(3), Terminator::Jump(3), Terminator::Return(None)]);
+        let doms = Dominators::compute(&cfg);
+
+        assert_eq!(doms.idom, [None, Some(0), Some(0), Some(0)]);
+        assert_eq!(set(&doms.children[0]), set(&[1, 2, 3]));
+        assert_eq!(doms.frontiers, [set(&[]), set(&[3]), set(&[3]), set(&[])]);
+        assert!(doms.dominates(0, 3) && !doms.dominates(1, 3) && doms.dominates(3, 3));
+    }
+
+    #[test]
+    fn loop_header_is_in_the_frontier_of_its_body() {
+        // 0 -> 1 <-> 2, 1 -> 3
+        let cfg = graph(vec![Terminator::Jump(1), branch(2, 3), Terminator::Jump(1), Terminator::Return(None)]);
+        let doms = Dominators::compute(&cfg);
+
+        assert_eq!(doms.idom, [None, Some(0), Some(1), Some(1)]);
+        assert_eq!(doms.frontiers, [set(&[]), set(&[1]), set(&[1]), set(&[])]);
+        assert_eq!(doms.predecessors[1], [0, 2]);
+        assert!(doms.dominates(1, 2) && !doms.dominates(2, 1));
+    }
+
+    #[test]
+    fn unreachable_blocks_have_no_dominator() {
+        // 0 -> 1, and 2 -> 1 with nothing reaching 2
+        let cfg = graph(vec![Terminator::Jump(1), Terminator::Return(None), Terminator::Jump(1)]);
+        let doms = Dominators::compute(&cfg);
+
+        assert_eq!(doms.idom, [None, Some(0), None]);
+        assert!(doms.frontiers.iter().all(|f| f.is_empty()));
+        assert!(!doms.dominates(0, 2));
+    }
+}
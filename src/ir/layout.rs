@@ -0,0 +1,213 @@
+use crate::ir::cfg::{CFG, BasicBlock, BlockId, Terminator};
+use crate::ir::dominators::{self, Dominators};
+
+/* Block layout for CFG::flatten
+ *
+ *   0: br t0, 1, 2              beqz t0, .LBB_f_4
+ *   1: ...; j 3                 ...; j .LBB_f_3
+ *   2: j 4                  ->  .LBB_f_4: ...
+ *   3: ...                      .LBB_f_3: ...
+ *   4: ...; j 3
+ *
+ * Blocks holding nothing but a jump are removed and branches to them go straight to
+ * where the chain ends. Blocks are then placed so each one is followed by a successor
+ * whose other predecessors (ignoring loop back edges) are already placed, preferring
+ * the `then` side of a branch. When the current block has no such successor, the most
+ * recently placed block that does continues the trace, so nested branches stay
+ * together. flatten() inverts branches whose `else` block falls through. */
+pub fn arrange(cfg: &CFG, function: &str) -> Result<(CFG, Vec<BlockId>), String> {
+    validate(cfg, function)?;
+
+    let mut cfg = cfg.clone();
+    forward_jumps(&mut cfg);
+    let order = order(&cfg);
+    Ok((cfg, order))
+}
+
+fn validate(cfg: &CFG, function: &str) -> Result<(), String> {
+    let internal = |message: String| Err(format!("internal error in '{}': {}", function, message));
+
+    if cfg.entry >= cfg.blocks.len() {
+        return internal(format!("entry block {} does not exist", cfg.entry));
+    }
+
+    for (index, block) in cfg.blocks.iter().enumerate() {
+        if block.id != index {
+            return internal(format!("block {} is stored at index {}", block.id, index));
+        }
+        if let Some(target) = block.terminator.successors().into_iter().find(|&t| t >= cfg.blocks.len()) {
+            return internal(format!("block {} jumps to non-existent block {}", block.id, target));
+        }
+    }
+
+    // Blocks without a terminator are only an error where control can reach them
+    let mut reachable = vec![false; cfg.blocks.len()];
+    let mut stack = vec![cfg.entry];
+    while let Some(id) = stack.pop() {
+        if std::mem::replace(&mut reachable[id], true) {
+            continue;
+        }
+        if matches!(cfg.blocks[id].terminator, Terminator::None) {
+            return internal(format!("block {} has no terminator", id));
+        }
+        stack.extend(cfg.blocks[id].terminator.successors());
+    }
+
+    Ok(())
+}
+
+// Retarget every edge past blocks that only jump elsewhere
+fn forward_jumps(cfg: &mut CFG) {
+    let entry = cfg.entry;
+    let destination = |mut target: BlockId, blocks: &[BasicBlock]| {
+        for _ in 0..blocks.len() {
+            let block = &blocks[target];
+            match block.terminator {
+                Terminator::Jump(next) if block.id != entry && block.instructions.is_empty() => target = next,
+                _ => break,
+            }
+        }
+        target
+    };
+
+    for id in 0..cfg.blocks.len() {
+        let mut terminator = cfg.blocks[id].terminator.clone();
+        terminator.retarget(|target| destination(target, &cfg.blocks));
+
+        // Both sides of a branch reaching the same block make it a jump
+        terminator = match terminator {
+            Terminator::Branch { then_block, else_block, .. }
+            | Terminator::CondBranch { then_block, else_block, .. } if then_block == else_block => {
+                Terminator::Jump(then_block)
+            }
+            other => other,
+        };
+        cfg.blocks[id].terminator = terminator;
+    }
+}
+
+fn order(cfg: &CFG) -> Vec<BlockId> {
+    let doms = Dominators::compute(cfg);
+    let predecessors = dominators::predecessors(cfg);
+
+    let reachable: Vec<bool> = (0..cfg.blocks.len())
+        .map(|id| id == cfg.entry || doms.idom[id].is_some())
+        .collect();
+    let total = reachable.iter().filter(|&&r| r).count();
+
+    let mut placed = vec![false; cfg.blocks.len()];
+    let mut order = Vec::with_capacity(total);
+
+    let ready = |block: BlockId, placed: &[bool]| {
+        !placed[block]
+            && predecessors[block].iter()
+                .all(|&pred| placed[pred] || !reachable[pred] || doms.dominates(block, pred))
+    };
+
+    let mut current = cfg.entry;
+    loop {
+        placed[current] = true;
+        order.push(current);
+        if order.len() == total {
+            break;
+        }
+
+        let next = order.iter().rev()
+            .flat_map(|&block| cfg.blocks[block].terminator.successors())
+            .find(|&succ| ready(succ, &placed))
+            .or_else(|| (0..cfg.blocks.len()).find(|&b| reachable[b] && !placed[b]));
+
+        match next {
+            Some(block) => current = block,
+            None => break,
+        }
+    }
+
+    order
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::{Instruction, Op, VirtualRegister};
+
+    fn branch(then_block: BlockId, else_block: BlockId) -> Terminator {
+        Terminator::Branch { cond: VirtualRegister { id: 0 }, then_block, else_block }
+    }
+
+    // Blocks with the given terminators, `true` for one holding an instruction
+    fn graph(blocks: Vec<(bool, Terminator)>) -> CFG {
+        let mut cfg = CFG::new();
+        for (work, terminator) in blocks {
+            let id = cfg.add_block();
+            if work {
+                let instr = Instruction::new(Op::LoadImm(0), Some(VirtualRegister { id }), vec![]);
+                cfg.block_mut(id).instructions.push(instr);
+            }
+            cfg.block_mut(id).terminator = terminator;
+        }
+        cfg
+    }
+
+    #[test]
+    fn forwards_jumps_and_places_successors_once_their_predecessors_are() {
+        // The example above
+        let cfg = graph(vec![
+            (true, branch(1, 2)),
+            (true, Terminator::Jump(3)),
+            (false, Terminator::Jump(4)),
+            (true, Terminator::Return(None)),
+            (true, Terminator::Jump(3)),
+        ]);
+        let (arranged, order) = arrange(&cfg, "f").unwrap();
+        assert!(matches!(arranged.block(0).terminator, Terminator::Branch { then_block: 1, else_block: 4, .. }));
+        assert_eq!(order, [0, 1, 4, 3]);
+    }
+
+    #[test]
+    fn branch_to_one_block_becomes_a_jump() {
+        let cfg = graph(vec![
+            (true, branch(1, 2)),
+            (false, Terminator::Jump(3)),
+            (false, Terminator::Jump(3)),
+            (true, Terminator::Return(None)),
+        ]);
+        let (arranged, order) = arrange(&cfg, "f").unwrap();
+        assert!(matches!(arranged.block(0).terminator, Terminator::Jump(3)));
+        assert_eq!(order, [0, 3]);
+    }
+
+    #[test]
+    fn loop_body_follows_its_header() {
+        let cfg = graph(vec![
+            (true, Terminator::Jump(1)),
+            (true, branch(2, 3)),
+            (true, Terminator::Jump(1)),
+            (true, Terminator::Return(None)),
+        ]);
+        assert_eq!(arrange(&cfg, "f").unwrap().1, [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn rejects_malformed_cfgs() {
+        let error = |cfg: &CFG| arrange(cfg, "f").unwrap_err();
+
+        let mut cfg = graph(vec![(true, Terminator::Return(None))]);
+        cfg.entry = 1;
+        assert_eq!(error(&cfg), "internal error in 'f': entry block 1 does not exist");
+
+        let mut cfg = graph(vec![(true, Terminator::Jump(1)), (true, Terminator::Return(None))]);
+        cfg.blocks.swap(0, 1);
+        assert_eq!(error(&cfg), "internal error in 'f': block 1 is stored at index 0");
+
+        let cfg = graph(vec![(true, branch(1, 2)), (true, Terminator::Return(None))]);
+        assert_eq!(error(&cfg), "internal error in 'f': block 0 jumps to non-existent block 2");
+
+        let cfg = graph(vec![(true, Terminator::Jump(1)), (true, Terminator::None)]);
+        assert_eq!(error(&cfg), "internal error in 'f': block 1 has no terminator");
+
+        // Unless nothing reaches it
+        let cfg = graph(vec![(true, Terminator::Return(None)), (true, Terminator::None)]);
+        assert_eq!(arrange(&cfg, "f").unwrap().1, [0]);
+    }
+}
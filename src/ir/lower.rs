@@ -8,6 +8,7 @@ struct Context<'a> {
     global_constants: HashMap<String, i32>,
     peripherals: &'a [ast::Peripheral],
     signatures: HashMap<String, &'a ast::TypeState>,
+    arguments: HashMap<String, &'a [ast::Argument]>,
     cfg: CFG,
     current_block: BlockId,
     next_register: usize,
@@ -17,6 +18,7 @@ impl<'a> Context<'a> {
     fn new(
         peripherals: &'a [ast::Peripheral],
         signatures: HashMap<String, &'a ast::TypeState>,
+        arguments: HashMap<String, &'a [ast::Argument]>,
         global_constants: HashMap<String, i32>,
     ) -> Self {
         let mut cfg = CFG::new();
@@ -27,12 +29,19 @@ impl<'a> Context<'a> {
             global_constants,
             peripherals,
             signatures,
+            arguments,
             cfg,
             current_block: entry,
             next_register: 0,
         }
     }
 
+    // The first variable bound to a register names it
+    fn bind(&mut self, name: &str, reg: VirtualRegister) {
+        self.vars.insert(name.to_string(), reg);
+        self.cfg.names.entry(reg).or_insert_with(|| name.to_string());
+    }
+
     fn new_register(&mut self) -> VirtualRegister {
         let r = VirtualRegister { id: self.next_register };
         self.next_register += 1;
@@ -70,14 +79,20 @@ impl<'a> Context<'a> {
         *self.vars.get(name).expect(&format!("Variable {} not found", name))
     }
 
-    fn get_mmio_address(&self, peripheral_name: &str, register_name: &str) -> Option<u32> {
+    // (base, offset, is_u64): the offset is folded into the base if it does not fit a load/store
+    // immediate, and u64 registers are accessed with ld/sd
+    fn get_mmio_address(&self, peripheral_name: &str, register_name: &str) -> Option<(u32, i32, bool)> {
         for p in self.peripherals {
             if p.name == peripheral_name {
                 let base = p.base_address?;
                 for block in &p.register_blocks {
                     for reg in &block.registers {
                         if reg.name == register_name {
-                            return Some(base + reg.offset);
+                            let double = block.reg_type == ast::RegisterType::U64;
+                            return Some(match i32::try_from(reg.offset) {
+                                Ok(offset) if offset < 2048 => (base, offset, double),
+                                _ => (base + reg.offset, 0, double),
+                            });
                         }
                     }
                 }
@@ -89,15 +104,17 @@ impl<'a> Context<'a> {
 
 pub fn lower(prog: &ast::Program) -> Vec<(String, CFG)> {
     let mut signatures = HashMap::new();
+    let mut arguments = HashMap::new();
     for func in &prog.functions {
         if let Some(sig) = &func.signature {
             signatures.insert(func.name.clone(), sig);
         }
+        arguments.insert(func.name.clone(), func.args.as_slice());
     }
 
     let mut lowered_functions = Vec::new();
     for func in &prog.functions {
-        let cfg = lower_function(func, &prog.peripherals, &signatures, &prog.constants);
+        let cfg = lower_function(func, &prog.peripherals, &signatures, &arguments, &prog.constants);
         lowered_functions.push((func.name.clone(), cfg));
     }
     lowered_functions
@@ -107,6 +124,7 @@ fn lower_function(
     func: &ast::Function,
     peripherals: &[ast::Peripheral],
     signatures: &HashMap<String, &ast::TypeState>,
+    arguments: &HashMap<String, &[ast::Argument]>,
     global_constants: &[(String, ast::Expr)],
 ) -> CFG {
     let consts_map: HashMap<String, i32> = global_constants
@@ -116,11 +134,13 @@ fn lower_function(
         })
         .collect();
 
-    let mut ctx = Context::new(peripherals, signatures.clone(), consts_map);
+    let mut ctx = Context::new(peripherals, signatures.clone(), arguments.clone(), consts_map);
+    ctx.cfg.params = func.args.len();
+    ctx.cfg.inline = func.inline;
     
-    for (i, (name, _type)) in func.args.iter().enumerate() {
+    for (i, arg) in func.args.iter().enumerate() {
         let reg = ctx.new_register();
-        ctx.vars.insert(name.clone(), reg);
+        ctx.bind(&arg.name, reg);
         ctx.emit_instr(Instruction::new(
             Op::MovArg(i), 
             Some(reg),
@@ -149,7 +169,7 @@ fn lower_statement(ctx: &mut Context, stmt: &ast::Statement) {
             });
             
             let result_reg = lower_expression(ctx, value);
-            ctx.vars.insert(var_name.clone(), result_reg);
+            ctx.bind(var_name, result_reg);
         }
 
         ast::Statement::Assign { var_name, value } => {
@@ -287,22 +307,26 @@ fn lower_statement(ctx: &mut Context, stmt: &ast::Statement) {
             
             let value_reg = lower_expression(ctx, value);
             
-            let addr = ctx.get_mmio_address(peripheral, register)
+            let (base, offset, double) = ctx.get_mmio_address(peripheral, register)
                 .expect(&format!("Unknown peripheral register {}.{}", peripheral, register));
             
             let addr_reg = ctx.new_register();
             ctx.emit_instr(Instruction::new(
-                Op::LoadAddr(addr),
+                Op::LoadAddr(base),
                 Some(addr_reg),
                 vec![]
             ));
             
             ctx.emit_instr(Instruction::new(
-                Op::StoreWord,
+                if double { Op::StoreDouble(offset) } else { Op::StoreWord(offset) },
                 None,
                 vec![value_reg, addr_reg]
             ));
         }
+
+        ast::Statement::Reach { .. } => {
+            unreachable!("reach statements are expanded by analysis::synthesis before lowering")
+        }
     }
 }
 
@@ -357,6 +381,14 @@ fn lower_expression(ctx: &mut Context, expr: &ast::Expr) -> VirtualRegister {
             for arg in args {
                 arg_regs.push(lower_expression(ctx, arg));
             }
+
+            // Omitted trailing arguments take their declared defaults
+            let params = ctx.arguments.get(name).copied().unwrap_or_default();
+            for param in params.iter().skip(args.len()) {
+                if let Some(default) = &param.default {
+                    arg_regs.push(lower_expression(ctx, default));
+                }
+            }
             
             let dest = ctx.new_register();
             ctx.emit_instr(Instruction::new(
@@ -368,25 +400,31 @@ fn lower_expression(ctx: &mut Context, expr: &ast::Expr) -> VirtualRegister {
         }
 
         ast::Expr::PeripheralRead { peripheral, register } => {
-            let addr = ctx.get_mmio_address(peripheral, register)
+            let (base, offset, double) = ctx.get_mmio_address(peripheral, register)
                 .expect(&format!("Unknown peripheral register {}.{}", peripheral, register));
             
             let addr_reg = ctx.new_register();
             ctx.emit_instr(Instruction::new(
-                Op::LoadAddr(addr),
+                Op::LoadAddr(base),
                 Some(addr_reg),
                 vec![]
             ));
             
             let dest = ctx.new_register();
             ctx.emit_instr(Instruction::new(
-                Op::LoadWord,
+                if double { Op::LoadDouble(offset) } else { Op::LoadWord(offset) },
                 Some(dest),
                 vec![addr_reg]
             ));
             dest
         }
         
+        ast::Expr::Binary { op, left, right } if is_comparison(op) => {
+            let left_reg = lower_expression(ctx, left);
+            let right_reg = lower_expression(ctx, right);
+            lower_comparison(ctx, op, left_reg, right_reg)
+        }
+
         ast::Expr::Binary { op, left, right } => {
             let left_reg = lower_expression(ctx, left);
             let right_reg = lower_expression(ctx, right);
@@ -407,8 +445,7 @@ fn lower_expression(ctx: &mut Context, expr: &ast::Expr) -> VirtualRegister {
                 ast::BinaryOp::Or     => Op::Or,
                 ast::BinaryOp::Eq | ast::BinaryOp::Ne |
                 ast::BinaryOp::Lt | ast::BinaryOp::Le |
-                ast::BinaryOp::Gt | ast::BinaryOp::Ge =>
-                    unimplemented!("comparison as stored value not yet supported; use in if/while condition"),
+                ast::BinaryOp::Gt | ast::BinaryOp::Ge => unreachable!(),
             };
 
             let dest = ctx.new_register();
@@ -440,6 +477,58 @@ fn lower_expression(ctx: &mut Context, expr: &ast::Expr) -> VirtualRegister {
     }
 }
 
+fn is_comparison(op: &ast::BinaryOp) -> bool {
+    matches!(
+        op,
+        ast::BinaryOp::Eq | ast::BinaryOp::Ne |
+        ast::BinaryOp::Lt | ast::BinaryOp::Le |
+        ast::BinaryOp::Gt | ast::BinaryOp::Ge
+    )
+}
+
+/* Comparisons used as values, producing 0 or 1
+ *
+ *   a < b    slt a, b            a <= b   slt b, a; xor 1
+ *   a > b    slt b, a            a >= b   slt a, b; xor 1
+ *   a == b   xor a, b; sltu 1    a != b   xor a, b; sltu 1; xor 1
+ */
+fn lower_comparison(
+    ctx: &mut Context,
+    op: &ast::BinaryOp,
+    left: VirtualRegister,
+    right: VirtualRegister,
+) -> VirtualRegister {
+    let emit = |ctx: &mut Context, op: Op, args: Vec<VirtualRegister>| {
+        let dest = ctx.new_register();
+        ctx.emit_instr(Instruction::new(op, Some(dest), args));
+        dest
+    };
+    let invert = |ctx: &mut Context, reg: VirtualRegister| {
+        let one = emit(ctx, Op::LoadImm(1), vec![]);
+        emit(ctx, Op::Xor, vec![reg, one])
+    };
+
+    match op {
+        ast::BinaryOp::Lt => emit(ctx, Op::Slt, vec![left, right]),
+        ast::BinaryOp::Gt => emit(ctx, Op::Slt, vec![right, left]),
+        ast::BinaryOp::Le => {
+            let gt = emit(ctx, Op::Slt, vec![right, left]);
+            invert(ctx, gt)
+        }
+        ast::BinaryOp::Ge => {
+            let lt = emit(ctx, Op::Slt, vec![left, right]);
+            invert(ctx, lt)
+        }
+        ast::BinaryOp::Eq | ast::BinaryOp::Ne => {
+            let diff = emit(ctx, Op::Xor, vec![left, right]);
+            let one = emit(ctx, Op::LoadImm(1), vec![]);
+            let eq = emit(ctx, Op::Sltu, vec![diff, one]);
+            if matches!(op, ast::BinaryOp::Ne) { invert(ctx, eq) } else { eq }
+        }
+        _ => unreachable!(),
+    }
+}
+
 // Convert AST expression to CFG expression
 fn ast_expr_to_cfg(expr: &ast::Expr) -> Expr {
     match expr {
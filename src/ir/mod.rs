@@ -1,5 +1,9 @@
 pub mod lower;
 pub mod cfg;
+pub mod dominators;
+pub mod layout;
+pub mod ssa;
+pub mod opt;
 
 pub use cfg::CmpOp;
 #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
@@ -19,15 +23,19 @@ pub struct Instruction {
 pub enum Op {
     LoadImm(i32),                   // li t0, 5
     LoadAddr(u32),                  // li t0, 0x40000000
-    LoadWord,                       // lw t1, 0(t0)
-    StoreWord,                      // sw t0, 0(t1)
+    LoadWord(i32),                  // lw t1, offset(t0)
+    StoreWord(i32),                 // sw t0, offset(t1)
+    LoadDouble(i32),                // ld t1, offset(t0) (u64 registers, RV64 only)
+    StoreDouble(i32),               // sd t0, offset(t1)
     Mov,                            // mv t1, t0
     MovArg(usize),                  // mv a0, t1
     Call(String),                   // call func
+    TailCall(String),               // tail func (after frame teardown)
     Ret(Option<VirtualRegister>),   // [mv a0, t1] ret
     Label(String),                  // .LBB_func_0
     Jump(String),                   // j .LBB_func_0
     BranchIfFalse(String),          // beqz t0, .LBB_func_end
+    BranchIfTrue(String),           // bnez t0, .LBB_func_0
     BranchCond(CmpOp, String),      // beq/bne/blt/bge rs1, rs2, label
     Add,                            // add rd, rs1, rs2
     Sub,                            // sub rd, rs1, rs2
@@ -39,8 +47,21 @@ pub enum Op {
     Xor,                            // xor rd, rs1, rs2
     Sll,                            // sll rd, rs1, rs2 (shift left logical)
     Srl,                            // srl rd, rs1, rs2 (shift right logical)
+    Slt,                            // slt rd, rs1, rs2 (set if less than)
+    Sltu,                           // sltu rd, rs1, rs2 (set if less than, unsigned)
     Neg,                            // neg rd, rs (sub rd, x0, rs)
     Not,                            // not rd, rs (xori rd, rs, -1)
+    AddImm(i32),                    // addi rd, rs, imm (selected by backend::isel)
+    AndImm(i32),                    // andi rd, rs, imm
+    OrImm(i32),                     // ori rd, rs, imm
+    XorImm(i32),                    // xori rd, rs, imm
+    SllImm(i32),                    // slli rd, rs, shamt
+    SrlImm(i32),                    // srli rd, rs, shamt
+    SltImm(i32),                    // slti rd, rs, imm
+    SltuImm(i32),                   // sltiu rd, rs, imm
+    LoadSlot(usize),                // lw t5, 4*slot(sp) (reload spilled register)
+    StoreSlot(usize),               // sw t5, 4*slot(sp) (store spilled register)
+    Phi(Vec<cfg::BlockId>),         // x2 = phi [x0, pred0], [x1, pred1] (removed by ssa::destruct)
 }
 
 impl Instruction {
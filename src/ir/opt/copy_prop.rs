@@ -0,0 +1,64 @@
+use std::collections::HashMap;
+use crate::ir::{Op, VirtualRegister};
+use crate::ir::cfg::CFG;
+
+/* Copy propagation of Op::Mov and trivial phis
+ *
+ *   t3 = mv t2
+ *   t4 = add t3, t1             ->  t4 = add t2, t1
+ *   t5 = phi [t2, 0], [t5, 2]   ->  uses of t5 read t2
+ *
+ * In SSA form a copy's source holds the same value wherever its destination is read, so
+ * every use is replaced, across blocks. Registers with more than one definition are left
+ * alone. The copies themselves stay until DCE finds them unused. */
+pub fn propagate(cfg: &mut CFG) -> bool {
+    let definitions = super::definitions(cfg);
+    let single = |vreg: &VirtualRegister| definitions.get(vreg) == Some(&1);
+
+    let mut copies: HashMap<VirtualRegister, VirtualRegister> = HashMap::new();
+    for instr in cfg.blocks.iter().flat_map(|b| &b.instructions) {
+        let Some(dest) = instr.destination.filter(|d| single(d)) else { continue };
+
+        // A phi whose operands are all one register (or the phi itself) is a copy of it
+        let source = match &instr.operation {
+            Op::Mov => Some(instr.args[0]),
+            Op::Phi(_) => {
+                let mut sources = instr.args.iter().filter(|&&a| a != dest);
+                let first = sources.next().copied();
+                first.filter(|f| sources.all(|s| s == f))
+            }
+            _ => None,
+        };
+
+        if let Some(source) = source.filter(|s| *s != dest && single(s)) {
+            copies.insert(dest, source);
+        }
+    }
+
+    // Follow chains of copies to the original register
+    let resolve = |mut vreg: VirtualRegister| {
+        for _ in 0..copies.len() {
+            match copies.get(&vreg) {
+                Some(&source) => vreg = source,
+                None => break,
+            }
+        }
+        vreg
+    };
+
+    let mut changed = false;
+    for block in &mut cfg.blocks {
+        let uses = block.instructions.iter_mut()
+            .flat_map(|i| i.args.iter_mut())
+            .chain(block.terminator.uses_mut());
+        for reg in uses {
+            let source = resolve(*reg);
+            if source != *reg {
+                *reg = source;
+                changed = true;
+            }
+        }
+    }
+
+    changed
+}
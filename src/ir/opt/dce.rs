@@ -0,0 +1,52 @@
+use std::collections::HashMap;
+use crate::ir::{Op, VirtualRegister};
+use crate::ir::cfg::CFG;
+
+/* Dead code elimination
+ *
+ * Removes instructions without side effects whose result is never read, until no more
+ * can be removed. Peripheral loads (Op::LoadWord, Op::LoadDouble) are kept even when unused, since
+ * reading a register such as a UART receive buffer can change the device's state. */
+pub fn eliminate(cfg: &mut CFG) -> bool {
+    let mut changed = false;
+
+    loop {
+        let mut uses: HashMap<VirtualRegister, usize> = HashMap::new();
+        for block in &cfg.blocks {
+            for arg in block.instructions.iter().flat_map(|i| &i.args) {
+                *uses.entry(*arg).or_default() += 1;
+            }
+            for reg in block.terminator.uses() {
+                *uses.entry(reg).or_default() += 1;
+            }
+        }
+
+        let mut removed = false;
+        for block in &mut cfg.blocks {
+            block.instructions.retain(|instr| {
+                let dead = is_pure(&instr.operation)
+                    && instr.destination.is_some_and(|d| !uses.contains_key(&d));
+                removed |= dead;
+                !dead
+            });
+        }
+
+        if !removed {
+            return changed;
+        }
+        changed = true;
+    }
+}
+
+fn is_pure(op: &Op) -> bool {
+    matches!(
+        op,
+        Op::LoadImm(_) | Op::LoadAddr(_) | Op::Mov | Op::MovArg(_)
+            | Op::Add | Op::Sub | Op::Mul | Op::Div | Op::Rem
+            | Op::And | Op::Or | Op::Xor | Op::Sll | Op::Srl | Op::Slt | Op::Sltu
+            | Op::Neg | Op::Not
+            | Op::AddImm(_) | Op::AndImm(_) | Op::OrImm(_) | Op::XorImm(_)
+            | Op::SllImm(_) | Op::SrlImm(_) | Op::SltImm(_) | Op::SltuImm(_)
+            | Op::Phi(_)
+    )
+}
@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+use crate::ir::{Op, VirtualRegister};
+use crate::ir::cfg::{CFG, CmpOp, Terminator};
+
+/* Constant folding
+ *
+ * Operations whose arguments are all constants become a LoadImm of the result, computed
+ * with the wrapping semantics of the RV32IM instruction the operation selects to. On
+ * RV64 (xlen 64) the 64-bit instructions are followed instead, and results that no
+ * longer fit in 32 bits are left to run. A Branch or CondBranch on constants becomes a
+ * Jump, and blocks no longer reachable from the entry are removed. */
+pub fn fold(cfg: &mut CFG, xlen: u32) -> bool {
+    let definitions = super::definitions(cfg);
+    let mut constants: HashMap<VirtualRegister, i32> = HashMap::new();
+    let mut changed = false;
+
+    // A constant defined after its uses in block order is picked up by the next round
+    for block in &mut cfg.blocks {
+        for instr in &mut block.instructions {
+            let args: Option<Vec<i32>> = instr.args.iter().map(|a| constants.get(a).copied()).collect();
+            if let (Some(args), false) = (args, instr.args.is_empty()) {
+                let value = match xlen {
+                    64 => evaluate_wide(&instr.operation, &args),
+                    _ => evaluate(&instr.operation, &args),
+                };
+                if let Some(value) = value {
+                    instr.operation = Op::LoadImm(value);
+                    instr.args.clear();
+                    changed = true;
+                }
+            }
+
+            if let (Op::LoadImm(value), Some(dest)) = (&instr.operation, instr.destination) {
+                if definitions.get(&dest) == Some(&1) {
+                    constants.insert(dest, *value);
+                }
+            }
+        }
+    }
+
+    for block in &mut cfg.blocks {
+        let taken = match &block.terminator {
+            Terminator::Branch { cond, then_block, else_block } => match constants.get(cond) {
+                Some(0) => Some(*else_block),
+                Some(_) => Some(*then_block),
+                None => None,
+            },
+            Terminator::CondBranch { op, lhs, rhs, then_block, else_block } => {
+                match (constants.get(lhs), constants.get(rhs)) {
+                    (Some(&lhs), Some(&rhs)) if compare(*op, lhs, rhs) => Some(*then_block),
+                    (Some(_), Some(_)) => Some(*else_block),
+                    _ => None,
+                }
+            }
+            _ => None,
+        };
+
+        if let Some(target) = taken {
+            block.terminator = Terminator::Jump(target);
+            changed = true;
+        }
+    }
+
+    if changed {
+        cfg.remove_unreachable();
+    }
+    changed
+}
+
+fn evaluate(op: &Op, args: &[i32]) -> Option<i32> {
+    let value = match (op, args) {
+        (Op::Mov, &[a]) => a,
+        (Op::Phi(_), &[a, ref rest @ ..]) if rest.iter().all(|&b| b == a) => a,
+        (Op::Neg, &[a]) => a.wrapping_neg(),
+        (Op::Not, &[a]) => !a,
+        (Op::Add, &[a, b]) => a.wrapping_add(b),
+        (Op::Sub, &[a, b]) => a.wrapping_sub(b),
+        (Op::Mul, &[a, b]) => a.wrapping_mul(b),
+        // div by zero gives -1 and rem by zero the dividend, without trapping
+        (Op::Div, &[a, b]) => if b == 0 { -1 } else { a.wrapping_div(b) },
+        (Op::Rem, &[a, b]) => if b == 0 { a } else { a.wrapping_rem(b) },
+        (Op::And, &[a, b]) => a & b,
+        (Op::Or, &[a, b]) => a | b,
+        (Op::Xor, &[a, b]) => a ^ b,
+        (Op::Sll, &[a, b]) => a.wrapping_shl(b as u32),
+        (Op::Srl, &[a, b]) => (a as u32).wrapping_shr(b as u32) as i32,
+        (Op::Slt, &[a, b]) => (a < b) as i32,
+        (Op::Sltu, &[a, b]) => ((a as u32) < (b as u32)) as i32,
+        _ => return None,
+    };
+    Some(value)
+}
+
+// As evaluate, on the sign-extended values li gives the constants in 64-bit registers
+fn evaluate_wide(op: &Op, args: &[i32]) -> Option<i32> {
+    let args: Vec<i64> = args.iter().map(|&a| a as i64).collect();
+    let value = match (op, args.as_slice()) {
+        (Op::Mov, &[a]) => a,
+        (Op::Phi(_), &[a, ref rest @ ..]) if rest.iter().all(|&b| b == a) => a,
+        (Op::Neg, &[a]) => -a,
+        (Op::Not, &[a]) => !a,
+        (Op::Add, &[a, b]) => a + b,
+        (Op::Sub, &[a, b]) => a - b,
+        (Op::Mul, &[a, b]) => a * b,
+        (Op::Div, &[a, b]) => if b == 0 { -1 } else { a / b },
+        (Op::Rem, &[a, b]) => if b == 0 { a } else { a % b },
+        (Op::And, &[a, b]) => a & b,
+        (Op::Or, &[a, b]) => a | b,
+        (Op::Xor, &[a, b]) => a ^ b,
+        (Op::Sll, &[a, b]) => a.wrapping_shl(b as u32),
+        (Op::Srl, &[a, b]) => (a as u64).wrapping_shr(b as u32) as i64,
+        (Op::Slt, &[a, b]) => (a < b) as i64,
+        (Op::Sltu, &[a, b]) => ((a as u64) < (b as u64)) as i64,
+        _ => return None,
+    };
+    i32::try_from(value).ok()
+}
+
+fn compare(op: CmpOp, lhs: i32, rhs: i32) -> bool {
+    match op {
+        CmpOp::Eq => lhs == rhs,
+        CmpOp::Ne => lhs != rhs,
+        CmpOp::Lt => lhs < rhs,
+        CmpOp::Le => lhs <= rhs,
+        CmpOp::Gt => lhs > rhs,
+        CmpOp::Ge => lhs >= rhs,
+    }
+}
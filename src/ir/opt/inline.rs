@@ -0,0 +1,154 @@
+use std::collections::{HashMap, HashSet};
+use crate::frontend::ast::Inline;
+use crate::ir::{Instruction, Op, VirtualRegister};
+use crate::ir::cfg::{CFG, BlockId, Terminator};
+
+/* Callees of at most this many instructions are inlined without #[inline] at -O1 */
+pub const INLINE_THRESHOLD: usize = 12;
+
+/* Instructions a caller may grow by through inlining callees without #[inline] */
+pub const GROWTH_LIMIT: usize = 256;
+
+/* Inlining of calls to small functions
+ *
+ *   B:  t1 = li 0x03                B:  t1 = li 0x03
+ *       t2 = call uart_set_lcr(t1)      j B'
+ *       sw t2, 0(t3)                B': t10 = mv t1            (MovArg 0)
+ *                                       t11 = li 0x10000000
+ *                                       sw t10, 3(t11)
+ *                                       j B''
+ *                                   B'': sw t2, 0(t3)
+ *
+ * A call is inlined when the callee is marked #[inline], or is not marked #[noinline]
+ * and is no larger than the threshold while the caller has grown by less than
+ * GROWTH_LIMIT. The threshold is 0 at -Os, where a call is smaller than a copy of the
+ * callee, which is emitted anyway. Functions that can reach themselves through calls
+ * are never inlined.
+ *
+ * The callee's blocks are copied in with renumbered registers, its arguments read from
+ * the call's operands and its returns moved into the call's result. Block statements
+ * stay in the caller's block, so the PeripheralDriverCall recorded for the call keeps
+ * its place on every path typestate verification looks at. Callees are always copied
+ * from the functions as lowered, and are still emitted on their own. */
+pub fn inline(functions: &mut [(String, CFG)], threshold: usize) {
+    let originals: HashMap<String, CFG> = functions.iter().cloned().collect();
+    let recursive = recursive_functions(&originals);
+
+    for (caller, cfg) in functions.iter_mut() {
+        let mut budget = GROWTH_LIMIT;
+
+        loop {
+            let site = cfg.blocks.iter().find_map(|block| {
+                block.instructions.iter().enumerate().find_map(|(index, instr)| {
+                    let Op::Call(target) = &instr.operation else { return None };
+                    let callee = originals.get(target)?;
+
+                    let eligible = target != caller
+                        && !recursive.contains(target)
+                        && callee.params == instr.args.len()
+                        && match callee.inline {
+                            Inline::Always => true,
+                            Inline::Never => false,
+                            Inline::Auto => size(callee) <= threshold.min(budget),
+                        };
+                    eligible.then_some((block.id, index, callee))
+                })
+            });
+
+            let Some((block, index, callee)) = site else { break };
+            budget = budget.saturating_sub(size(callee));
+            inline_call(cfg, block, index, callee);
+        }
+    }
+}
+
+fn inline_call(cfg: &mut CFG, block: BlockId, index: usize, callee: &CFG) {
+    let base = cfg.next_register();
+    let rename = |vreg: VirtualRegister| VirtualRegister { id: base + vreg.id };
+    let offset = cfg.blocks.len();
+    let tail = offset + callee.blocks.len();
+
+    for (vreg, name) in &callee.names {
+        cfg.names.insert(rename(*vreg), name.clone());
+    }
+
+    let mut rest = cfg.blocks[block].instructions.split_off(index);
+    let call = rest.remove(0);
+
+    for callee_block in &callee.blocks {
+        let id = cfg.add_block();
+        let mut instructions: Vec<Instruction> = callee_block.instructions.iter()
+            .map(|instr| match instr.operation {
+                Op::MovArg(i) => Instruction::new(Op::Mov, instr.destination.map(rename), vec![call.args[i]]),
+                _ => Instruction::new(
+                    instr.operation.clone(),
+                    instr.destination.map(rename),
+                    instr.args.iter().copied().map(rename).collect(),
+                ),
+            })
+            .collect();
+
+        let mut terminator = callee_block.terminator.clone();
+        terminator.retarget(|target| offset + target);
+        for reg in terminator.uses_mut() {
+            *reg = rename(*reg);
+        }
+
+        // A return without a value still defines the call's result
+        if let Terminator::Return(value) = terminator {
+            if let Some(dest) = call.destination {
+                instructions.push(match value {
+                    Some(value) => Instruction::new(Op::Mov, Some(dest), vec![value]),
+                    None => Instruction::new(Op::LoadImm(0), Some(dest), vec![]),
+                });
+            }
+            terminator = Terminator::Jump(tail);
+        }
+
+        cfg.blocks[id].instructions = instructions;
+        cfg.blocks[id].terminator = terminator;
+    }
+
+    let continuation = cfg.add_block();
+    cfg.blocks[continuation].instructions = rest;
+    cfg.blocks[continuation].terminator = std::mem::replace(
+        &mut cfg.blocks[block].terminator,
+        Terminator::Jump(offset + callee.entry),
+    );
+}
+
+// Instructions and terminators, not counting the moves reading arguments
+fn size(cfg: &CFG) -> usize {
+    cfg.blocks.iter()
+        .map(|b| 1 + b.instructions.iter().filter(|i| !matches!(i.operation, Op::MovArg(_))).count())
+        .sum()
+}
+
+fn recursive_functions(functions: &HashMap<String, CFG>) -> HashSet<String> {
+    let callees = |name: &str| -> Vec<String> {
+        functions.get(name).into_iter()
+            .flat_map(|cfg| cfg.blocks.iter().flat_map(|b| &b.instructions))
+            .filter_map(|i| match &i.operation {
+                Op::Call(target) => Some(target.clone()),
+                _ => None,
+            })
+            .collect()
+    };
+
+    functions.keys()
+        .filter(|&name| {
+            let mut seen = HashSet::new();
+            let mut stack = callees(name);
+            while let Some(next) = stack.pop() {
+                if next == *name {
+                    return true;
+                }
+                if seen.insert(next.clone()) {
+                    stack.extend(callees(&next));
+                }
+            }
+            false
+        })
+        .cloned()
+        .collect()
+}
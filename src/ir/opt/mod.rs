@@ -0,0 +1,62 @@
+pub mod inline;
+pub mod fold;
+pub mod copy_prop;
+pub mod dce;
+
+use std::collections::HashMap;
+use crate::ir::{ssa, VirtualRegister};
+use crate::ir::cfg::CFG;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum OptLevel {
+    #[default]
+    O0,     // Lowered IR as is
+    O1,     // Inlining, constant folding, copy propagation and dead code elimination
+    Os,     // As O1 for size: only #[inline] functions are inlined
+}
+
+/* Machine-independent optimisations on the CFG, between lowering and the backend
+ *
+ *   li t0, 4; li t1, 8; mul t2, t0, t1      ->  li t2, 32
+ *   li t0, 0; beqz t0, .else                ->  j .else (then block removed)
+ *   mv t3, t2; add t4, t3, t1               ->  add t4, t2, t1
+ *
+ * Small functions are inlined into their callers first. Each function is then put into
+ * SSA form, and the passes run until none of them changes it. Peripheral loads and
+ * stores, calls and returns are never removed. The backend takes the function out of
+ * SSA form before register allocation. Constants fold at the target's register width,
+ * xlen (32 or 64). */
+pub fn optimise(functions: &mut [(String, CFG)], level: OptLevel, xlen: u32) {
+    if level == OptLevel::O0 {
+        return;
+    }
+
+    let threshold = match level {
+        OptLevel::Os => 0,
+        _ => inline::INLINE_THRESHOLD,
+    };
+    inline::inline(functions, threshold);
+
+    for (_, cfg) in functions.iter_mut() {
+        ssa::construct(cfg);
+        loop {
+            let mut changed = fold::fold(cfg, xlen);
+            changed |= copy_prop::propagate(cfg);
+            changed |= dce::eliminate(cfg);
+            if !changed {
+                break;
+            }
+        }
+    }
+}
+
+// Number of instructions defining each register
+fn definitions(cfg: &CFG) -> HashMap<VirtualRegister, usize> {
+    let mut definitions = HashMap::new();
+    for instr in cfg.blocks.iter().flat_map(|b| &b.instructions) {
+        if let Some(dest) = instr.destination {
+            *definitions.entry(dest).or_default() += 1;
+        }
+    }
+    definitions
+}
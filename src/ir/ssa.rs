@@ -0,0 +1,410 @@
+use std::collections::{HashMap, HashSet};
+use crate::ir::{Instruction, Op, VirtualRegister};
+use crate::ir::cfg::{CFG, BlockId, Terminator};
+use crate::ir::dominators::{self, Dominators};
+use crate::backend::liveness;
+
+/* Static single assignment form
+ *
+ *   let x = 0;                  x1 = li 0
+ *   while (x < 3) {         .LBB_1:
+ *       x = x + 1;              x2 = phi [x1, 0], [x4, 2]
+ *   }                           bge x2, 3, .LBB_3
+ *                           .LBB_2:
+ *                               x3 = addi x2, 1
+ *                               x4 = mv x3
+ *
+ * construct() gives every definition its own register: phis are placed at the dominance
+ * frontiers of a variable's definitions where it is live (pruned SSA), then each use is
+ * renamed to the definition reaching it by walking the dominator tree. Registers that
+ * lowering already defines once are left alone.
+ *
+ * destruct() turns the phis of a block back into copies at the end of each predecessor,
+ * before register allocation. Critical edges are split first so the copies only run on
+ * the edge they belong to, and the copies of a block are sequentialised as one parallel
+ * copy, so phis that read each other's results (a swap) stay correct. */
+pub fn construct(cfg: &mut CFG) {
+    cfg.remove_unreachable();
+
+    let doms = Dominators::compute(cfg);
+    let liveness = liveness::analyse(cfg);
+
+    let mut def_blocks: HashMap<VirtualRegister, Vec<BlockId>> = HashMap::new();
+    let mut def_counts: HashMap<VirtualRegister, usize> = HashMap::new();
+    for block in &cfg.blocks {
+        for dest in block.instructions.iter().filter_map(|i| i.destination) {
+            *def_counts.entry(dest).or_default() += 1;
+            let blocks = def_blocks.entry(dest).or_default();
+            if !blocks.contains(&block.id) {
+                blocks.push(block.id);
+            }
+        }
+    }
+
+    let mut variables: Vec<VirtualRegister> = def_counts.iter()
+        .filter(|(_, &count)| count > 1)
+        .map(|(vreg, _)| *vreg)
+        .collect();
+    variables.sort_by_key(|v| v.id);
+
+    // Phi placement on the iterated dominance frontier
+    let mut phis: Vec<Vec<VirtualRegister>> = vec![Vec::new(); cfg.blocks.len()];
+    for &var in &variables {
+        let mut worklist = def_blocks[&var].clone();
+        let mut has_def: HashSet<BlockId> = worklist.iter().copied().collect();
+
+        while let Some(block) = worklist.pop() {
+            for &frontier in &doms.frontiers[block] {
+                if phis[frontier].contains(&var) || !liveness[&frontier].live_in.contains(&var) {
+                    continue;
+                }
+                phis[frontier].push(var);
+                if has_def.insert(frontier) {
+                    worklist.push(frontier);
+                }
+            }
+        }
+    }
+
+    for block in &mut cfg.blocks {
+        let preds = &doms.predecessors[block.id];
+        let inserted = phis[block.id].iter()
+            .map(|&var| Instruction::new(Op::Phi(preds.clone()), Some(var), vec![var; preds.len()]));
+        block.instructions.splice(0..0, inserted);
+    }
+
+    let mut renamer = Renamer {
+        variables: variables.into_iter().collect(),
+        stacks: HashMap::new(),
+        next: cfg.next_register(),
+    };
+    renamer.rename(cfg, &doms, &phis, cfg.entry);
+}
+
+struct Renamer {
+    variables: HashSet<VirtualRegister>,
+    stacks: HashMap<VirtualRegister, Vec<VirtualRegister>>,   // Reaching definitions of each variable
+    next: usize,
+}
+
+impl Renamer {
+    fn current(&self, var: VirtualRegister) -> VirtualRegister {
+        self.stacks.get(&var).and_then(|s| s.last()).copied().unwrap_or(var)
+    }
+
+    fn rename(&mut self, cfg: &mut CFG, doms: &Dominators, phis: &[Vec<VirtualRegister>], block: BlockId) {
+        let mut defined = Vec::new();
+
+        for instr in &mut cfg.blocks[block].instructions {
+            if !matches!(instr.operation, Op::Phi(_)) {
+                for arg in &mut instr.args {
+                    if self.variables.contains(arg) {
+                        *arg = self.current(*arg);
+                    }
+                }
+            }
+
+            if let Some(dest) = instr.destination.filter(|d| self.variables.contains(d)) {
+                let name = VirtualRegister { id: self.next };
+                self.next += 1;
+                if let Some(var_name) = cfg.names.get(&dest).cloned() {
+                    cfg.names.insert(name, var_name);
+                }
+                self.stacks.entry(dest).or_default().push(name);
+                defined.push(dest);
+                instr.destination = Some(name);
+            }
+        }
+
+        let mut terminator = cfg.blocks[block].terminator.clone();
+        for reg in terminator.uses_mut() {
+            if self.variables.contains(reg) {
+                *reg = self.current(*reg);
+            }
+        }
+        cfg.blocks[block].terminator = terminator;
+
+        // Fill in this block's operand of each phi in its successors
+        for succ in cfg.blocks[block].terminator.successors() {
+            for (instr, &var) in cfg.blocks[succ].instructions.iter_mut().zip(&phis[succ]) {
+                let Op::Phi(preds) = &instr.operation else { continue };
+                if let Some(index) = preds.iter().position(|&p| p == block) {
+                    instr.args[index] = self.current(var);
+                }
+            }
+        }
+
+        for &child in &doms.children[block] {
+            self.rename(cfg, doms, phis, child);
+        }
+
+        for var in defined {
+            self.stacks.get_mut(&var).unwrap().pop();
+        }
+    }
+}
+
+pub fn destruct(cfg: &mut CFG) {
+    let mut next = cfg.next_register();
+    let predecessors = dominators::predecessors(cfg);
+
+    for (block, preds) in predecessors.iter().enumerate() {
+        let phis: Vec<Instruction> = cfg.blocks[block].instructions.iter()
+            .filter(|i| matches!(i.operation, Op::Phi(_)))
+            .cloned()
+            .collect();
+        if phis.is_empty() {
+            continue;
+        }
+        cfg.blocks[block].instructions.retain(|i| !matches!(i.operation, Op::Phi(_)));
+
+        for &pred in preds {
+            let copies: Vec<(VirtualRegister, VirtualRegister)> = phis.iter()
+                .filter_map(|phi| {
+                    let Op::Phi(preds) = &phi.operation else { unreachable!() };
+                    let index = preds.iter().position(|&p| p == pred)?;
+                    Some((phi.destination.unwrap(), phi.args[index]))
+                })
+                .collect();
+
+            // A predecessor with other successors gets a block of its own for the copies
+            let target = if cfg.blocks[pred].terminator.successors().len() > 1 {
+                let split = cfg.add_block();
+                cfg.blocks[split].terminator = Terminator::Jump(block);
+                cfg.blocks[pred].terminator.retarget(|t| if t == block { split } else { t });
+                split
+            } else {
+                pred
+            };
+
+            let sequential = sequentialise(copies, &mut next);
+            cfg.blocks[target].instructions.extend(sequential);
+        }
+    }
+}
+
+/* Order a parallel copy so no source is overwritten before it is read
+ *
+ *   (a, b), (b, a)      ->  mv t, a; mv a, b; mv b, t
+ *
+ * A copy can go once no other pending copy reads its destination. When every
+ * destination is still read, the copies form a cycle, broken by saving one of them. */
+fn sequentialise(copies: Vec<(VirtualRegister, VirtualRegister)>, next: &mut usize) -> Vec<Instruction> {
+    let mut pending: Vec<(VirtualRegister, VirtualRegister)> = copies.into_iter()
+        .filter(|(dest, src)| dest != src)
+        .collect();
+    let mut output = Vec::new();
+
+    while !pending.is_empty() {
+        let ready = pending.iter().position(|(dest, _)| !pending.iter().any(|(_, src)| src == dest));
+        match ready {
+            Some(index) => {
+                let (dest, src) = pending.remove(index);
+                output.push(Instruction::new(Op::Mov, Some(dest), vec![src]));
+            }
+            None => {
+                let saved = pending[0].0;
+                let temp = VirtualRegister { id: *next };
+                *next += 1;
+                output.push(Instruction::new(Op::Mov, Some(temp), vec![saved]));
+                for (_, src) in pending.iter_mut().filter(|(_, src)| *src == saved) {
+                    *src = temp;
+                }
+            }
+        }
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::cfg::CmpOp;
+
+    fn reg(id: usize) -> VirtualRegister {
+        VirtualRegister { id }
+    }
+
+    fn op(operation: Op, dest: usize, args: &[usize]) -> Instruction {
+        Instruction::new(operation, Some(reg(dest)), args.iter().map(|&a| reg(a)).collect())
+    }
+
+    fn graph(blocks: Vec<(Vec<Instruction>, Terminator)>) -> CFG {
+        let mut cfg = CFG::new();
+        for (instructions, terminator) in blocks {
+            let id = cfg.add_block();
+            cfg.block_mut(id).instructions = instructions;
+            cfg.block_mut(id).terminator = terminator;
+        }
+        cfg
+    }
+
+    fn less(lhs: usize, rhs: usize, then_block: BlockId, else_block: BlockId) -> Terminator {
+        Terminator::CondBranch { op: CmpOp::Lt, lhs: reg(lhs), rhs: reg(rhs), then_block, else_block }
+    }
+
+    // Runs the function from its entry, the phis of a block reading their operands together
+    fn run(cfg: &CFG) -> i32 {
+        let mut regs: HashMap<VirtualRegister, i32> = HashMap::new();
+        let (mut block, mut previous) = (cfg.entry, None);
+        for _ in 0..1000 {
+            let instructions = &cfg.block(block).instructions;
+            let phis: Vec<(VirtualRegister, i32)> = instructions.iter()
+                .filter_map(|i| match &i.operation {
+                    Op::Phi(preds) => {
+                        let index = preds.iter().position(|&p| Some(p) == previous).unwrap();
+                        Some((i.destination.unwrap(), regs[&i.args[index]]))
+                    }
+                    _ => None,
+                })
+                .collect();
+            regs.extend(phis);
+            for instr in instructions.iter().filter(|i| !matches!(i.operation, Op::Phi(_))) {
+                let arg = |i: usize| regs[&instr.args[i]];
+                let value = match instr.operation {
+                    Op::LoadImm(val) => val,
+                    Op::Mov => arg(0),
+                    Op::Add => arg(0).wrapping_add(arg(1)),
+                    Op::Sub => arg(0).wrapping_sub(arg(1)),
+                    ref op => panic!("unexpected {:?}", op),
+                };
+                regs.insert(instr.destination.unwrap(), value);
+            }
+            previous = Some(block);
+            block = match cfg.block(block).terminator {
+                Terminator::Jump(target) => target,
+                Terminator::Branch { cond, then_block, else_block } => {
+                    if regs[&cond] != 0 { then_block } else { else_block }
+                }
+                Terminator::CondBranch { op: CmpOp::Lt, lhs, rhs, then_block, else_block } => {
+                    if regs[&lhs] < regs[&rhs] { then_block } else { else_block }
+                }
+                Terminator::Return(Some(val)) => return regs[&val],
+                ref terminator => panic!("unexpected {:?}", terminator),
+            };
+        }
+        panic!("no return")
+    }
+
+    fn phis(cfg: &CFG, block: BlockId) -> Vec<&Instruction> {
+        cfg.block(block).instructions.iter().filter(|i| matches!(i.operation, Op::Phi(_))).collect()
+    }
+
+    #[test]
+    fn phi_at_the_loop_header() {
+        // x = 0; while (x < 3) { x = x + 1; } return x;
+        let mut cfg = graph(vec![
+            (vec![op(Op::LoadImm(0), 0, &[]), op(Op::LoadImm(3), 1, &[])], Terminator::Jump(1)),
+            (vec![], less(0, 1, 2, 3)),
+            (vec![op(Op::LoadImm(1), 2, &[]), op(Op::Add, 0, &[0, 2])], Terminator::Jump(1)),
+            (vec![], Terminator::Return(Some(reg(0)))),
+        ]);
+        construct(&mut cfg);
+
+        let header = phis(&cfg, 1);
+        assert_eq!(header.len(), 1);
+        assert!(matches!(&header[0].operation, Op::Phi(preds) if preds == &[0, 2]));
+        let defined = |block: BlockId, index: usize| cfg.block(block).instructions[index].destination.unwrap();
+        assert_eq!(header[0].args, [defined(0, 0), defined(2, 1)]);
+        assert!(phis(&cfg, 2).is_empty() && phis(&cfg, 3).is_empty());
+
+        let mut dests: Vec<VirtualRegister> = cfg.blocks.iter()
+            .flat_map(|b| &b.instructions)
+            .filter_map(|i| i.destination)
+            .collect();
+        let count = dests.len();
+        dests.sort_by_key(|v| v.id);
+        dests.dedup();
+        assert_eq!(dests.len(), count, "every register is defined once");
+        assert_eq!(run(&cfg), 3);
+
+        destruct(&mut cfg);
+        assert!(cfg.blocks.iter().all(|b| phis(&cfg, b.id).is_empty()));
+        assert_eq!(run(&cfg), 3);
+    }
+
+    #[test]
+    fn no_phi_where_the_variable_is_dead() {
+        // x is redefined on both sides of a branch, the join returns y or x
+        let diamond = |result: usize| graph(vec![
+            (vec![op(Op::LoadImm(1), 0, &[]), op(Op::LoadImm(0), 1, &[])],
+                Terminator::Branch { cond: reg(1), then_block: 1, else_block: 2 }),
+            (vec![op(Op::LoadImm(2), 0, &[]), op(Op::Add, 2, &[0, 0])], Terminator::Jump(3)),
+            (vec![op(Op::LoadImm(3), 0, &[])], Terminator::Jump(3)),
+            (vec![], Terminator::Return(Some(reg(result)))),
+        ]);
+
+        let mut dead = diamond(1);
+        construct(&mut dead);
+        assert!(dead.blocks.iter().all(|b| phis(&dead, b.id).is_empty()));
+
+        let mut live = diamond(0);
+        construct(&mut live);
+        assert_eq!(phis(&live, 3).len(), 1);
+        assert_eq!(run(&live), 3);
+    }
+
+    #[test]
+    fn critical_edges_are_split() {
+        // 0 -> 1 -> 2 and 0 -> 2, where 2 has a phi
+        let mut cfg = graph(vec![
+            (vec![op(Op::LoadImm(1), 0, &[]), op(Op::LoadImm(0), 1, &[])],
+                Terminator::Branch { cond: reg(1), then_block: 1, else_block: 2 }),
+            (vec![op(Op::LoadImm(2), 2, &[])], Terminator::Jump(2)),
+            (vec![op(Op::Phi(vec![0, 1]), 3, &[0, 2])], Terminator::Return(Some(reg(3)))),
+        ]);
+        destruct(&mut cfg);
+
+        assert_eq!(cfg.blocks.len(), 4);
+        assert!(matches!(cfg.block(0).terminator, Terminator::Branch { then_block: 1, else_block: 3, .. }));
+        assert!(matches!(cfg.block(3).terminator, Terminator::Jump(2)));
+        let copy = |block: BlockId| cfg.block(block).instructions.last()
+            .filter(|i| matches!(i.operation, Op::Mov))
+            .map(|i| (i.destination.unwrap(), i.args[0]));
+        assert_eq!(copy(3), Some((reg(3), reg(0))));
+        assert_eq!(copy(1), Some((reg(3), reg(2))));
+        assert!(cfg.block(2).instructions.is_empty());
+        assert_eq!(run(&cfg), 1);
+    }
+
+    #[test]
+    fn lost_copy() {
+        // x = 0; do { y = x; x = x + 1; } while (x < 3); return y;
+        // the copy into the phi of the loop must not run on the way out
+        let mut cfg = graph(vec![
+            (vec![op(Op::LoadImm(0), 0, &[]), op(Op::LoadImm(1), 1, &[]), op(Op::LoadImm(3), 2, &[])],
+                Terminator::Jump(1)),
+            (vec![op(Op::Phi(vec![0, 1]), 3, &[0, 4]), op(Op::Add, 4, &[3, 1])], less(4, 2, 1, 2)),
+            (vec![], Terminator::Return(Some(reg(3)))),
+        ]);
+        assert_eq!(run(&cfg), 2);
+
+        destruct(&mut cfg);
+        assert_eq!(cfg.blocks.len(), 4);
+        assert_eq!(run(&cfg), 2);
+    }
+
+    #[test]
+    fn swap() {
+        // Phis reading each other's results: (a, b) = (b, a) twice, return a - b
+        let mut cfg = graph(vec![
+            (vec![
+                op(Op::LoadImm(1), 0, &[]), op(Op::LoadImm(2), 1, &[]),
+                op(Op::LoadImm(0), 2, &[]), op(Op::LoadImm(1), 3, &[]), op(Op::LoadImm(2), 4, &[]),
+            ], Terminator::Jump(1)),
+            (vec![
+                op(Op::Phi(vec![0, 1]), 5, &[0, 6]),
+                op(Op::Phi(vec![0, 1]), 6, &[1, 5]),
+                op(Op::Phi(vec![0, 1]), 7, &[2, 8]),
+                op(Op::Add, 8, &[7, 3]),
+            ], less(8, 4, 1, 2)),
+            (vec![op(Op::Sub, 9, &[5, 6])], Terminator::Return(Some(reg(9)))),
+        ]);
+        assert_eq!(run(&cfg), 1);
+
+        destruct(&mut cfg);
+        assert_eq!(run(&cfg), 1);
+        assert_eq!(sequentialise(vec![(reg(0), reg(1)), (reg(1), reg(0))], &mut 10).len(), 3);
+    }
+}
@@ -3,17 +3,73 @@ extern crate chumsky;
 use std::process;
 use std::env;
 use std::fs;
+use std::path::Path;
 
 mod frontend;
 mod analysis;
 mod ir;
 mod backend;
+mod emit;
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+enum Emit {
+    #[default]
+    Asm,
+    StateGraph,
+    CHeader,
+    C,
+    Rust,
+    Svd,
+    Obj,
+}
+
+impl Emit {
+    fn parse(kind: &str) -> Result<Emit, String> {
+        match kind {
+            "asm" => Ok(Emit::Asm),
+            "state-graph" => Ok(Emit::StateGraph),
+            "c-header" => Ok(Emit::CHeader),
+            "c" => Ok(Emit::C),
+            "rust" => Ok(Emit::Rust),
+            "svd" => Ok(Emit::Svd),
+            "obj" => Ok(Emit::Obj),
+            _ => Err(format!("unknown emit kind '{}'", kind)),
+        }
+    }
+
+    fn default_destination(self) -> &'static str {
+        match self {
+            Emit::Asm => "out.s",
+            Emit::StateGraph => "out.dot",
+            Emit::CHeader => "out.h",
+            Emit::C => "out.c",
+            Emit::Rust => "out.rs",
+            Emit::Svd => "out.svd",
+            Emit::Obj => "out.o",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+enum Command {
+    #[default]
+    Compile,
+    ImportSvd,
+    Build,
+    Board,
+}
+
 struct Config {
+    command: Command,
     source: String,
     destination: String,
+    emit: Emit,
+    opt_level: ir::opt::OptLevel,
+    target: backend::Target,
+    march: backend::March,
+    board: Option<frontend::board::Board>,
 }
 
 impl Config {
@@ -25,10 +81,69 @@ impl Config {
         let source = flags.source
             .ok_or("no source file provided")?;
 
+        let emit = flags.emit.unwrap_or_default();
+
         let destination = flags.destination
-            .unwrap_or_else(|| "out.s".to_string());
+            .unwrap_or_else(|| match flags.command {
+                Command::Compile => emit.default_destination().to_string(),
+                Command::ImportSvd => "out.peri".to_string(),
+                Command::Build => "out.elf".to_string(),
+                Command::Board => ".".to_string(),
+            });
+
+        if flags.command == Command::ImportSvd && flags.emit.is_some() {
+            return Err("'--emit' cannot be used with 'import-svd'".to_string());
+        }
+
+        if flags.command == Command::Build && flags.emit.is_some() {
+            return Err("'--emit' cannot be used with 'build'".to_string());
+        }
 
-        Ok(Config { source, destination })
+        if flags.command == Command::Board && flags.emit.is_some() {
+            return Err("'--emit' cannot be used with 'board'".to_string());
+        }
+
+        if is_assembly(&source) && (flags.command == Command::Build || emit != Emit::Obj) {
+            return Err("assembly sources can only be assembled with '--emit=obj'".to_string());
+        }
+
+        if flags.target != backend::Target::Rv32 && (flags.command == Command::Build || emit == Emit::Obj) {
+            let target = if flags.target == backend::Target::Rv64 { "rv64" } else { "cortex-m" };
+            return Err(format!("the built-in assembler is RV32 only, '--target {}' emits assembly", target));
+        }
+
+        if emit == Emit::C && (flags.target != backend::Target::Rv32 || flags.march.is_some()) {
+            return Err("'--target' and '--march' select the instruction set of assembly output and cannot be used with '--emit=c'".to_string());
+        }
+
+        match (flags.target, flags.march) {
+            (backend::Target::Rv64, Some(backend::March::Rv32i | backend::March::Rv32e)) => {
+                return Err("'--target rv64' supports '--march=rv32im' and 'rv32imc', for RV64IM and RV64IMC".to_string());
+            }
+            (backend::Target::CortexM, Some(_)) => {
+                return Err("'--march' selects a RISC-V instruction set and cannot be used with '--target cortex-m'".to_string());
+            }
+            _ => {}
+        }
+
+        let board = match (flags.command, flags.board) {
+            (Command::Build, Some(name)) => Some(backend::board::find(&name)?),
+            (Command::Board, None) => Some(backend::board::find(&source)?),
+            (Command::Build, None) => return Err("'build' needs a board, e.g. '--board qemu-virt'".to_string()),
+            (_, Some(_)) => return Err("'--board' can only be used with 'build'".to_string()),
+            (_, None) => None,
+        };
+
+        Ok(Config {
+            command: flags.command,
+            source,
+            destination,
+            emit,
+            opt_level: flags.opt_level,
+            target: flags.target,
+            march: flags.march.unwrap_or_default(),
+            board,
+        })
     }
 
     fn parse_flags(
@@ -54,16 +169,53 @@ impl Config {
                         args.next().ok_or("expected destination filename after '-o'")?
                     );
                 }
+
+                "--board" => {
+                    flags.board = Some(
+                        args.next().ok_or("expected a board name after '--board'")?
+                    );
+                }
+
+                "-O0" => flags.opt_level = ir::opt::OptLevel::O0,
+                "-O1" => flags.opt_level = ir::opt::OptLevel::O1,
+                "-Os" => flags.opt_level = ir::opt::OptLevel::Os,
+
+                _ if arg.starts_with("--emit=") => {
+                    flags.emit = Some(Emit::parse(&arg["--emit=".len()..])?);
+                }
+
+                "--target" => {
+                    let target = args.next().ok_or("expected a target after '--target'")?;
+                    flags.target = backend::Target::parse(&target)?;
+                }
+
+                _ if arg.starts_with("--march=") => {
+                    flags.march = Some(backend::March::parse(&arg["--march=".len()..])?);
+                }
                 
                 _ if arg.starts_with('-') => {
                     return Err(format!("unknown option '{}'", arg));
                 }
+
+                "import-svd" if flags.source.is_none() && flags.command == Command::Compile => {
+                    flags.command = Command::ImportSvd;
+                }
+
+                "build" if flags.source.is_none() && flags.command == Command::Compile => {
+                    flags.command = Command::Build;
+                }
+
+                "board" if flags.source.is_none() && flags.command == Command::Compile => {
+                    flags.command = Command::Board;
+                }
+
                 _ => {
                     if flags.source.is_some() {
                         return Err("unexpected extra argument".to_string());
                     }
-                    if !arg.ends_with(".peri") {
-                        return Err("source file must have a .peri extension".to_string());
+                    let checked = !matches!(flags.command, Command::ImportSvd | Command::Board);
+                    if checked && !arg.ends_with(".peri") && !is_assembly(&arg) {
+                        return Err("source file must have a .peri, .s or .S extension".to_string());
                     }
                     flags.source = Some(arg);
                 }
@@ -76,19 +228,62 @@ impl Config {
     fn print_usage() {
         eprintln!("peric {}", VERSION);
         eprintln!();
-        eprintln!("Usage: peric [OPTIONS] <source.peri> <destination.s>");
+        eprintln!("Usage: peric [OPTIONS] <source.peri>");
+        eprintln!("       peric --emit=obj [-o <file>] <source.S>");
+        eprintln!("       peric import-svd [-o <file>] <device.svd>");
+        eprintln!("       peric build --board <board> [OPTIONS] <source.peri>");
+        eprintln!("       peric board [-o <dir>] <board>");
+        eprintln!();
+        eprintln!("Commands:");
+        eprintln!("  build                Assemble and link with the board's start file into an ELF");
+        eprintln!("                       executable and a raw .bin next to it (default output: out.elf)");
+        eprintln!("  board                Write the board's startup file and GNU ld linker script,");
+        eprintln!("                       start_<board>.S and link_<board>.ld (default directory: .)");
+        eprintln!("  import-svd           Generate peripheral declarations from a CMSIS-SVD file");
+        eprintln!("                       (default output: out.peri)");
         eprintln!();
         eprintln!("Options:");
-        eprintln!("  -o <file>    Write output assembly to <file> (default: out.s)");
-        eprintln!("  --help       Print this help message");
-        eprintln!("  --version    Print version information");
+        eprintln!("  -o <file>            Write output to <file> (default: out.s, out.dot, out.h, out.c, out.rs, out.svd, out.o)");
+        eprintln!("  --emit=<kind>        Output kind:");
+        eprintln!("                         asm          Assembly for the target (default)");
+        eprintln!("                         state-graph  Peripheral state machines as DOT (<file>)");
+        eprintln!("                                      and Mermaid (<file> with .md extension)");
+        eprintln!("                         c-header     C header with typestate-checked driver handles");
+        eprintln!("                         c            Portable C99 with volatile peripheral accesses, for");
+        eprintln!("                                      targets without a backend");
+        eprintln!("                         rust         no_std Rust module with typestate-checked driver handles");
+        eprintln!("                         svd          CMSIS-SVD description of the peripheral register maps");
+        eprintln!("                         obj          RISC-V ELF32 relocatable object, assembled by peric");
+        eprintln!("                                      (.s and .S sources are assembled as they are)");
+        eprintln!("  --board <board>      Board to build for: qemu-virt, fpga or a .board description");
+        eprintln!("  --march=<arch>       Instruction set to generate code for: rv32i, rv32im (default),");
+        eprintln!("                       rv32imc or rv32e. Without M, multiply, divide and remainder");
+        eprintln!("                       call runtime routines emitted with the program");
+        eprintln!("  --target <target>    rv32 (default), rv64 or cortex-m; assembly output only for the last two");
+        eprintln!("                         rv64         64-bit registers, u64 peripheral registers and LP64 frames");
+        eprintln!("                         cortex-m     Thumb-2 for ARMv7-M with the AAPCS calling convention;");
+        eprintln!("                                      'board' writes a vector table startup file");
+        eprintln!("  -O0                  Generate code from the IR as lowered (default)");
+        eprintln!("  -O1                  Inline small functions, fold constants, propagate copies");
+        eprintln!("                       and remove dead code");
+        eprintln!("  -Os                  As -O1, inlining only #[inline] functions, preferring the");
+        eprintln!("                       registers compressed instructions can name (x8-x15) and");
+        eprintln!("                       printing the code size of each function");
+        eprintln!("  --help               Print this help message");
+        eprintln!("  --version            Print version information");
     }
 }
 
 #[derive(Default)]
 struct Flags {
+    command: Command,
     source: Option<String>,
     destination: Option<String>,
+    emit: Option<Emit>,
+    opt_level: ir::opt::OptLevel,
+    target: backend::Target,
+    march: Option<backend::March>,
+    board: Option<String>,
 }
 
 fn main() {
@@ -98,16 +293,41 @@ fn main() {
         process::exit(1);
     });
 
+    if let (Command::Board, Some(board)) = (config.command, &config.board) {
+        board_files(&config, board);
+        return;
+    }
+
     let source_code = fs::read_to_string(&config.source).unwrap_or_else(|err| {
         eprintln!("Error reading '{}': {}", config.source, err);
         process::exit(1);
     });
 
-    let ast = frontend::parser::parse(&source_code).unwrap_or_else(|err| {
+    if config.command == Command::ImportSvd {
+        import_svd(&config, &source_code);
+        return;
+    }
+
+    if is_assembly(&config.source) {
+        write_output(&config.destination, object_file(&source_code, &config.source, config.march));
+        println!("Assembly successful!");
+        return;
+    }
+
+    let mut ast = frontend::parser::parse(&source_code).unwrap_or_else(|err| {
         eprintln!("Parse error: {:?}", err);
         process::exit(1);
     });
 
+    if let Some(board) = &config.board {
+        if let Err(errors) = backend::board::place_peripherals(&mut ast, board) {
+            for err in &errors {
+                eprintln!("Board error: {}", err);
+            }
+            process::exit(1);
+        }
+    }
+
     if let Err(errors) = analysis::semantic::check(&ast) {
         for err in &errors {
             eprintln!("Semantic error: {}", err);
@@ -115,22 +335,248 @@ fn main() {
         process::exit(1);
     }
 
-    let ir = ir::lower::lower(&ast);
+    let ast = analysis::synthesis::expand(&ast).unwrap_or_else(|errors| {
+        for err in &errors {
+            eprintln!("Synthesis error: {}", err);
+        }
+        process::exit(1);
+    });
+
+    let mut ir = ir::lower::lower(&ast);
 
     if let Err(err) = analysis::typestate::check(&ast, &ir) {
         eprintln!("Typestate error: {}", err);
         process::exit(1);
     }
 
-    let output = backend::generate(&ir).unwrap_or_else(|err| {
+    for warning in analysis::reachability::check(&ast) {
+        eprintln!("Warning: {}", warning);
+    }
+
+    ir::opt::optimise(&mut ir, config.opt_level, config.target.xlen());
+
+    if let Some(board) = &config.board {
+        build(&config, board, &ir);
+        return;
+    }
+
+    match config.emit {
+        Emit::Asm => {
+            let output = generate(&config, &ir);
+            write_output(&config.destination, &output);
+        }
+
+        Emit::StateGraph => {
+            let graphs = analysis::reachability::explore(&ast);
+            let dot = emit::state_graph::generate_dot(&graphs).unwrap_or_else(|err| {
+                eprintln!("State graph error: {}", err);
+                process::exit(1);
+            });
+            let mermaid = emit::state_graph::generate_mermaid(&graphs).unwrap_or_else(|err| {
+                eprintln!("State graph error: {}", err);
+                process::exit(1);
+            });
+            write_output(&config.destination, &dot);
+            let markdown = Path::new(&config.destination).with_extension("md");
+            write_output(&markdown.to_string_lossy(), &mermaid);
+        }
+
+        Emit::CHeader => {
+            let graphs = analysis::reachability::explore(&ast);
+            let guard = include_guard(&config.destination);
+            let header = emit::c_header::generate(&ast, &graphs, &guard).unwrap_or_else(|err| {
+                eprintln!("C header error: {}", err);
+                process::exit(1);
+            });
+            write_output(&config.destination, &header);
+        }
+
+        Emit::C => {
+            let source = emit::c_source::generate(&ast, &ir).unwrap_or_else(|err| {
+                eprintln!("C generation error: {}", err);
+                process::exit(1);
+            });
+            write_output(&config.destination, &source);
+        }
+
+        Emit::Rust => {
+            let graphs = analysis::reachability::explore(&ast);
+            let module = emit::rust_api::generate(&ast, &graphs).unwrap_or_else(|err| {
+                eprintln!("Rust API error: {}", err);
+                process::exit(1);
+            });
+            write_output(&config.destination, &module);
+        }
+
+        Emit::Svd => {
+            let svd = emit::svd::generate(&ast, &device_name(&config.source)).unwrap_or_else(|err| {
+                eprintln!("SVD error: {}", err);
+                process::exit(1);
+            });
+            write_output(&config.destination, &svd);
+        }
+
+        Emit::Obj => {
+            let output = generate(&config, &ir);
+            write_output(&config.destination, object_file(&output, &config.source, config.march));
+        }
+    }
+
+    println!("Compilation successful!");
+}
+
+fn import_svd(config: &Config, source_code: &str) {
+    let peripherals = frontend::svd::import(source_code).unwrap_or_else(|err| {
+        eprintln!("SVD import error: {}", err);
+        process::exit(1);
+    });
+
+    let source_name = Path::new(&config.source)
+        .file_name()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let output = emit::peri::generate(&peripherals, &source_name).unwrap_or_else(|err| {
+        eprintln!("SVD import error: {}", err);
+        process::exit(1);
+    });
+    write_output(&config.destination, &output);
+
+    println!("Imported {} peripheral(s) into '{}'", peripherals.len(), config.destination);
+}
+
+// out/uart-drivers.h -> PERI_UART_DRIVERS_H
+fn include_guard(destination: &str) -> String {
+    let stem = Path::new(destination)
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let stem: String = stem.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+        .collect();
+    format!("PERI_{}_H", stem)
+}
+
+// examples/flash.peri -> flash, examples/16550_mono.peri -> DEVICE_16550_mono (SVD names are C identifiers)
+fn device_name(source: &str) -> String {
+    let name: String = Path::new(source)
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    match name.chars().next() {
+        None => "DEVICE".to_string(),
+        Some(first) if first.is_ascii_digit() => format!("DEVICE_{}", name),
+        Some(_) => name,
+    }
+}
+
+fn generate(config: &Config, ir: &[(String, ir::cfg::CFG)]) -> String {
+    let output = backend::generate(ir, config.target, config.march, config.opt_level).unwrap_or_else(|err| {
         eprintln!("Code generation error: {}", err);
         process::exit(1);
     });
+    if config.opt_level == ir::opt::OptLevel::Os && config.target == backend::Target::Rv32 {
+        size_report(&output, &config.source);
+    }
+    output
+}
+
+// Bytes of .text from each global symbol to the next, as the built-in assembler encodes them
+fn size_report(assembly: &str, name: &str) {
+    let object = assemble(assembly, name);
+    let Some(text) = object.sections.iter().position(|s| s.name == ".text") else { return };
+
+    let mut functions: Vec<(u32, &str)> = object.symbols.iter()
+        .filter(|s| s.global && s.section == Some(text))
+        .map(|s| (s.value, s.name.as_str()))
+        .collect();
+    functions.sort();
+
+    let end = object.sections[text].data.len() as u32;
+    let width = functions.iter().map(|(_, name)| name.len()).max().unwrap_or(0);
+    for (i, (start, name)) in functions.iter().enumerate() {
+        let next = functions.get(i + 1).map_or(end, |(next, _)| *next);
+        println!("  {:width$}  {:5} bytes", name, next - start, width = width);
+    }
+    println!("  {:width$}  {:5} bytes", "total", end, width = width);
+}
 
-    fs::write(&config.destination, output).unwrap_or_else(|err| {
-        eprintln!("Error writing '{}': {}", config.destination, err);
+// Assemble the program and the board's start file, link them, write the ELF and .bin
+fn build(config: &Config, board: &frontend::board::Board, ir: &[(String, ir::cfg::CFG)]) {
+    let output = generate(config, ir);
+    let (startup_file, startup) = startup(board, config.target);
+
+    let source_name = Path::new(&config.source)
+        .file_name()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let objects = [
+        (startup_file.as_str(), assemble(&startup, &startup_file)),
+        (source_name.as_str(), assemble(&output, &source_name)),
+    ];
+
+    let executable = backend::linker::link(&objects, board).unwrap_or_else(|err| {
+        eprintln!("Link error: {}", err);
         process::exit(1);
     });
 
-    println!("Compilation successful!");
+    let binary = Path::new(&config.destination).with_extension("bin");
+    let binary = binary.to_string_lossy();
+    write_output(&config.destination, backend::elf::write_executable(&executable, config.march.elf_flags()));
+    write_output(&binary, executable.binary());
+
+    let usage: Vec<String> = executable.usage.iter()
+        .map(|(region, used, length)| format!("{} of {} bytes of {}", used, length, region))
+        .collect();
+    println!("Built '{}' and '{}' for {} ({})", config.destination, binary, board.name, usage.join(", "));
+}
+
+// Write the startup file and linker script generated from a board description
+fn board_files(config: &Config, board: &frontend::board::Board) {
+    let (startup_file, startup) = startup(board, config.target);
+    let script = backend::board::linker_script(board).unwrap_or_else(|err| {
+        eprintln!("Board error: {}", err);
+        process::exit(1);
+    });
+    let directory = Path::new(&config.destination);
+    let startup_file = directory.join(startup_file);
+    let script_file = directory.join(format!("link_{}.ld", board.name));
+    write_output(&startup_file.to_string_lossy(), &startup);
+    write_output(&script_file.to_string_lossy(), &script);
+
+    println!("Wrote '{}' and '{}'", startup_file.display(), script_file.display());
+}
+
+// start_<board>.S and its contents
+fn startup(board: &frontend::board::Board, target: backend::Target) -> (String, String) {
+    let startup = backend::board::startup(board, target).unwrap_or_else(|err| {
+        eprintln!("Board error: {}", err);
+        process::exit(1);
+    });
+    (format!("start_{}.S", board.name), startup)
+}
+
+fn assemble(assembly: &str, name: &str) -> backend::assembler::Object {
+    backend::assembler::assemble(assembly).unwrap_or_else(|err| {
+        eprintln!("Assembler error in {}: {}", name, err);
+        process::exit(1);
+    })
+}
+
+// Assemble with the built-in assembler into an ELF32 relocatable object
+fn object_file(assembly: &str, name: &str, march: backend::March) -> Vec<u8> {
+    backend::elf::write_object(&assemble(assembly, name), march.elf_flags())
+}
+
+fn is_assembly(source: &str) -> bool {
+    source.ends_with(".s") || source.ends_with(".S")
+}
+
+fn write_output(destination: &str, contents: impl AsRef<[u8]>) {
+    fs::write(destination, contents).unwrap_or_else(|err| {
+        eprintln!("Error writing '{}': {}", destination, err);
+        process::exit(1);
+    });
 }
\ No newline at end of file